@@ -0,0 +1,304 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+pub mod errors;
+use errors::BenchError;
+
+/// The micro-benchmark harness included by the programs of the `benches` folder
+pub const HARNESS_HEADER: &str = include_str!("bench/cpm_bench.h");
+
+/// The file name of the harness header
+pub const HARNESS_HEADER_NAME: &str = "cpm_bench.h";
+
+/// The baseline that results are saved to and compared with when none is named
+pub const DEFAULT_BASELINE: &str = "base";
+
+/// The prefix of the lines printed by the harness for every benchmark
+const RESULT_LINE_PREFIX: &str = "cpm-bench ";
+
+/// Changes of the mean time smaller than this fraction are reported as noise
+const NOISE_THRESHOLD: f64 = 0.02;
+
+/// The statistics of the samples of a benchmark, in nanoseconds per iteration
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Debug)]
+pub struct BenchStats {
+    /// The number of iterations of every sample
+    pub iterations: u64,
+    /// The number of samples
+    pub samples: usize,
+    pub mean: f64,
+    pub median: f64,
+    /// The sample standard deviation
+    pub stddev: f64,
+}
+
+impl BenchStats {
+    /// Computes the statistics of samples that each ran `iterations` iterations in `sample_ns` nanoseconds
+    pub fn from_samples(iterations: u64, sample_ns: &[u64]) -> Self {
+        let mut times: Vec<f64> = sample_ns
+            .iter()
+            .map(|ns| *ns as f64 / iterations.max(1) as f64)
+            .collect();
+        times.sort_by(f64::total_cmp);
+        let count = times.len();
+        let mean = times.iter().sum::<f64>() / count.max(1) as f64;
+        let median = match count {
+            0 => 0.0,
+            _ if count.is_multiple_of(2) => (times[count / 2 - 1] + times[count / 2]) / 2.0,
+            _ => times[count / 2],
+        };
+        let variance = match count {
+            0 | 1 => 0.0,
+            _ => times.iter().map(|t| (t - mean).powi(2)).sum::<f64>() / (count - 1) as f64,
+        };
+        Self {
+            iterations,
+            samples: count,
+            mean,
+            median,
+            stddev: variance.sqrt(),
+        }
+    }
+
+    /// Compares these results with the ones of a baseline
+    ///
+    /// The change is significant when the means differ by more than the noise threshold and
+    /// by more than twice the standard error of their difference
+    pub fn compare(&self, baseline: &BenchStats) -> Change {
+        if baseline.mean <= 0.0 {
+            return Change::Unchanged(0.0);
+        }
+        let difference = self.mean - baseline.mean;
+        let ratio = difference / baseline.mean;
+        let standard_error = (self.stddev.powi(2) / self.samples.max(1) as f64
+            + baseline.stddev.powi(2) / baseline.samples.max(1) as f64)
+            .sqrt();
+        if ratio.abs() < NOISE_THRESHOLD || difference.abs() <= 2.0 * standard_error {
+            Change::Unchanged(ratio)
+        } else if ratio > 0.0 {
+            Change::Regressed(ratio)
+        } else {
+            Change::Improved(ratio)
+        }
+    }
+}
+
+/// How the mean time of a benchmark changed since a baseline, with the relative change
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Change {
+    Improved(f64),
+    Regressed(f64),
+    Unchanged(f64),
+}
+
+impl std::fmt::Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::Improved(ratio) => write!(f, "{:+.1}% improved", ratio * 100.0),
+            Change::Regressed(ratio) => write!(f, "{:+.1}% regressed", ratio * 100.0),
+            Change::Unchanged(ratio) => write!(f, "{:+.1}% no change", ratio * 100.0),
+        }
+    }
+}
+
+/// Parses the standard output of a benchmark program, the result lines of the harness
+/// are returned with the benchmark names, the other lines are returned as is
+pub fn parse_output(output: &str) -> Result<(Vec<(String, BenchStats)>, String), BenchError> {
+    let mut results = vec![];
+    let mut other_lines = String::new();
+    for line in output.lines() {
+        let Some(result) = line.strip_prefix(RESULT_LINE_PREFIX) else {
+            other_lines.push_str(line);
+            other_lines.push('\n');
+            continue;
+        };
+        let invalid = || BenchError::InvalidOutput(line.to_string());
+        let mut columns = result.split_whitespace();
+        let name = columns.next().ok_or_else(invalid)?;
+        let iterations = columns
+            .next()
+            .and_then(|c| c.parse().ok())
+            .ok_or_else(invalid)?;
+        let sample_ns = columns
+            .map(|c| c.parse().map_err(|_| invalid()))
+            .collect::<Result<Vec<u64>, _>>()?;
+        results.push((
+            name.to_string(),
+            BenchStats::from_samples(iterations, &sample_ns),
+        ));
+    }
+    Ok((results, other_lines))
+}
+
+/// Writes the harness header in `folder`, unless it's already there, and returns its path
+///
+/// The header isn't rewritten when it's unchanged, so it doesn't trigger rebuilds
+pub fn write_harness_header(folder: &Path) -> std::io::Result<PathBuf> {
+    let path = folder.join(HARNESS_HEADER_NAME);
+    if std::fs::read_to_string(&path).is_ok_and(|content| content == HARNESS_HEADER) {
+        return Ok(path);
+    }
+    std::fs::create_dir_all(folder)?;
+    std::fs::write(&path, HARNESS_HEADER)?;
+    Ok(path)
+}
+
+/// The saved results of benchmarks, by `<package>/<file>/<benchmark>` name
+#[derive(Deserialize, Serialize, Default)]
+pub struct Baseline {
+    pub benchmarks: BTreeMap<String, BenchStats>,
+}
+
+impl Baseline {
+    /// The path of the baseline `name` in the `bench` folder
+    pub fn path(bench_folder: &Path, name: &str) -> Result<PathBuf, BenchError> {
+        let valid = !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.')
+            && !name.starts_with('.');
+        if !valid {
+            return Err(BenchError::InvalidBaselineName(name.to_string()));
+        }
+        Ok(bench_folder.join(format!("{name}.json")))
+    }
+
+    /// Loads the baseline `name`, `None` when it wasn't saved
+    pub fn load(bench_folder: &Path, name: &str) -> Result<Option<Self>, BenchError> {
+        let path = Self::path(bench_folder, name)?;
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        serde_json::from_str(&content)
+            .map(Some)
+            .map_err(|error| BenchError::InvalidBaseline { path, error })
+    }
+
+    /// Saves the baseline as `name`, replacing a previous one
+    pub fn save(&self, bench_folder: &Path, name: &str) -> Result<PathBuf, BenchError> {
+        let path = Self::path(bench_folder, name)?;
+        std::fs::create_dir_all(bench_folder)?;
+        std::fs::write(&path, serde_json::to_string_pretty(self)?)?;
+        Ok(path)
+    }
+}
+
+/// Formats a time in nanoseconds with a readable unit (e.g. `1.25 µs`)
+pub fn format_time(ns: f64) -> String {
+    match ns {
+        ns if ns < 1e3 => format!("{ns:.2} ns"),
+        ns if ns < 1e6 => format!("{:.2} µs", ns / 1e3),
+        ns if ns < 1e9 => format!("{:.2} ms", ns / 1e6),
+        ns => format!("{:.2} s", ns / 1e9),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(mean: f64, stddev: f64) -> BenchStats {
+        BenchStats {
+            iterations: 1000,
+            samples: 50,
+            mean,
+            median: mean,
+            stddev,
+        }
+    }
+
+    #[test]
+    fn statistics_are_per_iteration() {
+        let stats = BenchStats::from_samples(10, &[400, 100, 300, 200]);
+        assert_eq!(stats.samples, 4);
+        assert_eq!(stats.mean, 25.0);
+        assert_eq!(stats.median, 25.0);
+        // sqrt((15² + 5² + 5² + 15²) / 3)
+        assert!((stats.stddev - 12.909944).abs() < 1e-6, "{}", stats.stddev);
+
+        let stats = BenchStats::from_samples(1, &[3, 1, 2]);
+        assert_eq!(stats.median, 2.0);
+        assert_eq!(stats.stddev, 1.0);
+        assert_eq!(BenchStats::from_samples(1, &[7]).stddev, 0.0);
+    }
+
+    #[test]
+    fn small_or_noisy_changes_are_not_significant() {
+        let baseline = stats(100.0, 1.0);
+        assert_eq!(
+            stats(101.0, 1.0).compare(&baseline),
+            Change::Unchanged(0.01)
+        );
+        // 10% slower, but within twice the standard error of the difference
+        assert!(matches!(
+            stats(110.0, 40.0).compare(&baseline),
+            Change::Unchanged(_)
+        ));
+        assert!(matches!(
+            stats(110.0, 1.0).compare(&baseline),
+            Change::Regressed(ratio) if (ratio - 0.1).abs() < 1e-9
+        ));
+        assert!(matches!(
+            stats(90.0, 1.0).compare(&baseline),
+            Change::Improved(ratio) if (ratio + 0.1).abs() < 1e-9
+        ));
+    }
+
+    #[test]
+    fn result_lines_are_separated_from_the_other_output() {
+        let (results, other_lines) =
+            parse_output("setting up\ncpm-bench parse 1000 2000 4000\ndone\n").unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].0, "parse");
+        assert_eq!(results[0].1.iterations, 1000);
+        assert_eq!(results[0].1.mean, 3.0);
+        assert_eq!(other_lines, "setting up\ndone\n");
+        assert!(matches!(
+            parse_output("cpm-bench parse many"),
+            Err(BenchError::InvalidOutput(line)) if line == "cpm-bench parse many"
+        ));
+    }
+
+    #[test]
+    fn output_of_the_harness_is_parsed() {
+        let folder = std::env::temp_dir().join(format!("cpm-bench-harness-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&folder);
+        write_harness_header(&folder).unwrap();
+        let source_path = folder.join("sum.c");
+        std::fs::write(
+            &source_path,
+            "#include <cpm_bench.h>\n\
+             CPM_BENCH(sum) {\n\
+                 CPM_BENCH_LOOP { int sum = 0; for (int i = 0; i < 100; i++) sum += i; CPM_BLACK_BOX(sum); }\n\
+             }\n\
+             CPM_BENCH(empty) { CPM_BENCH_LOOP {} }\n",
+        )
+        .unwrap();
+        let program_path = folder.join("sum");
+        let status = std::process::Command::new("gcc")
+            .args(["-DCPM_BENCH_SAMPLES=5", "-DCPM_BENCH_WARMUP_MS=1"])
+            .arg("-DCPM_BENCH_MEASUREMENT_MS=5")
+            .arg(format!("-I{}", folder.display()))
+            .arg(&source_path)
+            .arg("-o")
+            .arg(&program_path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let output = std::process::Command::new(&program_path).output().unwrap();
+        let (results, other_lines) =
+            parse_output(&String::from_utf8_lossy(&output.stdout)).unwrap();
+        let names: Vec<&str> = results.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["sum", "empty"]);
+        assert!(results
+            .iter()
+            .all(|(_, stats)| stats.samples == 5 && stats.iterations > 0));
+        assert_eq!(other_lines, "");
+        std::fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -0,0 +1,160 @@
+/*
+ * cpm_bench.h: the micro-benchmark harness of `cpm bench`
+ *
+ *     #include <cpm_bench.h>
+ *
+ *     CPM_BENCH(sum_1000) {
+ *         int values[1000] = {0};
+ *         CPM_BENCH_LOOP {
+ *             int sum = 0;
+ *             for (int i = 0; i < 1000; i++) sum += values[i];
+ *             CPM_BLACK_BOX(sum);
+ *         }
+ *     }
+ *
+ * The code before `CPM_BENCH_LOOP` isn't timed. Every benchmark is warmed up, then its loop
+ * is timed CPM_BENCH_SAMPLES times, each sample running the loop body many times.
+ * The settings can be changed by defining them before including this header.
+ */
+#ifndef CPM_BENCH_H
+#define CPM_BENCH_H
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+/* number of timed samples of every benchmark */
+#ifndef CPM_BENCH_SAMPLES
+#define CPM_BENCH_SAMPLES 50
+#endif
+
+/* time spent running a benchmark before it's sampled, in milliseconds */
+#ifndef CPM_BENCH_WARMUP_MS
+#define CPM_BENCH_WARMUP_MS 200
+#endif
+
+/* time spent sampling a benchmark, in milliseconds */
+#ifndef CPM_BENCH_MEASUREMENT_MS
+#define CPM_BENCH_MEASUREMENT_MS 1000
+#endif
+
+#ifndef CPM_BENCH_MAX
+#define CPM_BENCH_MAX 256
+#endif
+
+typedef struct {
+    uint64_t iterations;
+    uint64_t remaining;
+    uint64_t start_ns;
+    uint64_t elapsed_ns;
+    int started;
+} cpm_bench_state;
+
+typedef struct {
+    const char *name;
+    void (*run)(cpm_bench_state *);
+} cpm_bench_entry;
+
+static cpm_bench_entry cpm_bench_entries[CPM_BENCH_MAX];
+static int cpm_bench_count;
+
+static void cpm_bench_register(const char *name, void (*run)(cpm_bench_state *)) {
+    if (cpm_bench_count == CPM_BENCH_MAX) {
+        fprintf(stderr, "cpm_bench: more than %d benchmarks, define CPM_BENCH_MAX\n", CPM_BENCH_MAX);
+        exit(2);
+    }
+    cpm_bench_entries[cpm_bench_count].name = name;
+    cpm_bench_entries[cpm_bench_count].run = run;
+    cpm_bench_count++;
+}
+
+static uint64_t cpm_bench_now_ns(void) {
+    struct timespec now;
+#if defined(CLOCK_MONOTONIC)
+    clock_gettime(CLOCK_MONOTONIC, &now);
+#else
+    timespec_get(&now, TIME_UTC);
+#endif
+    return (uint64_t)now.tv_sec * 1000000000u + (uint64_t)now.tv_nsec;
+}
+
+static inline int cpm_bench_next(cpm_bench_state *state) {
+    if (!state->started) {
+        state->started = 1;
+        state->remaining = state->iterations;
+        state->start_ns = cpm_bench_now_ns();
+    }
+    if (state->remaining == 0) {
+        state->elapsed_ns = cpm_bench_now_ns() - state->start_ns;
+        return 0;
+    }
+    state->remaining--;
+    return 1;
+}
+
+/* Defines a benchmark, its body must contain a `CPM_BENCH_LOOP` */
+#define CPM_BENCH(name)                                                                    \
+    static void cpm_bench_run_##name(cpm_bench_state *cpm_bench_state_);                   \
+    __attribute__((constructor)) static void cpm_bench_register_##name(void) {             \
+        cpm_bench_register(#name, cpm_bench_run_##name);                                   \
+    }                                                                                      \
+    static void cpm_bench_run_##name(cpm_bench_state *cpm_bench_state_)
+
+/* The timed part of a benchmark, its body runs once per iteration */
+#define CPM_BENCH_LOOP while (cpm_bench_next(cpm_bench_state_))
+
+/* Keeps the compiler from optimizing away a value that's otherwise unused */
+#define CPM_BLACK_BOX(value) __asm__ volatile("" : : "g"(value) : "memory")
+
+/* Runs a benchmark for `iterations` iterations and returns the elapsed time in nanoseconds */
+static uint64_t cpm_bench_time(const cpm_bench_entry *entry, uint64_t iterations) {
+    cpm_bench_state state = {iterations, 0, 0, 0, 0};
+    entry->run(&state);
+    if (!state.started) {
+        fprintf(stderr, "cpm_bench: benchmark `%s` has no CPM_BENCH_LOOP\n", entry->name);
+        exit(2);
+    }
+    return state.elapsed_ns;
+}
+
+/*
+ * Runs the benchmarks whose name contains the first argument, if any, and prints a line
+ * per benchmark for `cpm bench`: `cpm-bench <name> <iterations> <sample ns>...`
+ */
+int main(int argc, char **argv) {
+    const char *filter = argc > 1 ? argv[1] : NULL;
+    for (int i = 0; i < cpm_bench_count; i++) {
+        const cpm_bench_entry *entry = &cpm_bench_entries[i];
+        if (filter != NULL && strstr(entry->name, filter) == NULL) {
+            continue;
+        }
+        /* doubles the iterations until the warmup time is spent */
+        uint64_t iterations = 1;
+        uint64_t warmup_ns = 0;
+        uint64_t elapsed_ns = 0;
+        while (warmup_ns < (uint64_t)CPM_BENCH_WARMUP_MS * 1000000u) {
+            elapsed_ns = cpm_bench_time(entry, iterations);
+            warmup_ns += elapsed_ns;
+            if (warmup_ns < (uint64_t)CPM_BENCH_WARMUP_MS * 1000000u) {
+                iterations *= 2;
+            }
+        }
+        double iteration_ns = elapsed_ns > 0 ? (double)elapsed_ns / (double)iterations : 1.0;
+        double sample_ns = (double)CPM_BENCH_MEASUREMENT_MS * 1000000.0 / CPM_BENCH_SAMPLES;
+        uint64_t sample_iterations = (uint64_t)(sample_ns / iteration_ns);
+        if (sample_iterations == 0) {
+            sample_iterations = 1;
+        }
+        printf("cpm-bench %s %llu", entry->name, (unsigned long long)sample_iterations);
+        for (int sample = 0; sample < CPM_BENCH_SAMPLES; sample++) {
+            printf(" %llu", (unsigned long long)cpm_bench_time(entry, sample_iterations));
+        }
+        printf("\n");
+        fflush(stdout);
+    }
+    return 0;
+}
+
+#endif
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum BenchError {
+    #[error("unexpected benchmark output `{0}`")]
+    InvalidOutput(String),
+    #[error("invalid baseline name `{0}`, only letters, digits, `-`, `_` and `.` are allowed")]
+    InvalidBaselineName(String),
+    #[error("invalid baseline {path:?}: {error}")]
+    InvalidBaseline {
+        path: PathBuf,
+        error: serde_json::Error,
+    },
+    #[error("failed to serialize the baseline: {0}")]
+    Serialize(#[from] serde_json::Error),
+    #[error("Io error: {0}")]
+    IOError(#[from] std::io::Error),
+}
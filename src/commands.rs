@@ -1,10 +1,35 @@
-use std::path::PathBuf;
-use std::process::Command;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::ffi::OsString;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
 
-use crate::compiler::GCC;
-use crate::manifest::{self, PackageKind};
+use crate::bench::{self, Baseline};
+use crate::compiler::{Clang, Compiler, GCC};
+use crate::coverage::{self, CoverageReport};
+use crate::dependencies::{errors::DependencyError, PackageSource, Resolve, ResolvedPackage};
+use crate::external::ExternalBuilder;
+use crate::filenames::LOCKFILE_NAME;
+use crate::fuzz;
+use crate::git::GitCache;
+use crate::install::{self, InstallLocation};
+use crate::lockfile::{LockedPackage, Lockfile};
+use crate::manifest::edit::{DependencyEntry, ManifestEditor};
+use crate::manifest::{self, Dependency, PackageKind};
+use crate::metadata::{self, Metadata};
+use crate::package::builder::{create_parent_folder, DependencyArtifact, DevTarget};
+use crate::packaging;
+use crate::pgo::{self, Training};
+use crate::process;
+use crate::profile::{BuildProfile, Pgo, Sanitizer};
+use crate::registry::Registry;
+use crate::size::{self, SectionSizes};
+use crate::target::TargetTriple;
+use crate::toolchain::Toolchain;
+use crate::vendor::VendorDirectory;
+use crate::watch::{Changes, WatchedPackage, Watcher};
 use crate::{filenames::MANIFEST_FILE_NAME, package::builder::PackageBuilder};
-use crate::{manifest::Manifest, CPMArguments};
+use crate::{manifest::Manifest, BuildOptions, CPMArguments};
 use clap::CommandFactory;
 /// Helper macro to report errors more easily
 /// # Examples
@@ -16,7 +41,7 @@ macro_rules! handle_error {
         match $result {
             Ok(it) => it,
             Err(err) => {
-                print_error(format!("\x1b[1;31merror:\x1b[0m {err}"));
+                print_error(format!("{err}"));
                 std::process::exit(1)
             }
         }
@@ -26,7 +51,7 @@ macro_rules! handle_error {
         match $result {
             Ok(it) => it,
             Err(err) => {
-                print_error(format!("\x1b[1;31merror:\x1b[0m {}: {err}", $message));
+                print_error(format!("{}: {err}", $message));
                 std::process::exit(1)
             }
         }
@@ -60,10 +85,18 @@ macro_rules! handle_error {
     };
 }
 
+/// The compiler backend of the builds of the workspace packages, their tests, examples,
+/// benchmarks and profile-guided builds (the fuzz targets are built with [`Clang`])
+const WORKSPACE_COMPILER: &dyn Compiler = &GCC;
+
 fn print_error(message: impl Into<String>) {
     eprintln!("\x1b[1;31merror:\x1b[0m {}", message.into());
 }
 
+fn print_warning(message: impl Into<String>) {
+    eprintln!("\x1b[1;33mwarning:\x1b[0m {}", message.into());
+}
+
 pub fn init(path: PathBuf) {
     handle_error!(
         result = std::fs::create_dir_all(&path),
@@ -94,7 +127,7 @@ pub fn init(path: PathBuf) {
         CPMArguments::command()
             .error(
                 clap::error::ErrorKind::ValueValidation,
-                &format!("the specified folder already contains a `{MANIFEST_FILE_NAME}` file"),
+                format!("the specified folder already contains a `{MANIFEST_FILE_NAME}` file"),
             )
             .exit();
     }
@@ -128,17 +161,19 @@ fn check_manifest_exists() {
     if !PathBuf::from(MANIFEST_FILE_NAME).exists() {
         cmd.error(
             clap::error::ErrorKind::Io,
-            &format!(
+            format!(
                 "you're not currently on a CPM project (`{MANIFEST_FILE_NAME}` does not exist)"
             ),
         )
         .exit();
     }
 }
-pub fn build_project() {
+/// Loads the manifest in the current folder and returns the workspace path
+/// and the paths of all the packages in the workspace
+fn workspace_packages() -> (PathBuf, Vec<PathBuf>) {
     check_manifest_exists();
     let manifest_string = handle_error!(
-        result = std::fs::read_to_string("cpm.toml"),
+        result = std::fs::read_to_string(MANIFEST_FILE_NAME),
         message = &format!("failed to read`{MANIFEST_FILE_NAME}`")
     );
     let manifest: Manifest = handle_error!(
@@ -149,73 +184,489 @@ pub fn build_project() {
         result = std::env::current_dir(),
         message = "failed to access current working dir"
     );
-    let mut packages_to_compile: Vec<PathBuf> = vec![];
+    let packages = workspace_member_paths(&cwd, &manifest);
+    (cwd, packages)
+}
+
+/// The paths of the packages of a workspace, from its manifest
+fn workspace_member_paths(workspace_path: &Path, manifest: &Manifest) -> Vec<PathBuf> {
+    let mut packages: Vec<PathBuf> = vec![];
     if manifest.package.is_some() {
-        packages_to_compile.push(cwd.clone());
+        packages.push(workspace_path.to_path_buf());
     }
     if let Some(workspace) = manifest.workspace.as_ref() {
-        packages_to_compile.extend(workspace.members.iter().map(|member_path| {
-            let mut absolute_path = cwd.clone();
+        packages.extend(workspace.members.iter().map(|member_path| {
+            let mut absolute_path = workspace_path.to_path_buf();
             absolute_path.push(member_path);
             absolute_path
         }));
     }
-    let workspace_path = cwd.clone();
-    for package_path in packages_to_compile {
-        let package_builder =
-            handle_error!(result = PackageBuilder::new(package_path, workspace_path.clone()));
-        package_builder.compile(&GCC).unwrap();
-        println!(
-            "\x1b[1;32mFinished building package \x1b[0m ({})",
-            package_builder.package().name
-        );
+    packages
+}
+
+/// Creates a builder for every package of the workspace, or only for the package
+/// named `package_name_flag` when it's specified
+fn package_builders(package_name_flag: Option<&str>) -> Vec<PackageBuilder> {
+    let (workspace_path, packages) = workspace_packages();
+    let package_builders: Vec<PackageBuilder> = packages
+        .into_iter()
+        .map(|package_path| {
+            handle_error!(result = PackageBuilder::new(package_path, workspace_path.clone()))
+        })
+        .filter(|package_builder| {
+            package_name_flag.is_none_or(|name| package_builder.package().name == name)
+        })
+        .collect();
+    if let (Some(name), true) = (package_name_flag, package_builders.is_empty()) {
+        print_error(format!("no package named `{name}` in the workspace"));
+        std::process::exit(1);
     }
-    // if todo!() as i32 > 0 {
-    //     print_error("stopping compilation due to {compilation_error_count} previous errors");
-    //     std::process::exit(1);
-    // }
-    //
+    package_builders
 }
-pub fn run_project(package_name_flag: Option<String>) {
-    check_manifest_exists();
-    let manifest_string = handle_error!(
-        result = std::fs::read_to_string("cpm.toml"),
-        message = &format!("failed to read`{MANIFEST_FILE_NAME}`")
+
+/// Opens the registry configured for the workspace
+fn workspace_registry(workspace_path: &Path) -> Registry {
+    let workspace_manifest = handle_error!(
+        result = Manifest::load_manifest_from_project_path(workspace_path),
+        message = format!("failed to load `{MANIFEST_FILE_NAME}`")
     );
-    let manifest: Manifest = handle_error!(
-        result = toml::from_str(&manifest_string),
-        message = &format!("`{MANIFEST_FILE_NAME}` contains errors")
+    Registry::for_workspace(workspace_path, &workspace_manifest)
+}
+
+/// Resolves the build profile selected by the options from the profiles of the workspace manifest,
+/// with the sanitizers of `--sanitize`
+fn workspace_profile(workspace_path: &Path, options: &BuildOptions) -> BuildProfile {
+    let workspace_manifest = handle_error!(
+        result = Manifest::load_manifest_from_project_path(workspace_path),
+        message = format!("failed to load `{MANIFEST_FILE_NAME}`")
     );
-    let cwd = handle_error!(
-        result = std::env::current_dir(),
-        message = "failed to access current working dir"
+    let mut profile = handle_error!(
+        result = BuildProfile::resolve(options.profile_name(), &workspace_manifest.profile)
     );
-    let mut packages: Vec<PathBuf> = vec![];
-    if manifest.package.is_some() {
-        packages.push(cwd.clone());
+    handle_error!(result = profile.set_sanitizers(options.sanitize.clone()));
+    profile.coverage = options.coverage;
+    profile
+}
+
+/// The target selected with `--target`, the host by default
+fn selected_target(options: &BuildOptions) -> TargetTriple {
+    match &options.target {
+        Some(triple) => handle_error!(result = TargetTriple::parse(triple)),
+        None => TargetTriple::host(),
     }
-    if let Some(workspace) = manifest.workspace.as_ref() {
-        packages.extend(workspace.members.iter().map(|member_path| {
-            let mut absolute_path = cwd.clone();
-            absolute_path.push(member_path);
-            absolute_path
-        }));
+}
+
+/// The toolchain of the target selected with `--target`, the host toolchain by default
+fn workspace_toolchain(workspace_path: &Path, options: &BuildOptions) -> Toolchain {
+    let Some(triple) = &options.target else {
+        return Toolchain::host();
+    };
+    let workspace_manifest = handle_error!(
+        result = Manifest::load_manifest_from_project_path(workspace_path),
+        message = format!("failed to load `{MANIFEST_FILE_NAME}`")
+    );
+    handle_error!(result = Toolchain::for_target(triple, workspace_path, &workspace_manifest))
+}
+
+/// Opens the vendor folder configured for the workspace, if any
+fn workspace_vendor(workspace_path: &Path, options: &BuildOptions) -> Option<VendorDirectory> {
+    let workspace_manifest = handle_error!(
+        result = Manifest::load_manifest_from_project_path(workspace_path),
+        message = format!("failed to load `{MANIFEST_FILE_NAME}`")
+    );
+    VendorDirectory::for_workspace(
+        workspace_path,
+        &workspace_manifest,
+        options.allow_modified_vendor,
+    )
+}
+
+/// The `[patch]` section of the workspace manifest, with paths resolved against the workspace
+fn workspace_patches(workspace_path: &Path) -> BTreeMap<String, Dependency> {
+    let workspace_manifest = handle_error!(
+        result = Manifest::load_manifest_from_project_path(workspace_path),
+        message = format!("failed to load `{MANIFEST_FILE_NAME}`")
+    );
+    workspace_manifest
+        .patch
+        .iter()
+        .map(|(name, patch)| (name.clone(), patch.relative_to(workspace_path)))
+        .collect()
+}
+
+/// Resolves the dependencies of the workspace packages, from the vendor folder when
+/// it's configured, and updates the lockfile when the resolution changed
+fn resolve_workspace(
+    workspace_path: &Path,
+    packages: &[PathBuf],
+    options: &BuildOptions,
+) -> Resolve {
+    let vendor = workspace_vendor(workspace_path, options);
+    resolve_dependencies(workspace_path, packages, options, vendor.as_ref())
+}
+
+/// Resolves the dependencies of the workspace packages and updates the lockfile
+/// when the resolution changed
+fn resolve_dependencies(
+    workspace_path: &Path,
+    packages: &[PathBuf],
+    options: &BuildOptions,
+    vendor: Option<&VendorDirectory>,
+) -> Resolve {
+    let lockfile = load_lockfile(workspace_path);
+    let resolve = handle_error!(
+        result =
+            try_resolve_dependencies(workspace_path, packages, options, vendor, lockfile.as_ref()),
+        message = "failed to resolve dependencies"
+    );
+    for name in &resolve.unused_patches {
+        print_warning(format!(
+            "patch `{name}` does not replace any dependency of the workspace"
+        ));
+    }
+    save_lockfile(workspace_path, &resolve, lockfile.as_ref());
+    resolve
+}
+
+/// Loads the lockfile of the workspace, if there's one
+fn load_lockfile(workspace_path: &Path) -> Option<Lockfile> {
+    handle_error!(
+        result = Lockfile::load(workspace_path),
+        message = format!("failed to load `{LOCKFILE_NAME}`")
+    )
+}
+
+/// Resolves the dependencies of the workspace packages with the versions pinned
+/// in `lockfile`, without updating the lockfile
+fn try_resolve_dependencies(
+    workspace_path: &Path,
+    packages: &[PathBuf],
+    options: &BuildOptions,
+    vendor: Option<&VendorDirectory>,
+    lockfile: Option<&Lockfile>,
+) -> Result<Resolve, DependencyError> {
+    let registry = workspace_registry(workspace_path);
+    Resolve::new(
+        packages,
+        &workspace_patches(workspace_path),
+        &selected_target(options),
+        &registry,
+        &GitCache::new(options.offline),
+        vendor,
+        lockfile,
+    )
+}
+
+/// Writes the lockfile of a resolved graph when it differs from the current `lockfile`
+fn save_lockfile(workspace_path: &Path, resolve: &Resolve, lockfile: Option<&Lockfile>) {
+    let new_lockfile = resolve.lockfile();
+    let lockfile_changed = match lockfile {
+        Some(lockfile) => *lockfile != new_lockfile,
+        None => !new_lockfile.packages.is_empty(),
+    };
+    if lockfile_changed {
+        handle_error!(
+            result = new_lockfile.save(workspace_path),
+            message = format!("failed to write `{LOCKFILE_NAME}`")
+        );
     }
-    let path_of_package_to_run: PathBuf;
+}
+
+/// Builds the packages named `roots` after all their dependencies with the build profile
+/// and the toolchain, and returns the builders of the roots
+///
+/// The packages are compiled with `compiler`, usually [`WORKSPACE_COMPILER`]
+///
+/// With `with_dev_dependencies`, the dev-dependencies of the roots are built too
+/// and given to their builders.
+/// When cross-compiling, the build-dependencies and their dependencies are built for the host
+/// (a package needed by both is built twice)
+fn build_packages(
+    workspace_path: &Path,
+    resolve: &Resolve,
+    roots: &[String],
+    profile: &BuildProfile,
+    toolchain: &Toolchain,
+    compiler: &dyn Compiler,
+    with_dev_dependencies: bool,
+) -> Vec<PackageBuilder> {
+    let mut build_roots = roots.to_vec();
+    if with_dev_dependencies {
+        build_roots.extend(
+            roots
+                .iter()
+                .filter_map(|root| resolve.packages.get(root))
+                .flat_map(|package| package.dev_dependencies.iter().cloned()),
+        );
+    }
+    let build_order = handle_error!(
+        result = resolve.build_order(&build_roots),
+        message = "failed to resolve dependencies"
+    );
+    let host_toolchain = Toolchain::host();
+    let cross_compiling = *toolchain != host_toolchain;
+    let toolchains = match cross_compiling {
+        true => vec![toolchain, &host_toolchain],
+        false => vec![toolchain],
+    };
+    for checked_toolchain in toolchains {
+        let probe_folder = profile
+            .target_folder_path(workspace_path, checked_toolchain)
+            .join("probe");
+        handle_error!(result = compiler.check_profile(profile, checked_toolchain, &probe_folder));
+    }
+    // the artifacts are keyed by the package name and whether it was built for the host
+    let mut library_artifacts: HashMap<(String, bool), DependencyArtifact> = HashMap::new();
+    let mut executable_paths: HashMap<(String, bool), PathBuf> = HashMap::new();
+    let mut root_builders: HashMap<String, PackageBuilder> = HashMap::new();
+    for (package, for_host) in build_order {
+        let package_toolchain = if for_host { &host_toolchain } else { toolchain };
+        let key = (package.name.clone(), for_host);
+        if let Some(settings) = &package.external {
+            let external_builder = ExternalBuilder::new(
+                &package.name,
+                &package.path,
+                settings,
+                workspace_path,
+                profile,
+                package_toolchain,
+            );
+            let artifact = handle_error!(
+                result = external_builder.build(compiler),
+                message = format!("failed to build dependency `{}`", package.name)
+            );
+            println!(
+                "\x1b[1;32mFinished building package \x1b[0m ({})",
+                package.name
+            );
+            library_artifacts.insert(key, artifact);
+            continue;
+        }
+        let mut package_builder =
+            handle_error!(result = PackageBuilder::new(&package.path, workspace_path));
+        handle_error!(
+            result = package_builder.set_toolchain(package_toolchain.clone()),
+            message = format!("invalid manifest of `{}`", package.name)
+        );
+        package_builder.set_profile(profile.clone());
+        let dependencies = handle_error!(
+            result = resolve.transitive_dependencies(&package.name, for_host),
+            message = "failed to resolve dependencies"
+        );
+        let dependency_artifacts =
+            library_artifacts_of(&package.name, dependencies, for_host, &library_artifacts);
+        package_builder.set_dependency_artifacts(dependency_artifacts);
+        let mut build_tool_paths = vec![];
+        for build_dependency in package.platform_build_dependencies(for_host) {
+            let executable_path = handle_error!(
+                option = executable_paths.get(&(
+                    build_dependency.clone(),
+                    for_host || resolve.cross_compiling()
+                )),
+                message = format!(
+                    "`{}` is a build-dependency of `{}`, but it's not an executable",
+                    build_dependency, package.name
+                )
+            );
+            build_tool_paths.push(executable_path.clone());
+        }
+        package_builder.set_build_tool_paths(build_tool_paths);
+        build_package(&package_builder, compiler);
+        if package_builder.package().kind == PackageKind::Executable {
+            executable_paths.insert(key, package_builder.output_path());
+        } else {
+            let artifact = handle_error!(result = package_builder.artifact());
+            library_artifacts.insert(key, artifact);
+        }
+        if !for_host && roots.contains(&package.name) {
+            root_builders.insert(package.name.clone(), package_builder);
+        }
+    }
+    if with_dev_dependencies {
+        for (name, package_builder) in root_builders.iter_mut() {
+            package_builder.set_dev_dependency_artifacts(library_artifacts_of(
+                name,
+                dev_dependencies_of(resolve, name),
+                false,
+                &library_artifacts,
+            ));
+        }
+    }
+    roots
+        .iter()
+        .filter_map(|name| root_builders.remove(name))
+        .collect()
+}
+
+/// The dev-dependencies of the package `name` and their dependencies, in link order,
+/// without the package itself (which is already linked with its dev targets)
+fn dev_dependencies_of<'a>(resolve: &'a Resolve, name: &str) -> Vec<&'a ResolvedPackage> {
+    let mut dev_dependencies = vec![];
+    for dev_dependency in &resolve.packages[name].dev_dependencies {
+        dev_dependencies.push(&resolve.packages[dev_dependency]);
+        dev_dependencies.extend(handle_error!(
+            result = resolve.transitive_dependencies(dev_dependency, false),
+            message = "failed to resolve dependencies"
+        ));
+    }
+    let mut seen = HashSet::new();
+    dev_dependencies
+        .retain(|dependency| dependency.name != name && seen.insert(dependency.name.clone()));
+    dev_dependencies
+}
+
+/// The artifacts of `packages`, libraries that were already built for the target
+/// (e.g. by another cpm process) with the profile and the toolchain
+fn built_library_artifacts(
+    workspace_path: &Path,
+    packages: Vec<&ResolvedPackage>,
+    profile: &BuildProfile,
+    toolchain: &Toolchain,
+) -> Vec<DependencyArtifact> {
+    let mut artifacts = vec![];
+    for package in packages {
+        if let Some(settings) = &package.external {
+            let external_builder = ExternalBuilder::new(
+                &package.name,
+                &package.path,
+                settings,
+                workspace_path,
+                profile,
+                toolchain,
+            );
+            artifacts.push(external_builder.artifact());
+            continue;
+        }
+        let mut package_builder =
+            handle_error!(result = PackageBuilder::new(&package.path, workspace_path));
+        handle_error!(
+            result = package_builder.set_toolchain(toolchain.clone()),
+            message = format!("invalid manifest of `{}`", package.name)
+        );
+        package_builder.set_profile(profile.clone());
+        artifacts.push(handle_error!(result = package_builder.artifact()));
+    }
+    artifacts
+}
+
+/// The artifacts of the dependencies of `package_name`, which must all be libraries
+/// built for the same platform (the host when `for_host` is set)
+fn library_artifacts_of(
+    package_name: &str,
+    dependencies: Vec<&ResolvedPackage>,
+    for_host: bool,
+    library_artifacts: &HashMap<(String, bool), DependencyArtifact>,
+) -> Vec<DependencyArtifact> {
+    let mut artifacts = vec![];
+    for dependency in dependencies {
+        let artifact = handle_error!(
+            option = library_artifacts.get(&(dependency.name.clone(), for_host)),
+            message = format!(
+                "`{package_name}` depends on `{}`, which is not a library",
+                dependency.name
+            )
+        );
+        artifacts.push(artifact.clone());
+    }
+    artifacts
+}
+
+/// The workspace packages of the resolve, or only the package named `package_name_flag`
+fn workspace_roots(resolve: &Resolve, package_name_flag: Option<&str>) -> Vec<String> {
+    let roots: Vec<String> = resolve
+        .packages
+        .values()
+        .filter(|package| package.source == PackageSource::Workspace)
+        .map(|package| package.name.clone())
+        .filter(|name| package_name_flag.is_none_or(|flag| flag == name))
+        .collect();
+    if let (Some(name), true) = (package_name_flag, roots.is_empty()) {
+        print_error(format!("no package named `{name}` in the workspace"));
+        std::process::exit(1);
+    }
+    roots
+}
+
+/// Builds every package of the workspace, or only the package named `package_name_flag`
+/// when it's specified, with their dependencies (and dev-dependencies with `with_dev_dependencies`)
+fn build_workspace(
+    package_name_flag: Option<&str>,
+    options: &BuildOptions,
+    with_dev_dependencies: bool,
+) -> Vec<PackageBuilder> {
+    let (workspace_path, packages) = workspace_packages();
+    let resolve = resolve_workspace(&workspace_path, &packages, options);
+    let roots = workspace_roots(&resolve, package_name_flag);
+    let profile = workspace_profile(&workspace_path, options);
+    let toolchain = workspace_toolchain(&workspace_path, options);
+    build_packages(
+        &workspace_path,
+        &resolve,
+        &roots,
+        &profile,
+        &toolchain,
+        WORKSPACE_COMPILER,
+        with_dev_dependencies,
+    )
+}
+
+fn build_package(package_builder: &PackageBuilder, compiler: &dyn Compiler) {
+    handle_error!(result = package_builder.compile(compiler));
+    println!(
+        "\x1b[1;32mFinished building package \x1b[0m ({})",
+        package_builder.package().name
+    );
+}
+
+pub fn build_project(package_name_flag: Option<String>, options: BuildOptions) {
+    build_workspace(package_name_flag.as_deref(), &options, false);
+}
+
+pub fn install_project(
+    package_name_flag: Option<String>,
+    location: InstallLocation,
+    options: BuildOptions,
+) {
+    for package_builder in build_workspace(package_name_flag.as_deref(), &options, false) {
+        let installed_files = handle_error!(
+            result = install::install_package(&package_builder, &location),
+            message = format!("failed to install `{}`", package_builder.package().name)
+        );
+        for installed_file in installed_files {
+            println!("\x1b[1;32mInstalled\x1b[0m {}", installed_file.display());
+        }
+    }
+}
+
+pub fn uninstall_project(package_name_flag: Option<String>, location: InstallLocation) {
+    for package_builder in package_builders(package_name_flag.as_deref()) {
+        let package_name = &package_builder.package().name;
+        let removed_files = handle_error!(
+            result = install::uninstall_package(package_name, &location),
+            message = format!("failed to uninstall `{package_name}`")
+        );
+        for removed_file in removed_files {
+            println!("\x1b[1;32mRemoved\x1b[0m {}", removed_file.display());
+        }
+    }
+}
+/// The path of the workspace package run by `cpm run`, the only package of the workspace or
+/// the executable package named `package_name_flag`
+fn package_to_run(packages: &[PathBuf], package_name_flag: Option<&str>) -> PathBuf {
     match packages.len() {
         0 => {
             print_error("No packages to run. did you forget to add the package to `members`?");
             std::process::exit(1);
         }
-        1 => path_of_package_to_run = packages[0].clone(),
+        1 => packages[0].clone(),
         _ => match package_name_flag {
             Some(name) => {
-                path_of_package_to_run = handle_error!(
+                handle_error!(
                     option = packages
                         .iter()
                         .find(|p| {
                             let manifest = handle_error!(
-                                result = manifest::Manifest::load_manifest_from_project_path(&p),
+                                result = manifest::Manifest::load_manifest_from_project_path(p),
                                 message = format!("Invalid package manifest at {}", p.display())
                             );
                             manifest.package.is_some_and(|p| {
@@ -224,7 +675,7 @@ pub fn run_project(package_name_flag: Option<String>) {
                         })
                         .cloned(),
                     message = "no such package to run"
-                );
+                )
             }
             None => {
                 print_error(
@@ -232,7 +683,7 @@ pub fn run_project(package_name_flag: Option<String>) {
                 );
                 println!("Available packages:");
                 println!();
-                for p in &packages {
+                for p in packages {
                     match manifest::Manifest::load_manifest_from_project_path(p) {
                         Ok(manifest::Manifest {
                             package:
@@ -256,21 +707,1988 @@ pub fn run_project(package_name_flag: Option<String>) {
             }
         },
     }
+}
+
+pub fn run_project(
+    package_name_flag: Option<String>,
+    args: Vec<String>,
+    build_only: bool,
+    options: BuildOptions,
+) {
+    let (cwd, packages) = workspace_packages();
+    let path_of_package_to_run = package_to_run(&packages, package_name_flag.as_deref());
     let workspace_path = cwd.clone();
-    let package_builder =
-        handle_error!(result = PackageBuilder::new(path_of_package_to_run, workspace_path.clone()));
-    package_builder.compile(&GCC).unwrap();
+    let package_name = handle_error!(
+        result = PackageBuilder::new(&path_of_package_to_run, &workspace_path),
+        message = format!("invalid package at {}", path_of_package_to_run.display())
+    )
+    .package()
+    .name
+    .clone();
+    let resolve = resolve_workspace(&workspace_path, &packages, &options);
+    let profile = workspace_profile(&workspace_path, &options);
+    let toolchain = workspace_toolchain(&workspace_path, &options);
+    let package_builder = handle_error!(
+        option = build_packages(
+            &workspace_path,
+            &resolve,
+            &[package_name],
+            &profile,
+            &toolchain,
+            WORKSPACE_COMPILER,
+            false
+        )
+        .pop(),
+        message = "no such package to run"
+    );
+    if build_only {
+        return;
+    }
+    run_program(&package_builder, &package_builder.output_path(), &args);
+}
+
+/// Runs a program built for a package with its `[run]` settings and exits with its exit code
+fn run_program(package_builder: &PackageBuilder, program_path: &Path, args: &[String]) -> ! {
+    let mut command = process::program_command(
+        program_path,
+        args,
+        &package_builder.package_manifest().run,
+        &package_builder.package_path(),
+        &package_builder.runtime_library_folders(),
+    );
+    let status = handle_error!(
+        result = command.status(),
+        message = format!("failed to run `{}`", program_path.display())
+    );
+    let (exit_code, message) = process::describe_exit_status(status);
+    if let Some(message) = message {
+        print_error(format!("process `{}` {message}", program_path.display()));
+    }
+    std::process::exit(exit_code);
+}
+
+/// The workspace package whose [`DevTarget`] folder has a program named `name`, exits
+/// when no package or several packages have it. `kind` describes the program in the errors
+fn package_with_dev_program(
+    resolve: &Resolve,
+    package_name_flag: Option<&str>,
+    target: DevTarget,
+    name: &str,
+    kind: &str,
+) -> String {
+    let file_name = format!("{name}.c");
+    let candidates: Vec<String> = metadata::workspace_members(resolve)
+        .into_iter()
+        .filter(|package| package_name_flag.is_none_or(|flag| flag == package))
+        .filter(|package| {
+            resolve.packages[package]
+                .path
+                .join(target.folder_name())
+                .join(&file_name)
+                .exists()
+        })
+        .collect();
+    match candidates.as_slice() {
+        [] => {
+            print_error(format!("no {kind} named `{name}`"));
+            std::process::exit(1);
+        }
+        [package_name] => package_name.clone(),
+        _ => {
+            print_error(format!(
+                "multiple packages have a {kind} named `{name}`, specify the package with `-p <name>`"
+            ));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Builds and runs the example `examples/<example>.c` of a workspace package
+pub fn run_example(
+    package_name_flag: Option<String>,
+    example: String,
+    args: Vec<String>,
+    build_only: bool,
+    options: BuildOptions,
+) {
+    let (workspace_path, packages) = workspace_packages();
+    let resolve = resolve_workspace(&workspace_path, &packages, &options);
+    let package_name = package_with_dev_program(
+        &resolve,
+        package_name_flag.as_deref(),
+        DevTarget::Examples,
+        &example,
+        "example",
+    );
+    let profile = workspace_profile(&workspace_path, &options);
+    let toolchain = workspace_toolchain(&workspace_path, &options);
+    let package_builder = handle_error!(
+        option = build_packages(
+            &workspace_path,
+            &resolve,
+            &[package_name],
+            &profile,
+            &toolchain,
+            WORKSPACE_COMPILER,
+            true
+        )
+        .pop(),
+        message = "no such package to run"
+    );
+    let source = package_builder
+        .package_path()
+        .join(DevTarget::Examples.folder_name())
+        .join(format!("{example}.c"));
+    let executable_path = handle_error!(
+        result =
+            package_builder.compile_dev_target(WORKSPACE_COMPILER, DevTarget::Examples, &source),
+        message = format!("failed to build example `{example}`")
+    );
+    if build_only {
+        return;
+    }
+    run_program(&package_builder, &executable_path, &args);
+}
+
+/// Lists the fuzz targets of every workspace package, or only of the package named `package_name_flag`
+pub fn list_fuzz_targets(package_name_flag: Option<String>) {
+    for package_builder in package_builders(package_name_flag.as_deref()) {
+        for source in package_builder.dev_target_sources(DevTarget::Fuzz) {
+            println!(
+                "{}/{}",
+                package_builder.package().name,
+                source.file_stem().unwrap_or_default().to_string_lossy()
+            );
+        }
+    }
+}
+
+/// Builds the fuzz target `fuzz/<target>.c` of a workspace package with the Clang backend,
+/// libFuzzer and the address sanitizer, and returns the builder of the package with the
+/// path of the fuzz target executable
+fn build_fuzz_target(
+    package_name_flag: Option<&str>,
+    target: &str,
+    options: &BuildOptions,
+) -> (PackageBuilder, PathBuf) {
+    let (workspace_path, packages) = workspace_packages();
+    let resolve = resolve_workspace(&workspace_path, &packages, options);
+    let package_name = package_with_dev_program(
+        &resolve,
+        package_name_flag,
+        DevTarget::Fuzz,
+        target,
+        "fuzz target",
+    );
+    let mut profile = workspace_profile(&workspace_path, options);
+    let mut sanitizers = profile.sanitizers.clone();
+    sanitizers.extend([Sanitizer::Address, Sanitizer::Fuzzer]);
+    handle_error!(result = profile.set_sanitizers(sanitizers));
+    let toolchain = workspace_toolchain(&workspace_path, options);
+    let clang = Clang.program(&toolchain);
+    if let Err(error) = std::process::Command::new(&clang).arg("--version").output() {
+        match error.kind() {
+            std::io::ErrorKind::NotFound => print_error(format!(
+                "`{clang}` is not installed, fuzz targets are built with Clang and libFuzzer"
+            )),
+            _ => print_error(format!("failed to run `{clang}`: {error}")),
+        }
+        std::process::exit(1);
+    }
+    let package_builder = handle_error!(
+        option = build_packages(
+            &workspace_path,
+            &resolve,
+            &[package_name],
+            &profile,
+            &toolchain,
+            &Clang,
+            true
+        )
+        .pop(),
+        message = "no such package to fuzz"
+    );
+    let source = package_builder
+        .package_path()
+        .join(DevTarget::Fuzz.folder_name())
+        .join(format!("{target}.c"));
+    let executable_path = handle_error!(
+        result = package_builder.compile_dev_target(&Clang, DevTarget::Fuzz, &source),
+        message = format!("failed to build fuzz target `{target}`")
+    );
+    (package_builder, executable_path)
+}
+
+/// Builds and runs a fuzz target on its corpus folder, `fuzz/corpus/<target>`, and exits with
+/// its exit code. The crash inputs are written to `fuzz/artifacts/<target>`
+pub fn run_fuzz_target(
+    package_name_flag: Option<String>,
+    target: String,
+    args: Vec<String>,
+    options: BuildOptions,
+) {
+    let (package_builder, executable_path) =
+        build_fuzz_target(package_name_flag.as_deref(), &target, &options);
+    let corpus_path = fuzz::corpus_folder_path(&package_builder.package_path(), &target);
+    let artifacts_path = fuzz::artifacts_folder_path(&package_builder.package_path(), &target);
+    for folder in [&corpus_path, &artifacts_path] {
+        handle_error!(
+            result = std::fs::create_dir_all(folder),
+            message = format!("failed to create {}", folder.display())
+        );
+    }
+    let mut fuzzer_args = vec![
+        corpus_path.display().to_string(),
+        // libFuzzer appends the name of the artifact to the prefix
+        format!("-artifact_prefix={}/", artifacts_path.display()),
+    ];
+    fuzzer_args.extend(args);
+    run_program(&package_builder, &executable_path, &fuzzer_args);
+}
+
+/// Minimizes a crash input of a fuzz target with libFuzzer and adds the minimized input
+/// as a regression test, `tests/fuzz_<target>_<hash>.c`, run by `cpm test`
+pub fn minimize_fuzz_input(
+    package_name_flag: Option<String>,
+    target: String,
+    input_path: PathBuf,
+    runs: u32,
+    options: BuildOptions,
+) {
+    let input_path = handle_error!(
+        result = std::fs::canonicalize(&input_path),
+        message = format!("no crash input at {}", input_path.display())
+    );
+    let (package_builder, executable_path) =
+        build_fuzz_target(package_name_flag.as_deref(), &target, &options);
+    let package_path = package_builder.package_path();
+    let artifacts_path = fuzz::artifacts_folder_path(&package_path, &target);
+    handle_error!(
+        result = std::fs::create_dir_all(&artifacts_path),
+        message = format!("failed to create {}", artifacts_path.display())
+    );
+    let input_name = input_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    let minimized_path = artifacts_path.join(format!("minimized-{input_name}"));
+    let args = [
+        "-minimize_crash=1".to_string(),
+        format!("-runs={runs}"),
+        format!("-exact_artifact_path={}", minimized_path.display()),
+        input_path.display().to_string(),
+    ];
+    let mut command = process::program_command(
+        &executable_path,
+        &args,
+        &package_builder.package_manifest().run,
+        &package_path,
+        &package_builder.runtime_library_folders(),
+    );
+    let status = handle_error!(
+        result = command.status(),
+        message = format!("failed to run fuzz target `{target}`")
+    );
+    if !status.success() || !minimized_path.exists() {
+        print_error(format!(
+            "failed to minimize {}, does it crash the fuzz target `{target}`?",
+            input_path.display()
+        ));
+        std::process::exit(1);
+    }
+    let minimized = handle_error!(
+        result = std::fs::read(&minimized_path),
+        message = format!("failed to read {}", minimized_path.display())
+    );
+    let test_path = package_path
+        .join(DevTarget::Tests.folder_name())
+        .join(fuzz::regression_test_file_name(&target, &minimized));
+    handle_error!(
+        result = create_parent_folder(&test_path).and_then(|_| std::fs::write(
+            &test_path,
+            fuzz::regression_test_source(&target, &input_name, &minimized)
+        )),
+        message = format!("failed to write {}", test_path.display())
+    );
     println!(
-        "\x1b[1;32mFinished building package \x1b[0m ({})",
+        "\x1b[1;32mMinimized\x1b[0m {} to {} bytes ({})",
+        input_path.display(),
+        minimized.len(),
+        minimized_path.display()
+    );
+    println!(
+        "\x1b[1;32mAdded\x1b[0m regression test {}",
+        test_path.display()
+    );
+}
+
+/// The command repeated by `--watch`
+pub enum WatchMode {
+    /// `cpm build`
+    Build,
+    /// `cpm test`
+    Test,
+    /// `cpm run`, the program is restarted after every successful build
+    Run {
+        example: Option<String>,
+        args: Vec<String>,
+    },
+}
+
+/// Runs the command of `mode`, then again every time the sources, headers or manifest
+/// of a package change, until interrupted
+///
+/// The command runs in a child `cpm` process with the arguments of this one without `--watch`,
+/// so its errors don't stop the watch. Only the workspace packages that depend on a changed
+/// package are built or tested again. In run mode the program is started by the watch itself,
+/// and restarted when a build succeeds
+pub fn watch_project(
+    mode: WatchMode,
+    package_name_flag: Option<String>,
+    options: BuildOptions,
+) -> ! {
+    let (workspace_path, packages) = workspace_packages();
+    let mut resolve = resolve_workspace(&workspace_path, &packages, &options);
+    let mut roots = watched_roots(
+        &workspace_path,
+        &resolve,
+        &packages,
+        package_name_flag.as_deref(),
+        &mode,
+    );
+    let mut watcher = watch_packages(&workspace_path, &resolve, &roots, &mode);
+    let mut program = None;
+    run_watched_command(
+        &mode,
+        &roots,
+        &roots,
+        &workspace_path,
+        &resolve,
+        &options,
+        &mut program,
+    );
+    loop {
+        println!(
+            "\x1b[1;32mWatching\x1b[0m {} folders for changes",
+            watcher.folder_count()
+        );
+        let changes = handle_error!(
+            result = watcher.wait_for_changes(|| check_watched_program(&mut program)),
+            message = "failed to watch the packages"
+        );
+        let affected = affected_roots(&resolve, &roots, &changes, &mode);
+        if affected.is_empty() {
+            continue;
+        }
+        let changed: Vec<String> = match changes.everything && changes.manifest {
+            true => vec![MANIFEST_FILE_NAME.to_string()],
+            false => changes.packages.iter().cloned().collect(),
+        };
+        if !changed.is_empty() {
+            println!("\x1b[1;32mChanged\x1b[0m {}", changed.join(", "));
+        }
+        run_watched_command(
+            &mode,
+            &roots,
+            &affected,
+            &workspace_path,
+            &resolve,
+            &options,
+            &mut program,
+        );
+        // invalid manifests were reported by the command, the watch then continues with
+        // the previous packages
+        if let Some((packages, new_resolve)) = changes
+            .manifest
+            .then(|| try_resolve_workspace(&workspace_path, &options))
+            .flatten()
+        {
+            resolve = new_resolve;
+            roots = watched_roots(
+                &workspace_path,
+                &resolve,
+                &packages,
+                package_name_flag.as_deref(),
+                &mode,
+            );
+            watcher = watch_packages(&workspace_path, &resolve, &roots, &mode);
+        }
+    }
+}
+
+/// Resolves the workspace again after a manifest changed, `None` when a manifest is invalid
+/// or the dependencies can't be resolved
+fn try_resolve_workspace(
+    workspace_path: &Path,
+    options: &BuildOptions,
+) -> Option<(Vec<PathBuf>, Resolve)> {
+    let manifest = Manifest::load_manifest_from_project_path(workspace_path).ok()?;
+    let packages = workspace_member_paths(workspace_path, &manifest);
+    if packages
+        .iter()
+        .any(|package| Manifest::load_manifest_from_project_path(package).is_err())
+    {
+        return None;
+    }
+    let vendor = workspace_vendor(workspace_path, options);
+    let lockfile = load_lockfile(workspace_path);
+    let resolve = try_resolve_dependencies(
+        workspace_path,
+        &packages,
+        options,
+        vendor.as_ref(),
+        lockfile.as_ref(),
+    )
+    .ok()?;
+    Some((packages, resolve))
+}
+
+/// The workspace packages built, tested or run by the command of `mode`
+fn watched_roots(
+    workspace_path: &Path,
+    resolve: &Resolve,
+    packages: &[PathBuf],
+    package_name_flag: Option<&str>,
+    mode: &WatchMode,
+) -> Vec<String> {
+    match mode {
+        WatchMode::Build | WatchMode::Test => workspace_roots(resolve, package_name_flag),
+        WatchMode::Run {
+            example: Some(example),
+            ..
+        } => vec![package_with_dev_program(
+            resolve,
+            package_name_flag,
+            DevTarget::Examples,
+            example,
+            "example",
+        )],
+        WatchMode::Run { example: None, .. } => {
+            let package_path = package_to_run(packages, package_name_flag);
+            let package_builder = handle_error!(
+                result = PackageBuilder::new(&package_path, workspace_path),
+                message = format!("invalid package at {}", package_path.display())
+            );
+            vec![package_builder.package().name.clone()]
+        }
+    }
+}
+
+/// The local packages whose changes affect `roots` (themselves and their dependencies,
+/// and their dev-dependencies in test mode), with the folders watched for them
+fn watched_packages(resolve: &Resolve, roots: &[String], mode: &WatchMode) -> Vec<String> {
+    let mut names: BTreeSet<String> = BTreeSet::new();
+    for root in roots {
+        let mut related = vec![root.clone()];
+        if let WatchMode::Test = mode {
+            related.extend(resolve.packages[root].dev_dependencies.iter().cloned());
+        }
+        for name in related {
+            let dependencies = handle_error!(
+                result = resolve.transitive_dependencies(&name, false),
+                message = "failed to resolve dependencies"
+            );
+            names.extend(dependencies.into_iter().map(|package| package.name.clone()));
+            names.insert(name);
+        }
+    }
+    names
+        .into_iter()
+        .filter(|name| {
+            let package = &resolve.packages[name];
+            matches!(
+                package.source,
+                PackageSource::Workspace | PackageSource::Path
+            ) && package.external.is_none()
+        })
+        .collect()
+}
+
+/// Starts watching the workspace manifest and the manifests, sources and headers of the local
+/// packages that affect `roots`, and the tests or examples used by the command of `mode`
+fn watch_packages(
+    workspace_path: &Path,
+    resolve: &Resolve,
+    roots: &[String],
+    mode: &WatchMode,
+) -> Watcher {
+    let mut watched = vec![];
+    for name in watched_packages(resolve, roots, mode) {
+        let package_path = &resolve.packages[&name].path;
+        let package_builder = handle_error!(
+            result = PackageBuilder::new(package_path, workspace_path),
+            message = format!("invalid package at {}", package_path.display())
+        );
+        let package = package_builder.package();
+        let mut folders = vec![
+            package_path.join(&package.src_folder),
+            package_path.join(&package.include_folder),
+        ];
+        let dev_target = match mode {
+            WatchMode::Test => Some(DevTarget::Tests),
+            WatchMode::Run {
+                example: Some(_), ..
+            } => Some(DevTarget::Examples),
+            _ => None,
+        };
+        if let (Some(dev_target), true) = (dev_target, roots.contains(&name)) {
+            folders.push(package_path.join(dev_target.folder_name()));
+        }
+        watched.push(WatchedPackage {
+            name,
+            path: package_path.clone(),
+            folders,
+        });
+    }
+    handle_error!(
+        result = Watcher::new(workspace_path, &watched),
+        message = "failed to watch the packages"
+    )
+}
+
+/// The roots that must be built again after `changes`
+fn affected_roots(
+    resolve: &Resolve,
+    roots: &[String],
+    changes: &Changes,
+    mode: &WatchMode,
+) -> Vec<String> {
+    if changes.everything {
+        return roots.to_vec();
+    }
+    roots
+        .iter()
+        .filter(|root| {
+            watched_packages(resolve, std::slice::from_ref(root), mode)
+                .iter()
+                .any(|name| changes.packages.contains(name))
+        })
+        .cloned()
+        .collect()
+}
+
+/// Runs the command of `mode` for the `affected` roots, its errors are reported by the child
+/// processes
+///
+/// When every root is affected, the command runs once with the arguments of this process,
+/// otherwise it runs for every affected root with `--package`. In run mode, the program
+/// is only restarted when the build succeeded
+fn run_watched_command(
+    mode: &WatchMode,
+    roots: &[String],
+    affected: &[String],
+    workspace_path: &Path,
+    resolve: &Resolve,
+    options: &BuildOptions,
+    program: &mut Option<Child>,
+) {
+    match mode {
+        WatchMode::Build | WatchMode::Test if affected.len() == roots.len() => {
+            run_watch_child(&[]);
+        }
+        WatchMode::Build | WatchMode::Test => {
+            for root in affected {
+                run_watch_child(&["--package", root]);
+            }
+        }
+        WatchMode::Run { example, args } => {
+            if !run_watch_child(&["--build-only"]) {
+                return;
+            }
+            if let Some(mut running) = program.take() {
+                println!("\x1b[1;32mRestarting\x1b[0m {}", roots[0]);
+                let _ = running.kill();
+                let _ = running.wait();
+            }
+            *program = Some(start_watched_program(
+                &roots[0],
+                example.as_deref(),
+                args,
+                workspace_path,
+                resolve,
+                options,
+            ));
+        }
+    }
+}
+
+/// Runs `cpm` with the arguments of this process without `--watch`, and `extra_args` added
+/// before the arguments passed to the program. Returns whether it succeeded
+fn run_watch_child(extra_args: &[&str]) -> bool {
+    let mut args: Vec<OsString> = std::env::args_os().skip(1).collect();
+    let separator = args
+        .iter()
+        .position(|arg| arg == "--")
+        .unwrap_or(args.len());
+    let program_args = args.split_off(separator);
+    args.retain(|arg| arg != "--watch");
+    args.extend(extra_args.iter().map(OsString::from));
+    args.extend(program_args);
+    let executable = handle_error!(
+        result = std::env::current_exe(),
+        message = "failed to find the cpm executable"
+    );
+    match std::process::Command::new(executable).args(args).status() {
+        Ok(status) => status.success(),
+        Err(error) => {
+            print_error(format!("failed to run cpm: {error}"));
+            false
+        }
+    }
+}
+
+/// Starts the program of the package `package_name`, which was just built
+fn start_watched_program(
+    package_name: &str,
+    example: Option<&str>,
+    args: &[String],
+    workspace_path: &Path,
+    resolve: &Resolve,
+    options: &BuildOptions,
+) -> Child {
+    let package_path = &resolve.packages[package_name].path;
+    let mut package_builder = handle_error!(
+        result = PackageBuilder::new(package_path, workspace_path),
+        message = format!("invalid package at {}", package_path.display())
+    );
+    let profile = workspace_profile(workspace_path, options);
+    let toolchain = workspace_toolchain(workspace_path, options);
+    handle_error!(
+        result = package_builder.set_toolchain(toolchain.clone()),
+        message = format!("invalid manifest of `{package_name}`")
+    );
+    package_builder.set_profile(profile.clone());
+    // the program was built by the child process, only the paths of its libraries are needed
+    let dependencies = handle_error!(
+        result = resolve.transitive_dependencies(package_name, false),
+        message = "failed to resolve dependencies"
+    );
+    package_builder.set_dependency_artifacts(built_library_artifacts(
+        workspace_path,
+        dependencies,
+        &profile,
+        &toolchain,
+    ));
+    if example.is_some() {
+        package_builder.set_dev_dependency_artifacts(built_library_artifacts(
+            workspace_path,
+            dev_dependencies_of(resolve, package_name),
+            &profile,
+            &toolchain,
+        ));
+    }
+    let program_path = match example {
+        Some(example) => package_builder.dev_target_output_path(
+            DevTarget::Examples,
+            &package_path
+                .join(DevTarget::Examples.folder_name())
+                .join(format!("{example}.c")),
+        ),
+        None => package_builder.output_path(),
+    };
+    let mut command = process::program_command(
+        &program_path,
+        args,
+        &package_builder.package_manifest().run,
+        package_path,
+        &package_builder.runtime_library_folders(),
+    );
+    println!("\x1b[1;32mRunning\x1b[0m {}", program_path.display());
+    handle_error!(
+        result = command.spawn(),
+        message = format!("failed to run `{}`", program_path.display())
+    )
+}
+
+/// Reports the exit of the program started by the watch, which then waits for changes
+fn check_watched_program(program: &mut Option<Child>) {
+    let Some(running) = program else {
+        return;
+    };
+    let Ok(Some(status)) = running.try_wait() else {
+        return;
+    };
+    let (exit_code, message) = process::describe_exit_status(status);
+    match message {
+        Some(message) => print_warning(format!("the program {message}")),
+        None => println!("\x1b[1;32mExited\x1b[0m with code {exit_code}"),
+    }
+    *program = None;
+}
+
+/// Builds and runs the tests of every workspace package, or only of the package named
+/// `package_name_flag`
+///
+/// Every `.c` file of the `tests` folder of a package is a test program, which passes when it
+/// exits with code 0. Only the tests whose name contains `filter` are run
+///
+/// With `coverage`, the packages are built with coverage instrumentation and the lines and
+/// branches executed by the tests are reported. The run fails when the line coverage is
+/// below `fail_under` percent
+pub fn test_project(
+    package_name_flag: Option<String>,
+    filter: Option<String>,
+    coverage: bool,
+    fail_under: Option<f64>,
+    mut options: BuildOptions,
+) {
+    options.coverage = coverage;
+    let package_builders = build_workspace(package_name_flag.as_deref(), &options, true);
+    let mut tests = vec![];
+    for package_builder in &package_builders {
+        for source in package_builder.dev_target_sources(DevTarget::Tests) {
+            let test_name = source
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            if filter
+                .as_ref()
+                .is_some_and(|f| !test_name.contains(f.as_str()))
+            {
+                continue;
+            }
+            let executable_path = handle_error!(
+                result = package_builder.compile_dev_target(
+                    WORKSPACE_COMPILER,
+                    DevTarget::Tests,
+                    &source
+                ),
+                message = format!("failed to build test `{test_name}`")
+            );
+            tests.push((package_builder, test_name, executable_path));
+        }
+    }
+    if coverage {
+        for package_builder in &package_builders {
+            handle_error!(
+                result = coverage::remove_counters(&package_builder.target_folder_path()),
+                message = "failed to remove the coverage data of the previous run"
+            );
+        }
+    }
+    let mut failed = vec![];
+    for (package_builder, test_name, executable_path) in &tests {
+        let full_name = format!("{}/{test_name}", package_builder.package().name);
+        let mut command = process::program_command(
+            executable_path,
+            &[],
+            &package_builder.package_manifest().run,
+            &package_builder.package_path(),
+            &package_builder.runtime_library_folders(),
+        );
+        // the standard error of sanitized tests is captured to find the sanitizer reports
+        let sanitized = !package_builder.profile().sanitizers.is_empty();
+        command.stdout(Stdio::inherit()).stderr(match sanitized {
+            true => Stdio::piped(),
+            false => Stdio::inherit(),
+        });
+        let output = handle_error!(
+            result = command.output(),
+            message = format!("failed to run test `{full_name}`")
+        );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if sanitized {
+            eprint!("{stderr}");
+        }
+        if output.status.success() {
+            println!("test {full_name} ... \x1b[1;32mok\x1b[0m");
+        } else {
+            let (exit_code, message) = process::describe_exit_status(output.status);
+            let reason = process::sanitizer_report(&stderr)
+                .or(message)
+                .unwrap_or_else(|| format!("exit code {exit_code}"));
+            println!("test {full_name} ... \x1b[1;31mFAILED\x1b[0m ({reason})");
+            failed.push(full_name);
+        }
+    }
+    println!();
+    println!(
+        "test result: {} passed; {} failed",
+        tests.len() - failed.len(),
+        failed.len()
+    );
+    let below_threshold = coverage && !report_coverage(&package_builders, fail_under);
+    if !failed.is_empty() {
+        print_error(format!("failing tests: {}", failed.join(", ")));
+    }
+    if !failed.is_empty() || below_threshold {
+        std::process::exit(1);
+    }
+}
+
+/// Prints the coverage of every package after its tests ran and writes the `lcov.info`
+/// and HTML reports in the output folder
+///
+/// Returns whether the line coverage reaches the `fail_under` percentage
+fn report_coverage(package_builders: &[PackageBuilder], fail_under: Option<f64>) -> bool {
+    let Some(first_builder) = package_builders.first() else {
+        return true;
+    };
+    let mut rows = vec![[
+        "Package".to_string(),
+        "Lines".to_string(),
+        "Branches".to_string(),
+    ]];
+    let mut total = CoverageReport::default();
+    for package_builder in package_builders {
+        let package_path = package_builder.package_path();
+        let target_path = package_builder.workspace_path().join("target");
+        let report = handle_error!(
+            result = CoverageReport::collect(
+                package_builder.toolchain(),
+                &package_builder.object_files(),
+                |source| source.starts_with(&package_path) && !source.starts_with(&target_path),
+            ),
+            message = format!(
+                "failed to collect the coverage of `{}`",
+                package_builder.package().name
+            )
+        );
+        let (lines_hit, lines_found) = report.line_totals();
+        let (branches_hit, branches_found) = report.branch_totals();
+        rows.push([
+            package_builder.package().name.clone(),
+            coverage::format_ratio(lines_hit, lines_found),
+            coverage::format_ratio(branches_hit, branches_found),
+        ]);
+        total.extend(report);
+    }
+    let (lines_hit, lines_found) = total.line_totals();
+    let (branches_hit, branches_found) = total.branch_totals();
+    rows.push([
+        "Total".to_string(),
+        coverage::format_ratio(lines_hit, lines_found),
+        coverage::format_ratio(branches_hit, branches_found),
+    ]);
+    println!();
+    print_table(&rows);
+
+    let output_folder = first_builder.target_folder_path();
+    let lcov_path = output_folder.join("lcov.info");
+    handle_error!(
+        result = std::fs::write(&lcov_path, total.to_lcov()),
+        message = format!("failed to write {}", lcov_path.display())
+    );
+    let index_path = handle_error!(
+        result = total.write_html(&output_folder.join("html"), "Coverage report"),
+        message = "failed to write the HTML coverage report"
+    );
+    println!();
+    println!("lcov report: {}", lcov_path.display());
+    println!("HTML report: {}", index_path.display());
+
+    let line_coverage = coverage::percentage(lines_hit, lines_found);
+    match fail_under {
+        Some(threshold) if line_coverage < threshold => {
+            print_error(format!(
+                "line coverage {line_coverage:.1}% is below the minimum of {threshold}%"
+            ));
+            false
+        }
+        _ => true,
+    }
+}
+
+/// Builds and runs the benchmarks of every workspace package, or only of the package named
+/// `package_name_flag`, with the `release` profile unless another profile is selected
+///
+/// Every `.c` file of the `benches` folder of a package is a benchmark program, usually
+/// built with the `cpm_bench.h` harness. The results are compared with the baseline named
+/// `baseline` or `save_baseline` (`base` by default) saved in `target/bench`, and saved as
+/// the baseline unless it was named with `baseline`
+pub fn bench_project(
+    package_name_flag: Option<String>,
+    filter: Option<String>,
+    save_baseline: Option<String>,
+    baseline: Option<String>,
+    mut options: BuildOptions,
+) {
+    if options.profile.is_none() {
+        options.release = true;
+    }
+    let package_builders = build_workspace(package_name_flag.as_deref(), &options, true);
+    let Some(first_builder) = package_builders.first() else {
+        return;
+    };
+    let bench_folder = first_builder
+        .workspace_path()
+        .join("target")
+        .join(first_builder.toolchain().output_subfolder())
+        .join("bench");
+    let compared_baseline = baseline
+        .clone()
+        .or(save_baseline.clone())
+        .unwrap_or_else(|| bench::DEFAULT_BASELINE.to_string());
+    let previous = handle_error!(result = Baseline::load(&bench_folder, &compared_baseline));
+    if let (Some(name), None) = (&baseline, &previous) {
+        print_error(format!(
+            "no baseline named `{name}` in {}",
+            bench_folder.display()
+        ));
+        std::process::exit(1);
+    }
+    let mut benches = vec![];
+    for package_builder in &package_builders {
+        for source in package_builder.dev_target_sources(DevTarget::Benches) {
+            let file_name = source
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            let executable_path = handle_error!(
+                result = package_builder.compile_dev_target(
+                    WORKSPACE_COMPILER,
+                    DevTarget::Benches,
+                    &source
+                ),
+                message = format!("failed to build benchmark `{file_name}`")
+            );
+            benches.push((package_builder, file_name, executable_path));
+        }
+    }
+    let mut results = Baseline::default();
+    let mut failed = vec![];
+    for (package_builder, file_name, executable_path) in &benches {
+        let program_name = format!("{}/{file_name}", package_builder.package().name);
+        // a filter matching the file name runs all its benchmarks
+        let args: Vec<String> = filter
+            .iter()
+            .filter(|f| !file_name.contains(f.as_str()))
+            .cloned()
+            .collect();
+        let mut command = process::program_command(
+            executable_path,
+            &args,
+            &package_builder.package_manifest().run,
+            &package_builder.package_path(),
+            &package_builder.runtime_library_folders(),
+        );
+        println!("\x1b[1;32mRunning\x1b[0m {program_name}");
+        let output = handle_error!(
+            result = command.stderr(Stdio::inherit()).output(),
+            message = format!("failed to run benchmark `{program_name}`")
+        );
+        let (program_results, other_output) = handle_error!(
+            result = bench::parse_output(&String::from_utf8_lossy(&output.stdout)),
+            message = format!("invalid output of benchmark `{program_name}`")
+        );
+        print!("{other_output}");
+        if !output.status.success() {
+            let (exit_code, message) = process::describe_exit_status(output.status);
+            print_error(format!(
+                "benchmark `{program_name}` failed ({})",
+                message.unwrap_or_else(|| format!("exit code {exit_code}"))
+            ));
+            failed.push(program_name);
+            continue;
+        }
+        for (name, stats) in program_results {
+            results
+                .benchmarks
+                .insert(format!("{program_name}/{name}"), stats);
+        }
+    }
+    let mut rows = vec![[
+        "Benchmark".to_string(),
+        "Mean".to_string(),
+        "Median".to_string(),
+        "Std dev".to_string(),
+        format!("Change (vs `{compared_baseline}`)"),
+    ]];
+    for (name, stats) in &results.benchmarks {
+        let change = previous
+            .as_ref()
+            .and_then(|previous| previous.benchmarks.get(name))
+            .map(|previous_stats| stats.compare(previous_stats).to_string())
+            .unwrap_or_default();
+        rows.push([
+            name.clone(),
+            bench::format_time(stats.mean),
+            bench::format_time(stats.median),
+            bench::format_time(stats.stddev),
+            change,
+        ]);
+    }
+    println!();
+    print_table(&rows);
+    if baseline.is_none() && !results.benchmarks.is_empty() {
+        // the benchmarks that didn't run keep their previous results
+        let mut saved = previous.unwrap_or_default();
+        saved.benchmarks.extend(results.benchmarks);
+        let path = handle_error!(
+            result = saved.save(&bench_folder, &compared_baseline),
+            message = "failed to save the benchmark results"
+        );
+        println!();
+        println!("Saved baseline `{compared_baseline}` ({})", path.display());
+    }
+    if !failed.is_empty() {
+        print_error(format!("failing benchmarks: {}", failed.join(", ")));
+        std::process::exit(1);
+    }
+}
+
+/// Builds the workspace packages, or only the package named `package_name_flag`, with
+/// profile-guided optimization, with the `release` profile unless another profile is selected
+///
+/// The packages are built with instrumentation and the training workloads of the `[pgo]`
+/// sections of their manifests run to collect their execution profiles in `target/pgo`.
+/// The packages are then rebuilt with these profiles in the `pgo-use` folder of the profile
+/// output folder, next to the outputs of the profile, which aren't replaced. With
+/// `skip_training`, the profiles of the previous training are used, with warnings when
+/// they're stale
+pub fn pgo_project(
+    package_name_flag: Option<String>,
+    skip_training: bool,
+    mut options: BuildOptions,
+) {
+    if options.profile.is_none() {
+        options.release = true;
+    }
+    let (workspace_path, packages) = workspace_packages();
+    let resolve = resolve_workspace(&workspace_path, &packages, &options);
+    let roots = workspace_roots(&resolve, package_name_flag.as_deref());
+    let profile = workspace_profile(&workspace_path, &options);
+    let toolchain = workspace_toolchain(&workspace_path, &options);
+    let profiles_folder = pgo::profiles_folder_path(&workspace_path, &toolchain);
+    if skip_training {
+        check_pgo_training(
+            &workspace_path,
+            &resolve,
+            &roots,
+            &profile,
+            &toolchain,
+            &profiles_folder,
+        );
+    } else {
+        run_pgo_training(
+            &workspace_path,
+            &resolve,
+            &roots,
+            &profile,
+            &toolchain,
+            &profiles_folder,
+        );
+    }
+    let optimized_profile = pgo::phase_profile(&profile, Pgo::Use);
+    // the profiles aren't inputs of the builds, so the optimized outputs are always rebuilt
+    let optimized_folder = optimized_profile.target_folder_path(&workspace_path, &toolchain);
+    if optimized_folder.exists() {
+        handle_error!(
+            result = std::fs::remove_dir_all(&optimized_folder),
+            message = format!("failed to remove {}", optimized_folder.display())
+        );
+    }
+    handle_error!(
+        result = pgo::install_profiles(&profiles_folder, &optimized_folder),
+        message = "failed to copy the profiles"
+    );
+    let package_builders = build_packages(
+        &workspace_path,
+        &resolve,
+        &roots,
+        &optimized_profile,
+        &toolchain,
+        WORKSPACE_COMPILER,
+        false,
+    );
+    for package_builder in &package_builders {
+        println!(
+            "\x1b[1;32mOptimized\x1b[0m {} ({})",
+            package_builder.package().name,
+            package_builder.output_path().display()
+        );
+    }
+    println!(
+        "The optimized outputs are in {}, the outputs of the `{}` profile aren't replaced",
+        optimized_folder.display(),
+        profile.name
+    );
+}
+
+/// Builds the packages with instrumentation and runs their training workloads, then
+/// collects their execution profiles in `profiles_folder`
+///
+/// An executable package runs with the arguments of its `[pgo] run` setting (none by default),
+/// a library runs its benchmarks. `[pgo] bench = true` runs the benchmarks of any package
+fn run_pgo_training(
+    workspace_path: &Path,
+    resolve: &Resolve,
+    roots: &[String],
+    profile: &BuildProfile,
+    toolchain: &Toolchain,
+    profiles_folder: &Path,
+) {
+    let instrumented_profile = pgo::phase_profile(profile, Pgo::Generate);
+    let instrumented_folder = instrumented_profile.target_folder_path(workspace_path, toolchain);
+    let package_builders = build_packages(
+        workspace_path,
+        resolve,
+        roots,
+        &instrumented_profile,
+        toolchain,
+        WORKSPACE_COMPILER,
+        true,
+    );
+    handle_error!(
+        result = pgo::remove_profiles(&instrumented_folder),
+        message = "failed to remove the profiles of the previous training"
+    );
+    let mut trained = vec![];
+    for package_builder in &package_builders {
+        let package = package_builder.package();
+        let settings = package_builder
+            .package_manifest()
+            .pgo
+            .clone()
+            .unwrap_or_default();
+        let executable = package.kind == PackageKind::Executable;
+        if settings.run.is_some() && !executable {
+            print_error(format!(
+                "`[pgo] run` is set for `{}`, but it's not an executable",
+                package.name
+            ));
+            std::process::exit(1);
+        }
+        let run_args = match settings.run {
+            Some(args) => Some(args),
+            None if executable && !settings.bench => Some(vec![]),
+            None => None,
+        };
+        let benches = match settings.bench || !executable {
+            true => package_builder.dev_target_sources(DevTarget::Benches),
+            false => vec![],
+        };
+        if run_args.is_none() && benches.is_empty() {
+            print_warning(format!(
+                "`{}` has no training workload, add a `[pgo]` section to its manifest",
+                package.name
+            ));
+            continue;
+        }
+        if let Some(args) = run_args {
+            run_pgo_workload(
+                package_builder,
+                &package_builder.output_path(),
+                &args,
+                false,
+            );
+        }
+        for source in benches {
+            let executable_path = handle_error!(
+                result = package_builder.compile_dev_target(
+                    WORKSPACE_COMPILER,
+                    DevTarget::Benches,
+                    &source
+                ),
+                message = format!("failed to build benchmark `{}`", source.display())
+            );
+            // the results of the benchmarks don't matter, only their profiles
+            run_pgo_workload(package_builder, &executable_path, &[], true);
+        }
+        trained.push(package.name.clone());
+    }
+    let profile_count = handle_error!(
+        result = pgo::collect_profiles(&instrumented_folder, profiles_folder),
+        message = "failed to collect the profiles"
+    );
+    if profile_count == 0 {
+        print_error(format!(
+            "the training didn't write any profile in {}",
+            profiles_folder.display()
+        ));
+        std::process::exit(1);
+    }
+    let training = Training {
+        profile: profile.name.clone(),
+        target: toolchain.target.triple.clone(),
+        packages: trained,
+    };
+    handle_error!(
+        result = training.save(profiles_folder),
+        message = "failed to save the training record"
+    );
+    println!(
+        "\x1b[1;32mCollected\x1b[0m {profile_count} profiles in {}",
+        profiles_folder.display()
+    );
+}
+
+/// Runs a program of the training workload of a package, exits when it fails
+fn run_pgo_workload(
+    package_builder: &PackageBuilder,
+    program_path: &Path,
+    args: &[String],
+    quiet: bool,
+) {
+    println!(
+        "\x1b[1;32mTraining\x1b[0m {} ({})",
+        package_builder.package().name,
+        program_path.display()
+    );
+    let mut command = process::program_command(
+        program_path,
+        args,
+        &package_builder.package_manifest().run,
+        &package_builder.package_path(),
+        &package_builder.runtime_library_folders(),
+    );
+    if quiet {
+        command.stdout(Stdio::null());
+    }
+    let status = handle_error!(
+        result = command.status(),
+        message = format!("failed to run `{}`", program_path.display())
+    );
+    if !status.success() {
+        let (exit_code, message) = process::describe_exit_status(status);
+        print_error(format!(
+            "the training workload `{}` failed ({})",
+            program_path.display(),
+            message.unwrap_or_else(|| format!("exit code {exit_code}"))
+        ));
+        std::process::exit(1);
+    }
+}
+
+/// Checks that the profiles of the previous training can be used to optimize the packages,
+/// warns when they were collected for another profile or target, or when the packages changed
+fn check_pgo_training(
+    workspace_path: &Path,
+    resolve: &Resolve,
+    roots: &[String],
+    profile: &BuildProfile,
+    toolchain: &Toolchain,
+    profiles_folder: &Path,
+) {
+    let training = handle_error!(result = Training::load(profiles_folder));
+    let Some(training) = training else {
+        print_error(format!(
+            "no profiles in {}, run `cpm pgo` without `--skip-training` first",
+            profiles_folder.display()
+        ));
+        std::process::exit(1);
+    };
+    if training.profile != profile.name {
+        print_warning(format!(
+            "the profiles were collected with the `{}` profile, not `{}`",
+            training.profile, profile.name
+        ));
+    }
+    if training.target != toolchain.target.triple {
+        print_warning(format!(
+            "the profiles were collected for `{}`, not `{}`",
+            training.target, toolchain.target.triple
+        ));
+    }
+    for root in roots {
+        let package_builder = handle_error!(
+            result = PackageBuilder::new(&resolve.packages[root].path, workspace_path)
+        );
+        let inputs = handle_error!(result = package_builder.inputs());
+        let modified = Training::modified_inputs(profiles_folder, &inputs);
+        if let Some(first) = modified.first() {
+            print_warning(format!(
+                "the profiles of `{root}` are stale, {} of its files changed since the training (e.g. {}), run `cpm pgo` to train again",
+                modified.len(),
+                first.display()
+            ));
+        }
+    }
+}
+
+/// Creates the tarball of a package and, when `verify` is set, checks that the
+/// extracted tarball builds on its own. Returns the path of the tarball
+fn create_package(
+    package_builder: &PackageBuilder,
+    registry: &Registry,
+    verify: bool,
+    options: &BuildOptions,
+) -> PathBuf {
+    let package = package_builder.package();
+    let tarball_path = handle_error!(
+        result = packaging::create_package_tarball(package_builder),
+        message = format!("failed to package `{}`", package.name)
+    );
+    println!(
+        "\x1b[1;32mPackaged\x1b[0m {} v{} ({})",
+        package.name,
+        package.version,
+        tarball_path.display()
+    );
+    if verify {
+        let extracted_path = handle_error!(
+            result = packaging::extract_for_verification(
+                &tarball_path,
+                &package_builder.workspace_path(),
+                &format!("{}-{}", package.name, package.version),
+            ),
+            message = "failed to extract the package tarball"
+        );
+        let resolve = handle_error!(
+            result = Resolve::new(
+                std::slice::from_ref(&extracted_path),
+                &BTreeMap::new(),
+                package_builder.target(),
+                registry,
+                &GitCache::new(options.offline),
+                None,
+                None
+            ),
+            message = format!("failed to resolve the dependencies of `{}`", package.name)
+        );
+        build_packages(
+            &extracted_path,
+            &resolve,
+            std::slice::from_ref(&package.name),
+            package_builder.profile(),
+            package_builder.toolchain(),
+            WORKSPACE_COMPILER,
+            false,
+        );
+        println!(
+            "\x1b[1;32mVerified\x1b[0m {} v{}",
+            package.name, package.version
+        );
+    }
+    tarball_path
+}
+
+pub fn package_project(package_name_flag: Option<String>, no_verify: bool, options: BuildOptions) {
+    let (workspace_path, _) = workspace_packages();
+    let registry = workspace_registry(&workspace_path);
+    let profile = workspace_profile(&workspace_path, &options);
+    let toolchain = workspace_toolchain(&workspace_path, &options);
+    for mut package_builder in package_builders(package_name_flag.as_deref()) {
+        package_builder.set_profile(profile.clone());
+        handle_error!(
+            result = package_builder.set_toolchain(toolchain.clone()),
+            message = format!("invalid manifest of `{}`", package_builder.package().name)
+        );
+        create_package(&package_builder, &registry, !no_verify, &options);
+    }
+}
+
+pub fn publish_project(package_name_flag: Option<String>, no_verify: bool, options: BuildOptions) {
+    let (workspace_path, _) = workspace_packages();
+    let registry = workspace_registry(&workspace_path);
+    let profile = workspace_profile(&workspace_path, &options);
+    let toolchain = workspace_toolchain(&workspace_path, &options);
+    for mut package_builder in package_builders(package_name_flag.as_deref()) {
+        package_builder.set_profile(profile.clone());
+        handle_error!(
+            result = package_builder.set_toolchain(toolchain.clone()),
+            message = format!("invalid manifest of `{}`", package_builder.package().name)
+        );
+        let package = package_builder.package();
+        let already_published = handle_error!(
+            result = registry.index(&package.name),
+            message = "failed to read the registry index"
+        )
+        .versions
+        .iter()
+        .any(|entry| entry.version == package.version);
+        if already_published {
+            print_error(format!(
+                "version {} of `{}` is already published",
+                package.version, package.name
+            ));
+            std::process::exit(1);
+        }
+        let tarball_path = create_package(&package_builder, &registry, !no_verify, &options);
+        let manifest = handle_error!(
+            result = packaging::normalized_manifest(package_builder.package_manifest())
+        );
+        handle_error!(
+            result = registry.publish(&package.name, &package.version, &tarball_path, &manifest,),
+            message = format!("failed to publish `{}`", package.name)
+        );
+        println!(
+            "\x1b[1;32mPublished\x1b[0m {} v{} to {}",
+            package.name,
+            package.version,
+            registry.index_path(&package.name).display()
+        );
+    }
+}
+
+pub fn vendor_project(vendor_folder: PathBuf, options: BuildOptions) {
+    let (workspace_path, packages) = workspace_packages();
+    let vendor = VendorDirectory::new(workspace_path.join(&vendor_folder), false);
+    let resolve = resolve_dependencies(&workspace_path, &packages, &options, None);
+    handle_error!(
+        result = vendor.clear(),
+        message = "failed to remove the previously vendored packages"
+    );
+    let mut vendored_count = 0;
+    for package in resolve.packages.values() {
+        let vendored = handle_error!(
+            result = vendor.vendor_package(package),
+            message = format!("failed to vendor `{}`", package.name)
+        );
+        if vendored {
+            println!(
+                "\x1b[1;32mVendored\x1b[0m {} v{}",
+                package.name, package.version
+            );
+            vendored_count += 1;
+        }
+    }
+    println!(
+        "\x1b[1;32mFinished\x1b[0m vendoring {vendored_count} packages into {}",
+        vendor_folder.display()
+    );
+    let configured = workspace_vendor(&workspace_path, &options)
+        .is_some_and(|configured| configured.path() == vendor.path());
+    if !configured {
+        println!();
+        println!(
+            "To build with the vendored sources, add this to the workspace `{MANIFEST_FILE_NAME}`:"
+        );
+        println!();
+        println!("[vendor]");
+        println!("path = {:?}", vendor_folder.display().to_string());
+    }
+}
+
+/// Finds the folder of the package whose manifest is edited by `cpm add` and `cpm remove`
+///
+/// It's the package named `package_name_flag`, or the package in the current folder
+fn package_to_edit(package_name_flag: Option<&str>) -> PackageBuilder {
+    let (workspace_path, _) = workspace_packages();
+    let mut package_builders = package_builders(package_name_flag);
+    match package_name_flag {
+        Some(_) => package_builders.remove(0),
+        None => handle_error!(
+            option = package_builders
+                .into_iter()
+                .find(|package_builder| package_builder.package_path() == workspace_path),
+            message =
+                "there's no package in the current folder, specify the package with `-p <name>`"
+        ),
+    }
+}
+
+/// Saves the edited manifest and checks that the dependencies still resolve,
+/// the manifest is restored when they don't
+fn save_edited_manifest(editor: &ManifestEditor, options: &BuildOptions) {
+    handle_error!(
+        result = editor.save(),
+        message = format!("failed to write `{MANIFEST_FILE_NAME}`")
+    );
+    let (workspace_path, packages) = workspace_packages();
+    let vendor = workspace_vendor(&workspace_path, options);
+    let lockfile = load_lockfile(&workspace_path);
+    let resolve = try_resolve_dependencies(
+        &workspace_path,
+        &packages,
+        options,
+        vendor.as_ref(),
+        lockfile.as_ref(),
+    );
+    match resolve {
+        Ok(resolve) => save_lockfile(&workspace_path, &resolve, lockfile.as_ref()),
+        Err(error) => {
+            handle_error!(
+                result = editor.restore(),
+                message = format!("failed to restore `{MANIFEST_FILE_NAME}`")
+            );
+            print_error(format!("failed to resolve dependencies: {error}"));
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Adds a dependency to the manifest of a package
+///
+/// `dependency` is the name of the dependency, optionally followed by `@<version requirement>`.
+/// Registry dependencies without a version requirement get the latest version of the registry
+pub fn add_dependency(
+    package_name_flag: Option<String>,
+    dependency: String,
+    mut entry: DependencyEntry,
+    options: BuildOptions,
+) {
+    let (name, version) = match dependency.split_once('@') {
+        Some((name, version)) => (name.to_string(), Some(version.to_string())),
+        None => (dependency, None),
+    };
+    if let Some(version) = &version {
+        handle_error!(
+            result = semver::VersionReq::parse(version),
+            message = format!("invalid version requirement `{version}`")
+        );
+    }
+    let package_builder = package_to_edit(package_name_flag.as_deref());
+    entry.version = version;
+    if entry.version.is_none() && entry.path.is_none() && entry.git.is_none() {
+        let index = handle_error!(
+            result = workspace_registry(&package_builder.workspace_path()).index(&name),
+            message = "failed to read the registry index"
+        );
+        let latest = handle_error!(
+            option = index
+                .versions
+                .iter()
+                .filter(|entry| !entry.yanked)
+                .map(|entry| &entry.version)
+                .max(),
+            message = format!("no package named `{name}` in the registry")
+        );
+        entry.version = Some(latest.to_string());
+    }
+    let mut editor = handle_error!(
+        result = ManifestEditor::open(&package_builder.package_path()),
+        message = format!("failed to open `{MANIFEST_FILE_NAME}`")
+    );
+    let replaced = handle_error!(
+        result = editor.add_dependency(&name, &entry),
+        message = format!("failed to edit `{MANIFEST_FILE_NAME}`")
+    );
+    save_edited_manifest(&editor, &options);
+    println!(
+        "\x1b[1;32m{}\x1b[0m {name}{} to the dependencies of `{}`",
+        if replaced { "Updated" } else { "Added" },
+        entry
+            .version
+            .map(|version| format!(" {version}"))
+            .unwrap_or_default(),
+        package_builder.package().name
+    );
+}
+
+/// Removes a dependency from the manifest of a package
+pub fn remove_dependency(package_name_flag: Option<String>, name: String, options: BuildOptions) {
+    let package_builder = package_to_edit(package_name_flag.as_deref());
+    let mut editor = handle_error!(
+        result = ManifestEditor::open(&package_builder.package_path()),
+        message = format!("failed to open `{MANIFEST_FILE_NAME}`")
+    );
+    handle_error!(result = editor.remove_dependency(&name));
+    save_edited_manifest(&editor, &options);
+    println!(
+        "\x1b[1;32mRemoved\x1b[0m {name} from the dependencies of `{}`",
         package_builder.package().name
     );
-    std::process::exit(
-        Command::new(package_builder.output_path())
-            .spawn()
-            .unwrap()
-            .wait()
-            .unwrap()
-            .code()
-            .unwrap(),
+}
+
+/// Describes a locked package for the messages of `cpm update` (e.g. `v1.2.0` or `v0.1.0 (903e47a)`)
+fn locked_package_description(package: &LockedPackage) -> String {
+    match package.source.rsplit_once('#') {
+        Some((_, commit)) => format!("v{} ({})", package.version, &commit[..commit.len().min(7)]),
+        None => format!("v{}", package.version),
+    }
+}
+
+/// Prints the packages added, removed and updated between two lockfiles
+/// and returns the number of changes
+fn print_lockfile_changes(old_lockfile: Option<&Lockfile>, new_lockfile: &Lockfile) -> usize {
+    let old_packages = old_lockfile
+        .map(|l| l.packages.as_slice())
+        .unwrap_or_default();
+    let mut names: Vec<&String> = old_packages
+        .iter()
+        .chain(&new_lockfile.packages)
+        .map(|package| &package.name)
+        .collect();
+    names.sort();
+    names.dedup();
+    let mut changes = 0;
+    for name in names {
+        let old = old_packages.iter().find(|p| &p.name == name);
+        let new = new_lockfile.package(name);
+        let message = match (old, new) {
+            (Some(old), None) => {
+                format!("Removing\x1b[0m {name} {}", locked_package_description(old))
+            }
+            (None, Some(new)) => {
+                format!("Adding\x1b[0m {name} {}", locked_package_description(new))
+            }
+            (Some(old), Some(new)) if old.version != new.version || old.source != new.source => {
+                format!(
+                    "Updating\x1b[0m {name} {} -> {}",
+                    locked_package_description(old),
+                    locked_package_description(new)
+                )
+            }
+            _ => continue,
+        };
+        println!("\x1b[1;32m{message}");
+        changes += 1;
+    }
+    changes
+}
+
+/// Updates the versions pinned in the lockfile within the requirements of the manifests
+///
+/// Every package is updated, or only the package named `package_name_flag`.
+/// `precise` pins that package to a specific version (or commit for git packages)
+pub fn update_dependencies(
+    package_name_flag: Option<String>,
+    precise: Option<String>,
+    options: BuildOptions,
+) {
+    let (workspace_path, packages) = workspace_packages();
+    let lockfile = load_lockfile(&workspace_path);
+    let vendor = workspace_vendor(&workspace_path, &options);
+    let update_lockfile = match (&package_name_flag, &lockfile) {
+        (None, _) => None,
+        (Some(name), lockfile) => {
+            let mut update_lockfile = lockfile.clone().unwrap_or_default();
+            let locked = handle_error!(
+                option = update_lockfile.remove_package(name),
+                message = format!("package `{name}` is not in `{LOCKFILE_NAME}`")
+            );
+            if let Some(precise) = &precise {
+                let (version, source) = match locked.source.rsplit_once('#') {
+                    Some((prefix, _)) => (locked.version.clone(), format!("{prefix}#{precise}")),
+                    None => (
+                        handle_error!(
+                            result = semver::Version::parse(precise),
+                            message = format!("invalid version `{precise}`")
+                        ),
+                        locked.source.clone(),
+                    ),
+                };
+                if locked.source == "registry" {
+                    let index = match &vendor {
+                        Some(vendor) => handle_error!(
+                            result = vendor.index(name),
+                            message = "failed to read the vendored index"
+                        ),
+                        None => handle_error!(
+                            result = workspace_registry(&workspace_path).index(name),
+                            message = "failed to read the registry index"
+                        ),
+                    };
+                    if !index.versions.iter().any(|entry| entry.version == version) {
+                        print_error(format!(
+                            "version {precise} of `{name}` not found in the registry"
+                        ));
+                        std::process::exit(1);
+                    }
+                }
+                update_lockfile.packages.push(LockedPackage {
+                    version,
+                    source,
+                    checksum: None,
+                    ..locked
+                });
+            }
+            Some(update_lockfile)
+        }
+    };
+    let resolve = handle_error!(
+        result = try_resolve_dependencies(
+            &workspace_path,
+            &packages,
+            &options,
+            vendor.as_ref(),
+            update_lockfile.as_ref()
+        ),
+        message = "failed to resolve dependencies"
+    );
+    if let (Some(name), Some(precise)) = (&package_name_flag, &precise) {
+        let resolved = handle_error!(
+            option = resolve.packages.get(name),
+            message = format!("no dependency named `{name}` in the workspace")
+        );
+        if let (PackageSource::Registry { .. }, false) =
+            (&resolved.source, resolved.version.to_string() == *precise)
+        {
+            print_error(format!(
+                "version {precise} of `{name}` does not satisfy the requirements of its dependents"
+            ));
+            std::process::exit(1);
+        }
+    }
+    let changes = print_lockfile_changes(lockfile.as_ref(), &resolve.lockfile());
+    save_lockfile(&workspace_path, &resolve, lockfile.as_ref());
+    println!("\x1b[1;32mFinished\x1b[0m updating `{LOCKFILE_NAME}` ({changes} changes)");
+}
+
+/// Prints, for every registry dependency, the locked version, the latest version compatible
+/// with the requirements of its dependents and the latest version of the registry
+pub fn outdated_dependencies(options: BuildOptions) {
+    let (workspace_path, packages) = workspace_packages();
+    let resolve = resolve_workspace(&workspace_path, &packages, &options);
+    let requirements = handle_error!(
+        result = resolve.dependency_requirements(),
+        message = "failed to resolve dependencies"
+    );
+    let registry = workspace_registry(&workspace_path);
+    let mut rows = vec![[
+        "Name".to_string(),
+        "Locked".to_string(),
+        "Compatible".to_string(),
+        "Latest".to_string(),
+    ]];
+    for package in resolve.packages.values() {
+        if !matches!(package.source, PackageSource::Registry { .. }) {
+            continue;
+        }
+        let index = handle_error!(
+            result = registry.index(&package.name),
+            message = "failed to read the registry index"
+        );
+        let available = index.versions.iter().filter(|entry| !entry.yanked);
+        let package_requirements = requirements
+            .get(&package.name)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let compatible = available
+            .clone()
+            .filter(|entry| {
+                package_requirements
+                    .iter()
+                    .all(|(_, requirement)| requirement.matches(&entry.version))
+            })
+            .map(|entry| &entry.version)
+            .max();
+        let latest = available.map(|entry| &entry.version).max();
+        let display = |version: Option<&semver::Version>| {
+            version.map_or_else(|| "-".to_string(), |version| version.to_string())
+        };
+        rows.push([
+            package.name.clone(),
+            package.version.to_string(),
+            display(compatible),
+            display(latest),
+        ]);
+    }
+    if rows.len() == 1 {
+        println!("No registry dependencies");
+        return;
+    }
+    print_table(&rows);
+}
+
+/// Prints rows of cells as a table with aligned columns, the first row being the header
+fn print_table<const COLUMNS: usize>(rows: &[[String; COLUMNS]]) {
+    let widths: Vec<usize> = (0..COLUMNS)
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+    for row in rows {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:width$}"))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+/// Prints the dependency tree of every workspace package, or only of the package
+/// named `package_name_flag`
+pub fn print_tree(package_name_flag: Option<String>, options: BuildOptions) {
+    let (workspace_path, packages) = workspace_packages();
+    let resolve = resolve_workspace(&workspace_path, &packages, &options);
+    let roots: Vec<String> = metadata::workspace_members(&resolve)
+        .into_iter()
+        .filter(|name| package_name_flag.as_ref().is_none_or(|flag| flag == name))
+        .collect();
+    if let (Some(name), true) = (&package_name_flag, roots.is_empty()) {
+        print_error(format!("no package named `{name}` in the workspace"));
+        std::process::exit(1);
+    }
+    let trees: Vec<String> = roots
+        .iter()
+        .map(|root| metadata::dependency_tree(&resolve, root))
+        .collect();
+    println!("{}", trees.join("\n\n"));
+}
+
+/// Prints the resolved dependency graph of the workspace as JSON
+pub fn print_metadata(options: BuildOptions) {
+    let (workspace_path, packages) = workspace_packages();
+    let resolve = resolve_workspace(&workspace_path, &packages, &options);
+    let metadata = Metadata::new(workspace_path, &resolve);
+    let json = handle_error!(
+        result = serde_json::to_string_pretty(&metadata),
+        message = "failed to serialize the metadata"
     );
+    // the metadata is usually piped into other tools, which may stop reading early
+    match writeln!(std::io::stdout().lock(), "{json}") {
+        Err(err) if err.kind() == std::io::ErrorKind::BrokenPipe => {}
+        result => handle_error!(result = result, message = "failed to write the metadata"),
+    }
+}
+
+/// Builds every workspace package, or only the package named `package_name_flag`, and prints
+/// the sizes of the sections of their outputs with the change since the previous build
+/// and their `symbol_count` largest symbols
+///
+/// With `by_object`, the sizes of the object files and of the dependencies linked into
+/// the outputs are printed too
+pub fn print_sizes(
+    package_name_flag: Option<String>,
+    symbol_count: usize,
+    by_object: bool,
+    options: BuildOptions,
+) {
+    let package_builders = build_workspace(package_name_flag.as_deref(), &options, false);
+    for (i, package_builder) in package_builders.iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        let package = package_builder.package();
+        let toolchain = package_builder.toolchain();
+        let output_path = package_builder.output_path();
+        let history = handle_error!(
+            result = package_builder.size_history(),
+            message = format!("failed to measure `{}`", package.name)
+        );
+        println!(
+            "\x1b[1;32m{}\x1b[0m v{} ({})",
+            package.name,
+            package.version,
+            output_path.display()
+        );
+        println!();
+        let budget = package.size_budget.unwrap_or_default();
+        let limits = [budget.text, budget.data, budget.bss];
+        let previous_sizes = history.previous.map(|previous| previous.sections());
+        let mut rows = vec![[
+            "Section".to_string(),
+            "Size".to_string(),
+            "Change".to_string(),
+            "Budget".to_string(),
+        ]];
+        for (i, ((section, size), limit)) in history
+            .current
+            .sections()
+            .into_iter()
+            .zip(limits)
+            .enumerate()
+        {
+            let change = match previous_sizes.map(|sections| sections[i].1) {
+                Some(previous) if size >= previous => format!("+{}", size - previous),
+                Some(previous) => format!("-{}", previous - size),
+                None => "-".to_string(),
+            };
+            let budget = match limit {
+                Some(limit) => format!(
+                    "{limit} ({:.1}%)",
+                    size as f64 * 100.0 / limit.max(1) as f64
+                ),
+                None => "-".to_string(),
+            };
+            rows.push([format!(".{section}"), size.to_string(), change, budget]);
+        }
+        print_table(&rows);
+        if by_object {
+            print_object_sizes(package_builder, symbol_count);
+            continue;
+        }
+        let symbols = handle_error!(
+            result = size::largest_symbols(toolchain, &output_path, symbol_count),
+            message = format!("failed to list the symbols of {}", output_path.display())
+        );
+        if !symbols.is_empty() {
+            println!();
+            let mut rows = vec![["Size".to_string(), "Type".to_string(), "Symbol".to_string()]];
+            rows.extend(symbols.into_iter().map(|symbol| {
+                [
+                    symbol.size.to_string(),
+                    symbol.kind.to_string(),
+                    symbol.name,
+                ]
+            }));
+            print_table(&rows);
+        }
+    }
+}
+
+/// Prints the sizes of the object files and of the dependencies linked into the output
+/// of a package, and its `symbol_count` largest symbols grouped by the file that defines them
+fn print_object_sizes(package_builder: &PackageBuilder, symbol_count: usize) {
+    let toolchain = package_builder.toolchain();
+    let objects_folder_path = package_builder.objects_folder_path();
+    let mut files: Vec<(String, PathBuf)> = package_builder
+        .object_files()
+        .into_iter()
+        .map(|object_file| {
+            let name = object_file
+                .strip_prefix(&objects_folder_path)
+                .unwrap_or(&object_file)
+                .display()
+                .to_string();
+            (name, object_file)
+        })
+        .collect();
+    if package_builder.package().kind != PackageKind::StaticLibrary {
+        files.extend(
+            package_builder
+                .dependency_artifacts()
+                .iter()
+                .flat_map(|artifact| &artifact.library_paths)
+                .map(|library| (library.display().to_string(), library.clone())),
+        );
+    }
+    let mut file_sizes: Vec<(&str, SectionSizes)> = vec![];
+    let mut file_symbols = vec![];
+    for (name, path) in &files {
+        let sizes = handle_error!(
+            result = size::section_sizes(toolchain, path),
+            message = format!("failed to measure {}", path.display())
+        );
+        let symbols = handle_error!(
+            result = size::largest_symbols(toolchain, path, symbol_count),
+            message = format!("failed to list the symbols of {}", path.display())
+        );
+        file_sizes.push((name, sizes));
+        file_symbols.push((name.as_str(), symbols));
+    }
+    file_sizes.sort_by(|(a_name, a), (b_name, b)| b.text.cmp(&a.text).then(a_name.cmp(b_name)));
+    println!();
+    let mut rows = vec![[
+        "Text".to_string(),
+        "Data".to_string(),
+        "Bss".to_string(),
+        "File".to_string(),
+    ]];
+    rows.extend(file_sizes.into_iter().map(|(name, sizes)| {
+        [
+            sizes.text.to_string(),
+            sizes.data.to_string(),
+            sizes.bss.to_string(),
+            name.to_string(),
+        ]
+    }));
+    print_table(&rows);
+    let groups = size::group_largest_symbols(file_symbols, symbol_count);
+    if groups.is_empty() {
+        return;
+    }
+    println!();
+    let mut rows = vec![[
+        "File".to_string(),
+        "Size".to_string(),
+        "Type".to_string(),
+        "Symbol".to_string(),
+    ]];
+    for (name, symbols) in groups {
+        for (i, symbol) in symbols.into_iter().enumerate() {
+            // the file is only written on the first row of its symbols
+            let file = if i == 0 {
+                name.to_string()
+            } else {
+                String::new()
+            };
+            rows.push([
+                file,
+                symbol.size.to_string(),
+                symbol.kind.to_string(),
+                symbol.name,
+            ]);
+        }
+    }
+    print_table(&rows);
 }
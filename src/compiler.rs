@@ -1,26 +1,42 @@
-use std::{path::PathBuf, process::Command};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
 
-use crate::manifest;
+use crate::{manifest, profile::BuildProfile, toolchain::Toolchain};
 
+mod clang;
+pub mod errors;
 mod gcc;
+pub use clang::Clang;
+use errors::CompilerError;
 pub use gcc::GCC;
+
 /// Any type that implements this trait can be used to generate a command to compile a package.
 ///
 /// For instance you might implement a generator for GCC, Clang, MSVC and other compilers based on the
 /// manifest of the package
 pub trait Compiler {
+    /// The name of the compiler program of the toolchain (e.g. `gcc` or `aarch64-linux-gnu-gcc`)
+    ///
+    /// It's passed as `CC` to the build systems of third-party dependencies
+    fn program(&self, toolchain: &Toolchain) -> String;
+
     /// Generates the compile command for a specific file
     ///
     /// It must be able to compile the input from the `source_path` and produce a file in `output_path`
     /// with the result.
     ///
     /// It may use the package info to modify the way the command is generated (e.g. with compiler flags or dependencies)
+    /// and must apply the settings of the build profile and use the programs and flags of the toolchain
     fn compile_command(
         &self,
         package_path: PathBuf,
         source_path: PathBuf,
         output_path: PathBuf,
         package_info: &manifest::Package,
+        profile: &BuildProfile,
+        toolchain: &Toolchain,
     ) -> Command;
 
     /// Generates a link command
@@ -29,11 +45,27 @@ pub trait Compiler {
     /// on this trait and produce a executable at `output_path`.
     ///
     /// It may use the package info to modify the way the command is generated (e.g. with linker flags or dependencies)
+    /// and must apply the settings of the build profile and use the programs and flags of the toolchain
     fn link_command(
         &self,
         package_path: PathBuf,
         object_files: Vec<PathBuf>,
         output_path: PathBuf,
         package_info: &manifest::Package,
+        profile: &BuildProfile,
+        toolchain: &Toolchain,
     ) -> Command;
+
+    /// Checks that the toolchain supports the link-time settings of the build profile
+    /// (`lto`, `linker`, `strip` and `split-debuginfo`)
+    ///
+    /// It's called before building, so unsupported combinations (e.g. ThinLTO with GCC or a linker
+    /// that isn't installed) are reported up front instead of failing when linking a package.
+    /// `probe_folder` can be used to build a test program
+    fn check_profile(
+        &self,
+        profile: &BuildProfile,
+        toolchain: &Toolchain,
+        probe_folder: &Path,
+    ) -> Result<(), CompilerError>;
 }
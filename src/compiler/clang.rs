@@ -0,0 +1,67 @@
+use crate::{profile::BuildProfile, toolchain::Toolchain};
+
+use super::{errors::CompilerError, gcc, Compiler};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// The Clang driver, which accepts the options of GCC
+///
+/// It's used by `cpm fuzz` for libFuzzer, which GCC doesn't provide
+pub struct Clang;
+
+impl Compiler for Clang {
+    fn program(&self, toolchain: &Toolchain) -> String {
+        toolchain.compiler_program("clang")
+    }
+
+    fn compile_command(
+        &self,
+        package_path: PathBuf,
+        source_path: PathBuf,
+        output_path: PathBuf,
+        package_info: &crate::manifest::Package,
+        profile: &BuildProfile,
+        toolchain: &Toolchain,
+    ) -> Command {
+        gcc::compile_command(
+            "clang",
+            package_path,
+            source_path,
+            output_path,
+            package_info,
+            profile,
+            toolchain,
+        )
+    }
+
+    fn link_command(
+        &self,
+        package_path: PathBuf,
+        object_files: Vec<PathBuf>,
+        output_path: PathBuf,
+        package_info: &crate::manifest::Package,
+        profile: &BuildProfile,
+        toolchain: &Toolchain,
+    ) -> Command {
+        gcc::link_command(
+            "clang",
+            package_path,
+            object_files,
+            output_path,
+            package_info,
+            profile,
+            toolchain,
+        )
+    }
+
+    fn check_profile(
+        &self,
+        profile: &BuildProfile,
+        toolchain: &Toolchain,
+        probe_folder: &Path,
+    ) -> Result<(), CompilerError> {
+        gcc::check_profile("clang", profile, toolchain, probe_folder)
+    }
+}
@@ -0,0 +1,13 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CompilerError {
+    #[error("the toolchain doesn't support {settings} of profile `{profile}`:\n{output}")]
+    UnsupportedSettings {
+        profile: String,
+        settings: String,
+        output: String,
+    },
+    #[error("Io error: {0}")]
+    IOError(#[from] std::io::Error),
+}
@@ -1,59 +1,303 @@
-use crate::manifest::PackageKind;
+use crate::{
+    manifest::{Lto, PackageKind, Strip},
+    profile::{BuildProfile, Pgo},
+    toolchain::Toolchain,
+};
 
-use super::Compiler;
-use std::{path::PathBuf, process::Command};
+use super::{errors::CompilerError, Compiler};
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+#[allow(clippy::upper_case_acronyms)]
 pub struct GCC;
 
 impl Compiler for GCC {
+    fn program(&self, toolchain: &Toolchain) -> String {
+        toolchain.compiler_program("gcc")
+    }
+
     fn compile_command(
         &self,
         package_path: PathBuf,
         source_path: PathBuf,
         output_path: PathBuf,
         package_info: &crate::manifest::Package,
+        profile: &BuildProfile,
+        toolchain: &Toolchain,
     ) -> Command {
-        let mut command = Command::new("gcc");
-        if package_info.disable_std_library {
-            command.arg("-no-std");
-        }
-        command.arg(source_path).args(["-c", "-o"]).arg(output_path);
-        command.args(&package_info.additional_compiler_flags);
-        let mut include_folder_absolute_path = package_path.clone();
-        include_folder_absolute_path.push(&package_info.include_folder);
-        command.arg(format!("-I{}", include_folder_absolute_path.display()));
-        command
+        compile_command(
+            "gcc",
+            package_path,
+            source_path,
+            output_path,
+            package_info,
+            profile,
+            toolchain,
+        )
     }
 
     fn link_command(
         &self,
-        _package_path: PathBuf,
+        package_path: PathBuf,
         object_files: Vec<PathBuf>,
         output_path: PathBuf,
         package_info: &crate::manifest::Package,
+        profile: &BuildProfile,
+        toolchain: &Toolchain,
     ) -> Command {
-        let mut command = Command::new("gcc");
-        command
-            .args(&object_files)
-            .args(package_info.additional_linker_flags.as_slice());
-        if package_info.disable_std_library {
-            command.arg("-no-std");
-        }
-        if package_info.enable_math_library {
-            command.arg("-lm");
+        link_command(
+            "gcc",
+            package_path,
+            object_files,
+            output_path,
+            package_info,
+            profile,
+            toolchain,
+        )
+    }
+
+    fn check_profile(
+        &self,
+        profile: &BuildProfile,
+        toolchain: &Toolchain,
+        probe_folder: &Path,
+    ) -> Result<(), CompilerError> {
+        check_profile("gcc", profile, toolchain, probe_folder)
+    }
+}
+
+/// Creates the compile command of a driver that accepts the options of GCC (`gcc` or `clang`)
+pub(super) fn compile_command(
+    driver: &str,
+    package_path: PathBuf,
+    source_path: PathBuf,
+    output_path: PathBuf,
+    package_info: &crate::manifest::Package,
+    profile: &BuildProfile,
+    toolchain: &Toolchain,
+) -> Command {
+    let mut command = Command::new(toolchain.compiler_program(driver));
+    if package_info.freestanding {
+        command.arg("-ffreestanding");
+    }
+    if package_info.kind == PackageKind::DynamicLibrary {
+        command.arg("-fPIC");
+    }
+    command.arg(source_path).args(["-c", "-o"]).arg(output_path);
+    command.args(toolchain.compiler_flags());
+    command.args(profile.flags());
+    command.args(lto_flag(driver, profile.lto));
+    command.args(profile.sanitizer_compiler_flags());
+    if profile.coverage {
+        // writes the `.gcno` files next to the objects
+        command.arg("--coverage");
+    }
+    match profile.pgo {
+        Some(Pgo::Generate) => {
+            // writes the `.gcda` profiles next to the objects
+            command.arg("-fprofile-generate");
+            if package_info.enable_pthread_library {
+                command.arg("-fprofile-update=atomic");
+            }
         }
-        if package_info.enable_pthread_library {
-            command.arg("-lpthread");
+        Some(Pgo::Use) => {
+            // code that the training didn't run is optimized as usual, and stale profiles
+            // are reported as warnings instead of failing the build
+            command.args([
+                "-fprofile-use",
+                "-fprofile-partial-training",
+                "-Wmissing-profile",
+                "-Wno-error=coverage-mismatch",
+            ]);
         }
-        match package_info.kind {
-            PackageKind::DynamicLibrary => {
-                command.arg("-shared");
-            }
-            PackageKind::StaticLibrary => {
-                command.arg("-static");
-            }
-            PackageKind::Executable => {}
+        None => {}
+    }
+    command.args(&package_info.additional_compiler_flags);
+    command.args(
+        package_info
+            .defines
+            .iter()
+            .map(|define| format!("-D{define}")),
+    );
+    let mut include_folder_absolute_path = package_path.clone();
+    include_folder_absolute_path.push(&package_info.include_folder);
+    command.arg(format!("-I{}", include_folder_absolute_path.display()));
+    command
+}
+
+/// Creates the link command of a driver that accepts the options of GCC (`gcc` or `clang`)
+pub(super) fn link_command(
+    driver: &str,
+    package_path: PathBuf,
+    object_files: Vec<PathBuf>,
+    output_path: PathBuf,
+    package_info: &crate::manifest::Package,
+    profile: &BuildProfile,
+    toolchain: &Toolchain,
+) -> Command {
+    if package_info.kind == PackageKind::StaticLibrary {
+        let mut command = Command::new(archiver_program(driver, profile, toolchain));
+        command.arg("rcs").arg(&output_path).args(&object_files);
+        return command;
+    }
+    let mut command = Command::new(toolchain.linker_program(driver));
+    command
+        .args(&object_files)
+        .args(toolchain.linker_flags())
+        .args(&profile.linker_flags)
+        .args(profile.sanitizer_flags())
+        .args(link_setting_flags(driver, profile))
+        .args(package_info.additional_linker_flags.as_slice());
+    if profile.coverage {
+        command.arg("--coverage");
+    }
+    if profile.pgo == Some(Pgo::Generate) {
+        command.arg("-fprofile-generate");
+    }
+    if package_info.freestanding {
+        command.arg("-nostdlib");
+    }
+    if let Some(linker_script) = &package_info.linker_script {
+        command.arg("-T").arg(package_path.join(linker_script));
+    }
+    if let Some(entry) = &package_info.entry {
+        command.arg(format!("-Wl,--entry={entry}"));
+    }
+    if package_info.map_file {
+        command.arg(format!(
+            "-Wl,-Map={}",
+            output_path.with_extension("map").display()
+        ));
+    }
+    if package_info.enable_math_library {
+        command.arg("-lm");
+    }
+    if package_info.enable_pthread_library {
+        command.arg("-lpthread");
+    }
+    match package_info.kind {
+        PackageKind::DynamicLibrary => {
+            command.arg("-shared").arg(format!(
+                "-Wl,-soname,lib{}.so.{}",
+                package_info.name, package_info.version.major
+            ));
         }
-        command.arg("-o").arg(&output_path);
-        command
+        PackageKind::StaticLibrary | PackageKind::Executable => {}
+    }
+    command.arg("-o").arg(&output_path);
+    command
+}
+
+/// The flag that enables the link-time optimization of the profile, passed to both the compiler
+/// and the linker
+///
+/// GCC runs the link-time optimization of a fat build in parallel with `-flto=auto`, and rejects
+/// `-flto=thin` since it has no ThinLTO
+fn lto_flag(driver: &str, lto: Lto) -> Option<&'static str> {
+    match (lto, driver) {
+        (Lto::Off, _) => None,
+        (Lto::Thin, _) => Some("-flto=thin"),
+        (Lto::Fat, "clang") => Some("-flto=full"),
+        (Lto::Fat, _) => Some("-flto=auto"),
+    }
+}
+
+/// The flags of the link-time settings of the profile passed to the linker
+/// (`lto`, `linker` and `strip`)
+fn link_setting_flags(driver: &str, profile: &BuildProfile) -> Vec<String> {
+    let mut flags: Vec<String> = lto_flag(driver, profile.lto)
+        .map(str::to_string)
+        .into_iter()
+        .collect();
+    if let Some(linker) = profile.linker {
+        flags.push(format!("-fuse-ld={}", linker.name()));
+    }
+    match profile.strip {
+        Strip::None => {}
+        Strip::Debuginfo => flags.push("-Wl,--strip-debug".to_string()),
+        Strip::Symbols => flags.push("-s".to_string()),
+    }
+    flags
+}
+
+/// The archiver of static libraries
+///
+/// The objects of link-time optimization contain the intermediate representation of the compiler,
+/// they are indexed by the archiver wrapper of the compiler which loads its plugin
+/// (`gcc-ar` or `llvm-ar`), unless the toolchain specifies an archiver
+fn archiver_program(driver: &str, profile: &BuildProfile, toolchain: &Toolchain) -> String {
+    match (profile.lto, &toolchain.archiver, driver) {
+        (Lto::Off, _, _) | (_, Some(_), _) => toolchain.archiver_program(),
+        (_, None, "clang") => toolchain.binutils_program("llvm-ar"),
+        (_, None, _) => toolchain.binutils_program("gcc-ar"),
+    }
+}
+
+/// Checks that a driver that accepts the options of GCC supports the link-time settings
+/// of the profile, by building a trivial program with them in `probe_folder`
+///
+/// Nothing is built when the profile uses the default settings
+pub(super) fn check_profile(
+    driver: &str,
+    profile: &BuildProfile,
+    toolchain: &Toolchain,
+    probe_folder: &Path,
+) -> Result<(), CompilerError> {
+    if !profile.has_link_settings() {
+        return Ok(());
+    }
+    std::fs::create_dir_all(probe_folder)?;
+    let source_path = probe_folder.join("probe.c");
+    let object_path = probe_folder.join("probe.o");
+    std::fs::write(&source_path, "int main(void) { return 0; }\n")?;
+    let mut compile = Command::new(toolchain.compiler_program(driver));
+    compile
+        .arg(&source_path)
+        .args(["-c", "-o"])
+        .arg(&object_path)
+        .args(toolchain.compiler_flags())
+        .args(profile.flags())
+        .args(lto_flag(driver, profile.lto));
+    let mut link = Command::new(toolchain.linker_program(driver));
+    link.arg(&object_path)
+        .args(toolchain.linker_flags())
+        .args(&profile.linker_flags)
+        .args(link_setting_flags(driver, profile))
+        .arg("-o")
+        .arg(probe_folder.join("probe"));
+    let mut commands = vec![compile, link];
+    if profile.lto != Lto::Off {
+        let mut archive = Command::new(archiver_program(driver, profile, toolchain));
+        archive
+            .arg("rcs")
+            .arg(probe_folder.join("libprobe.a"))
+            .arg(&object_path);
+        commands.push(archive);
+    }
+    let result = commands
+        .iter_mut()
+        .try_for_each(|command| probe(command, profile));
+    let _ = std::fs::remove_dir_all(probe_folder);
+    result
+}
+
+fn probe(command: &mut Command, profile: &BuildProfile) -> Result<(), CompilerError> {
+    let unsupported = |output: String| CompilerError::UnsupportedSettings {
+        profile: profile.name.clone(),
+        settings: profile.link_settings_description(),
+        output,
+    };
+    let output = command.output().map_err(|error| {
+        unsupported(format!(
+            "failed to run `{}`: {error}",
+            command.get_program().to_string_lossy()
+        ))
+    })?;
+    if !output.status.success() {
+        return Err(unsupported(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
     }
+    Ok(())
 }
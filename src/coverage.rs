@@ -0,0 +1,381 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::toolchain::Toolchain;
+
+pub mod errors;
+use errors::CoverageError;
+
+/// The output of `gcov --json-format` for an object file
+#[derive(Deserialize)]
+struct GcovOutput {
+    files: Vec<GcovFile>,
+}
+
+#[derive(Deserialize)]
+struct GcovFile {
+    file: PathBuf,
+    lines: Vec<GcovLine>,
+}
+
+#[derive(Deserialize)]
+struct GcovLine {
+    line_number: u32,
+    count: u64,
+    #[serde(default)]
+    branches: Vec<GcovBranch>,
+}
+
+#[derive(Deserialize)]
+struct GcovBranch {
+    count: u64,
+}
+
+/// The execution counts of the lines and branches of a source file
+#[derive(Default, Clone)]
+pub struct FileCoverage {
+    /// The number of times each executable line ran
+    pub lines: BTreeMap<u32, u64>,
+    /// The number of times each branch of each line was taken
+    pub branches: BTreeMap<u32, Vec<u64>>,
+}
+
+impl FileCoverage {
+    /// The number of executed lines and the number of executable lines
+    pub fn line_totals(&self) -> (usize, usize) {
+        let covered = self.lines.values().filter(|count| **count > 0).count();
+        (covered, self.lines.len())
+    }
+
+    /// The number of taken branches and the number of branches
+    pub fn branch_totals(&self) -> (usize, usize) {
+        let branches = self.branches.values().flatten();
+        let covered = branches.clone().filter(|count| **count > 0).count();
+        (covered, branches.count())
+    }
+
+    /// Adds the counts of another report of the same file
+    fn merge(&mut self, other: FileCoverage) {
+        for (line, count) in other.lines {
+            *self.lines.entry(line).or_default() += count;
+        }
+        for (line, counts) in other.branches {
+            let branches = self.branches.entry(line).or_default();
+            if branches.len() < counts.len() {
+                branches.resize(counts.len(), 0);
+            }
+            for (branch, count) in branches.iter_mut().zip(counts) {
+                *branch += count;
+            }
+        }
+    }
+}
+
+/// The coverage of the source files of a package, by absolute path
+#[derive(Default)]
+pub struct CoverageReport {
+    pub files: BTreeMap<PathBuf, FileCoverage>,
+}
+
+impl CoverageReport {
+    /// Collects the coverage of the object files from their `.gcno` and `.gcda` files
+    /// with the `gcov` of the toolchain
+    ///
+    /// Only the source files accepted by `include` are kept (e.g. the files of a package,
+    /// not the headers of its dependencies)
+    pub fn collect(
+        toolchain: &Toolchain,
+        object_files: &[PathBuf],
+        include: impl Fn(&Path) -> bool,
+    ) -> Result<Self, CoverageError> {
+        let mut report = CoverageReport::default();
+        for object_file in object_files {
+            let gcov = toolchain.binutils_program("gcov");
+            let output = Command::new(&gcov)
+                .args(["--json-format", "--stdout", "--branch-probabilities"])
+                .arg(object_file)
+                .output()
+                .map_err(|error| match error.kind() {
+                    ErrorKind::NotFound => CoverageError::GcovNotInstalled(gcov.clone()),
+                    _ => CoverageError::IOError(error),
+                })?;
+            if !output.status.success() {
+                return Err(CoverageError::GcovFailed {
+                    object_file: object_file.clone(),
+                    stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                });
+            }
+            let gcov_output: GcovOutput =
+                serde_json::from_slice(&output.stdout).map_err(|error| {
+                    CoverageError::InvalidGcovOutput {
+                        object_file: object_file.clone(),
+                        error,
+                    }
+                })?;
+            report.add_gcov_output(gcov_output, &include);
+        }
+        Ok(report)
+    }
+
+    /// Adds the counts of the files of a `gcov` output that are accepted by `include`
+    fn add_gcov_output(&mut self, gcov_output: GcovOutput, include: &impl Fn(&Path) -> bool) {
+        for file in gcov_output.files {
+            if !include(&file.file) {
+                continue;
+            }
+            let coverage = FileCoverage {
+                lines: file
+                    .lines
+                    .iter()
+                    .map(|line| (line.line_number, line.count))
+                    .collect(),
+                branches: file
+                    .lines
+                    .iter()
+                    .filter(|line| !line.branches.is_empty())
+                    .map(|line| {
+                        let counts = line.branches.iter().map(|b| b.count).collect();
+                        (line.line_number, counts)
+                    })
+                    .collect(),
+            };
+            self.files.entry(file.file).or_default().merge(coverage);
+        }
+    }
+
+    /// The number of executed lines and the number of executable lines of all the files
+    pub fn line_totals(&self) -> (usize, usize) {
+        sum_totals(self.files.values().map(FileCoverage::line_totals))
+    }
+
+    /// The number of taken branches and the number of branches of all the files
+    pub fn branch_totals(&self) -> (usize, usize) {
+        sum_totals(self.files.values().map(FileCoverage::branch_totals))
+    }
+
+    /// Adds the files of another report
+    pub fn extend(&mut self, other: CoverageReport) {
+        for (path, coverage) in other.files {
+            self.files.entry(path).or_default().merge(coverage);
+        }
+    }
+
+    /// Renders the report in the lcov tracefile format (`lcov.info`)
+    pub fn to_lcov(&self) -> String {
+        let mut lcov = String::new();
+        for (path, file) in &self.files {
+            let _ = writeln!(lcov, "TN:\nSF:{}", path.display());
+            for (line, counts) in &file.branches {
+                for (branch, count) in counts.iter().enumerate() {
+                    let taken = match count {
+                        0 if file.lines.get(line) == Some(&0) => "-".to_string(),
+                        count => count.to_string(),
+                    };
+                    let _ = writeln!(lcov, "BRDA:{line},0,{branch},{taken}");
+                }
+            }
+            let (branches_hit, branches_found) = file.branch_totals();
+            let _ = writeln!(lcov, "BRF:{branches_found}\nBRH:{branches_hit}");
+            for (line, count) in &file.lines {
+                let _ = writeln!(lcov, "DA:{line},{count}");
+            }
+            let (lines_hit, lines_found) = file.line_totals();
+            let _ = writeln!(lcov, "LF:{lines_found}\nLH:{lines_hit}\nend_of_record");
+        }
+        lcov
+    }
+
+    /// Writes an HTML report in `folder`: an `index.html` with the coverage of every file
+    /// and a page per file with its executed and missed lines highlighted
+    pub fn write_html(&self, folder: &Path, title: &str) -> Result<PathBuf, CoverageError> {
+        std::fs::create_dir_all(folder)?;
+        let mut rows = String::new();
+        for (i, (path, file)) in self.files.iter().enumerate() {
+            let page_name = format!("file{i}.html");
+            let (lines_hit, lines_found) = file.line_totals();
+            let (branches_hit, branches_found) = file.branch_totals();
+            let _ = writeln!(
+                rows,
+                "<tr><td><a href=\"{page_name}\">{}</a></td><td>{}</td><td>{}</td></tr>",
+                escape_html(&path.display().to_string()),
+                format_ratio(lines_hit, lines_found),
+                format_ratio(branches_hit, branches_found),
+            );
+            let source = std::fs::read_to_string(path).unwrap_or_default();
+            let mut lines = String::new();
+            for (number, text) in (1..).zip(source.lines()) {
+                let (class, count) = match file.lines.get(&number) {
+                    Some(0) => ("missed", "0".to_string()),
+                    Some(count) => ("hit", count.to_string()),
+                    None => ("", String::new()),
+                };
+                let _ = writeln!(
+                    lines,
+                    "<tr class=\"{class}\"><td>{number}</td><td>{count}</td><td><pre>{}</pre></td></tr>",
+                    escape_html(text)
+                );
+            }
+            std::fs::write(
+                folder.join(&page_name),
+                html_page(
+                    &path.display().to_string(),
+                    &format!(
+                        "<p><a href=\"index.html\">{}</a></p><table>{lines}</table>",
+                        escape_html(title)
+                    ),
+                ),
+            )?;
+        }
+        let (lines_hit, lines_found) = self.line_totals();
+        let (branches_hit, branches_found) = self.branch_totals();
+        let _ = writeln!(
+            rows,
+            "<tr><th>Total</th><th>{}</th><th>{}</th></tr>",
+            format_ratio(lines_hit, lines_found),
+            format_ratio(branches_hit, branches_found),
+        );
+        let index_path = folder.join("index.html");
+        std::fs::write(
+            &index_path,
+            html_page(
+                title,
+                &format!(
+                    "<table><tr><th>File</th><th>Lines</th><th>Branches</th></tr>{rows}</table>"
+                ),
+            ),
+        )?;
+        Ok(index_path)
+    }
+}
+
+/// The percentage of `covered` in `total`, 100% when there's nothing to cover
+pub fn percentage(covered: usize, total: usize) -> f64 {
+    if total == 0 {
+        return 100.0;
+    }
+    covered as f64 * 100.0 / total as f64
+}
+
+/// Formats a coverage ratio (e.g. `42/50 (84.0%)`)
+pub fn format_ratio(covered: usize, total: usize) -> String {
+    format!("{covered}/{total} ({:.1}%)", percentage(covered, total))
+}
+
+/// Removes the `.gcda` files of a folder, so the counts of a previous run aren't added to the next one
+pub fn remove_counters(folder: &Path) -> std::io::Result<()> {
+    for entry in WalkDir::new(folder).into_iter().filter_map(|e| e.ok()) {
+        if entry.path().extension().is_some_and(|ext| ext == "gcda") {
+            std::fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+fn sum_totals(totals: impl Iterator<Item = (usize, usize)>) -> (usize, usize) {
+    totals.fold((0, 0), |(covered, total), (file_covered, file_total)| {
+        (covered + file_covered, total + file_total)
+    })
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn html_page(title: &str, body: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>{title}</title>
+<style>
+body {{ font-family: sans-serif; }}
+table {{ border-collapse: collapse; }}
+td, th {{ padding: 0 0.5em; text-align: left; }}
+pre {{ margin: 0; }}
+tr.hit {{ background: #dfd; }}
+tr.missed {{ background: #fdd; }}
+</style>
+</head>
+<body>
+<h1>{title}</h1>
+{body}
+</body>
+</html>
+"#,
+        title = escape_html(title)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The output of `gcov --json-format` for `fixtures/sign.c`, run without arguments
+    /// (positive values only) and with an argument (negative values only)
+    const POSITIVE_RUN: &str = include_str!("coverage/fixtures/sign-positive.json");
+    const NEGATIVE_RUN: &str = include_str!("coverage/fixtures/sign-negative.json");
+    const SOURCE_PATH: &str = "/home/user/app/src/sign.c";
+
+    fn report(gcov_outputs: &[&str]) -> CoverageReport {
+        let mut report = CoverageReport::default();
+        for gcov_output in gcov_outputs {
+            report.add_gcov_output(serde_json::from_str(gcov_output).unwrap(), &|_| true);
+        }
+        report
+    }
+
+    #[test]
+    fn lines_and_branches_are_read_from_the_gcov_output() {
+        let report = report(&[POSITIVE_RUN]);
+        let file = &report.files[Path::new(SOURCE_PATH)];
+        assert_eq!(file.lines.get(&2), Some(&3));
+        assert_eq!(file.lines.get(&5), Some(&0));
+        // lines without code aren't executable
+        assert_eq!(file.lines.get(&4), None);
+        assert_eq!(file.branches.get(&14), Some(&vec![0, 3]));
+        assert_eq!(report.line_totals(), (8, 11));
+        assert_eq!(report.branch_totals(), (4, 8));
+    }
+
+    #[test]
+    fn counts_of_the_same_file_are_added() {
+        let report = report(&[POSITIVE_RUN, NEGATIVE_RUN]);
+        let file = &report.files[Path::new(SOURCE_PATH)];
+        assert_eq!(file.lines.get(&13), Some(&8));
+        assert_eq!(file.branches.get(&2), Some(&vec![3, 3]));
+        assert_eq!(report.line_totals(), (10, 11));
+        assert_eq!(report.branch_totals(), (7, 8));
+    }
+
+    #[test]
+    fn excluded_files_are_ignored() {
+        let mut report = CoverageReport::default();
+        report.add_gcov_output(serde_json::from_str(POSITIVE_RUN).unwrap(), &|path| {
+            path.starts_with("/home/user/lib")
+        });
+        assert!(report.files.is_empty());
+        assert_eq!(report.line_totals(), (0, 0));
+    }
+
+    #[test]
+    fn branches_of_unexecuted_lines_are_not_taken_in_lcov() {
+        let lcov = report(&[POSITIVE_RUN]).to_lcov();
+        assert!(lcov.starts_with(&format!("TN:\nSF:{SOURCE_PATH}\n")));
+        assert!(lcov.contains("BRDA:2,0,0,3\nBRDA:2,0,1,0\n"));
+        assert!(lcov.contains("BRDA:5,0,0,-\nBRDA:5,0,1,-\n"));
+        assert!(lcov.contains("BRF:8\nBRH:4\n"));
+        assert!(lcov.ends_with("LF:11\nLH:8\nend_of_record\n"));
+    }
+}
@@ -0,0 +1,21 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CoverageError {
+    #[error("`{0}` is not installed")]
+    GcovNotInstalled(String),
+    #[error("`gcov` failed for {object_file:?}: {stderr}")]
+    GcovFailed {
+        object_file: PathBuf,
+        stderr: String,
+    },
+    #[error("invalid `gcov` output for {object_file:?}: {error}")]
+    InvalidGcovOutput {
+        object_file: PathBuf,
+        error: serde_json::Error,
+    },
+    #[error("Io error: {0}")]
+    IOError(#[from] std::io::Error),
+}
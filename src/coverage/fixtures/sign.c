@@ -0,0 +1,17 @@
+int sign(int value) {
+    if (value > 0) {
+        return 1;
+    }
+    if (value < 0) {
+        return -1;
+    }
+    return 0;
+}
+
+int main(int argc, char **argv) {
+    int total = 0;
+    for (int i = 1; i <= 3; i++) {
+        total += sign(argc > 1 ? -i : i);
+    }
+    return total == 0;
+}
@@ -0,0 +1,1237 @@
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    git::{GitCache, GitReference},
+    lockfile::{LockedPackage, Lockfile},
+    manifest::{Dependency, DetailedDependency, Manifest},
+    registry::{IndexEntry, IndexTargetEntry, Registry, RegistryIndex},
+    target::TargetTriple,
+    vendor::VendorDirectory,
+};
+
+pub mod errors;
+use errors::DependencyError;
+
+/// Maximum number of times the registry requirements are re-evaluated before giving up
+const MAX_RESOLVE_ITERATIONS: usize = 100;
+
+/// Where a resolved package comes from
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum PackageSource {
+    /// A member of the workspace
+    Workspace,
+    /// A local folder referenced with `path = "..."`
+    Path,
+    /// A package of the registry
+    Registry { checksum: String },
+    /// A commit of a git repository
+    Git {
+        url: String,
+        reference: GitReference,
+        commit: String,
+    },
+}
+
+impl PackageSource {
+    /// The identifier of the source written in the lockfile, `None` for local packages
+    ///
+    /// Git packages are written as `git+<url>[?<reference>]#<commit>`
+    pub fn lockfile_source(&self) -> Option<String> {
+        match self {
+            PackageSource::Workspace | PackageSource::Path => None,
+            PackageSource::Registry { .. } => Some("registry".to_string()),
+            PackageSource::Git {
+                url,
+                reference,
+                commit,
+            } => Some(format!("{}{commit}", git_source_prefix(url, reference))),
+        }
+    }
+}
+
+/// The beginning of the lockfile source of a git package, up to the commit
+fn git_source_prefix(url: &str, reference: &GitReference) -> String {
+    match reference.query() {
+        Some(query) => format!("git+{url}?{query}#"),
+        None => format!("git+{url}#"),
+    }
+}
+
+/// A package of the dependency graph
+pub struct ResolvedPackage {
+    pub name: String,
+    pub version: semver::Version,
+    pub source: PackageSource,
+    /// The folder that contains the `cpm.toml` of the package,
+    /// or the sources of an external package
+    pub path: PathBuf,
+    /// Names of the direct dependencies of this package
+    pub dependencies: Vec<String>,
+    /// Names of the dev-dependencies of this package, only resolved for the workspace members
+    pub dev_dependencies: Vec<String>,
+    /// Names of the build-dependencies of this package
+    pub build_dependencies: Vec<String>,
+    /// Names of the direct dependencies of this package when it's built for the host,
+    /// only set when cross-compiling since the `[target.<key>]` sections of the host apply
+    pub host_dependencies: Vec<String>,
+    /// Names of the build-dependencies of this package when it's built for the host,
+    /// only set when cross-compiling
+    pub host_build_dependencies: Vec<String>,
+    /// The build settings of a package that isn't a cpm package, see [`crate::external`]
+    pub external: Option<DetailedDependency>,
+    /// Whether this package comes from the `[patch]` section of the workspace manifest
+    pub patched: bool,
+}
+
+impl ResolvedPackage {
+    /// Names of the dependencies, build-dependencies and dev-dependencies of this package,
+    /// for the target and for the host
+    pub fn all_dependencies(&self) -> impl Iterator<Item = &String> {
+        self.dependencies
+            .iter()
+            .chain(&self.build_dependencies)
+            .chain(&self.dev_dependencies)
+            .chain(&self.host_dependencies)
+            .chain(&self.host_build_dependencies)
+    }
+
+    /// Names of the direct dependencies of this package when it's built for the host
+    /// or for the target
+    pub fn platform_dependencies(&self, for_host: bool) -> &[String] {
+        match for_host {
+            true => &self.host_dependencies,
+            false => &self.dependencies,
+        }
+    }
+
+    /// Names of the build-dependencies of this package when it's built for the host
+    /// or for the target
+    pub fn platform_build_dependencies(&self, for_host: bool) -> &[String] {
+        match for_host {
+            true => &self.host_build_dependencies,
+            false => &self.build_dependencies,
+        }
+    }
+
+    /// A package that isn't a cpm package, built with the settings of its dependency
+    fn external(
+        name: &str,
+        source: PackageSource,
+        path: PathBuf,
+        settings: &DetailedDependency,
+        patched: bool,
+    ) -> Self {
+        Self {
+            name: name.to_string(),
+            version: semver::Version::new(0, 0, 0),
+            source,
+            path,
+            dependencies: vec![],
+            dev_dependencies: vec![],
+            build_dependencies: vec![],
+            host_dependencies: vec![],
+            host_build_dependencies: vec![],
+            external: Some(settings.clone()),
+            patched,
+        }
+    }
+}
+
+/// The resolved dependency graph of a workspace
+///
+/// There's only one version of each package in the graph, since C doesn't support
+/// linking two versions of the same library
+pub struct Resolve {
+    pub packages: BTreeMap<String, ResolvedPackage>,
+    /// The target the graph was resolved for, which selects the `[target.<key>]` dependencies
+    pub target: TargetTriple,
+    /// Names of the patches that don't replace any dependency of the graph
+    pub unused_patches: Vec<String>,
+}
+
+/// A version requirement on a registry package and the package that requires it
+struct Requirement {
+    name: String,
+    requirement: semver::VersionReq,
+    required_by: String,
+    /// Whether the package is required on the host (e.g. by a build-dependency)
+    for_host: bool,
+}
+
+/// A registry version chosen for the graph, with its dependencies on the platforms that need it
+struct SelectedVersion {
+    entry: IndexEntry,
+    /// The dependencies of the version built for the target, `None` when only the host needs it
+    target: Option<IndexTargetEntry>,
+    /// The dependencies of the version built for the host, only set when cross-compiling
+    host: Option<IndexTargetEntry>,
+}
+
+impl Resolve {
+    /// Resolves the dependencies of the workspace members
+    ///
+    /// Path dependencies are loaded from disk and registry dependencies are resolved
+    /// against the registry, preferring the versions pinned in the lockfile when they
+    /// still satisfy the requirements.
+    /// When `vendor` is set, registry and git dependencies are taken from the vendor folder instead.
+    ///
+    /// `patches` replace the registry and git dependencies of the same name in the whole graph,
+    /// their paths must be absolute.
+    /// The dependencies of the `[target.<key>]` sections that match `target` are included,
+    /// except for the build-dependencies and the packages they depend on, which run on the host
+    /// and get the `[target.<key>]` sections of the host
+    pub fn new(
+        member_paths: &[PathBuf],
+        patches: &BTreeMap<String, Dependency>,
+        target: &TargetTriple,
+        registry: &Registry,
+        git_cache: &GitCache,
+        vendor: Option<&VendorDirectory>,
+        lockfile: Option<&Lockfile>,
+    ) -> Result<Self, DependencyError> {
+        // the host only differs from the target when cross-compiling
+        let host = Some(TargetTriple::host()).filter(|host| !host.is_same_target(target));
+        let mut packages: BTreeMap<String, ResolvedPackage> = BTreeMap::new();
+        let mut requirements = vec![];
+        // the folder of a package, its source, whether it's a patch and whether it's for the host
+        let mut queue: VecDeque<(PathBuf, PackageSource, bool, bool)> = member_paths
+            .iter()
+            .map(|p| {
+                (
+                    normalize_package_path(p),
+                    PackageSource::Workspace,
+                    false,
+                    false,
+                )
+            })
+            .collect();
+        let mut patch_paths = BTreeMap::new();
+        for (name, patch) in patches {
+            let (patch_path, patch_source) =
+                fetch_dependency(name, patch, Path::new(""), git_cache, vendor, lockfile)?
+                    .ok_or_else(|| DependencyError::PatchWithoutSource(name.clone()))?;
+            if let Some(settings) = patch.external_build() {
+                packages.insert(
+                    name.clone(),
+                    ResolvedPackage::external(name, patch_source, patch_path, settings, true),
+                );
+                continue;
+            }
+            let patch_manifest = load_package_manifest(&patch_path, target)?;
+            check_local_version(
+                name,
+                &patch_path,
+                &patch_manifest,
+                &patch.version_requirement(),
+            )?;
+            patch_paths.insert(name.clone(), (patch_path.clone(), patch_source.clone()));
+            queue.push_back((patch_path, patch_source, true, false));
+        }
+        let mut visited_paths = HashSet::new();
+        while let Some((package_path, source, patched, for_host)) = queue.pop_front() {
+            if !visited_paths.insert((package_path.clone(), for_host)) {
+                continue;
+            }
+            let package_target = host.as_ref().filter(|_| for_host).unwrap_or(target);
+            let manifest = load_package_manifest(&package_path, package_target)?;
+            let package = manifest
+                .package
+                .as_ref()
+                .ok_or_else(|| DependencyError::PackageNotFound(package_path.clone()))?;
+            if let Some(existing) = packages.get(&package.name) {
+                // a package built for both the target and the host is visited twice
+                if existing.path != package_path {
+                    return Err(DependencyError::DuplicatePackage {
+                        name: package.name.clone(),
+                        first_path: existing.path.clone(),
+                        second_path: package_path,
+                    });
+                }
+            }
+            let is_member = source == PackageSource::Workspace;
+            for (name, dependency, is_build_dependency) in
+                manifest_dependencies(&manifest, is_member && !for_host)
+            {
+                let dependency_for_host = for_host || (is_build_dependency && host.is_some());
+                let fetched = if patches.contains_key(name) && dependency.path().is_none() {
+                    // the patch is checked against the requirement like a registry package
+                    if let Some((patch_path, patch_source)) = patch_paths.get(name) {
+                        queue.push_back((
+                            patch_path.clone(),
+                            patch_source.clone(),
+                            true,
+                            dependency_for_host,
+                        ));
+                    }
+                    None
+                } else {
+                    fetch_dependency(name, dependency, &package_path, git_cache, vendor, lockfile)?
+                };
+                let Some((dependency_path, dependency_source)) = fetched else {
+                    requirements.push(Requirement {
+                        name: name.clone(),
+                        requirement: dependency.version_requirement(),
+                        required_by: package.name.clone(),
+                        for_host: dependency_for_host,
+                    });
+                    continue;
+                };
+                if let Some(settings) = dependency.external_build() {
+                    if let Some(existing) = packages.get(name) {
+                        if existing.path != dependency_path {
+                            return Err(DependencyError::DuplicatePackage {
+                                name: name.clone(),
+                                first_path: existing.path.clone(),
+                                second_path: dependency_path,
+                            });
+                        }
+                        continue;
+                    }
+                    packages.insert(
+                        name.clone(),
+                        ResolvedPackage::external(
+                            name,
+                            dependency_source,
+                            dependency_path,
+                            settings,
+                            false,
+                        ),
+                    );
+                    continue;
+                }
+                let dependency_target = host.as_ref().filter(|_| dependency_for_host);
+                let dependency_manifest =
+                    load_package_manifest(&dependency_path, dependency_target.unwrap_or(target))?;
+                check_local_version(
+                    name,
+                    &dependency_path,
+                    &dependency_manifest,
+                    &dependency.version_requirement(),
+                )?;
+                queue.push_back((
+                    dependency_path,
+                    dependency_source,
+                    false,
+                    dependency_for_host,
+                ));
+            }
+            let resolved =
+                packages
+                    .entry(package.name.clone())
+                    .or_insert_with(|| ResolvedPackage {
+                        name: package.name.clone(),
+                        version: package.version.clone(),
+                        source,
+                        path: package_path,
+                        dependencies: vec![],
+                        dev_dependencies: vec![],
+                        build_dependencies: vec![],
+                        host_dependencies: vec![],
+                        host_build_dependencies: vec![],
+                        external: None,
+                        patched,
+                    });
+            let dependencies = manifest.dependencies.keys().cloned().collect();
+            let build_dependencies = manifest.build_dependencies.keys().cloned().collect();
+            if for_host {
+                resolved.host_dependencies = dependencies;
+                resolved.host_build_dependencies = build_dependencies;
+            } else {
+                resolved.dependencies = dependencies;
+                resolved.build_dependencies = build_dependencies;
+                if is_member {
+                    resolved.dev_dependencies = manifest.dev_dependencies.keys().cloned().collect();
+                }
+            }
+        }
+
+        let selected = select_registry_versions(
+            &packages,
+            requirements,
+            target,
+            host.as_ref(),
+            registry,
+            vendor,
+            lockfile,
+        )?;
+        for (name, selected) in selected {
+            let entry = selected.entry;
+            if let Some(locked) = lockfile.and_then(|l| l.package(&name)) {
+                if locked.version == entry.version
+                    && locked
+                        .checksum
+                        .as_ref()
+                        .is_some_and(|c| c != &entry.checksum)
+                {
+                    return Err(DependencyError::LockedChecksumMismatch {
+                        name,
+                        version: entry.version,
+                    });
+                }
+            }
+            let path = match vendor {
+                Some(vendor) => vendor.package_path(&name),
+                None => registry.unpack(&name, &entry.version, &entry.checksum)?,
+            };
+            let target_dependencies = selected.target.unwrap_or_default();
+            let host_dependencies = selected.host.unwrap_or_default();
+            packages.insert(
+                name.clone(),
+                ResolvedPackage {
+                    name,
+                    version: entry.version,
+                    source: PackageSource::Registry {
+                        checksum: entry.checksum,
+                    },
+                    path,
+                    dependencies: target_dependencies.dependencies.into_keys().collect(),
+                    dev_dependencies: vec![],
+                    build_dependencies: target_dependencies
+                        .build_dependencies
+                        .into_keys()
+                        .collect(),
+                    host_dependencies: host_dependencies.dependencies.into_keys().collect(),
+                    host_build_dependencies: host_dependencies
+                        .build_dependencies
+                        .into_keys()
+                        .collect(),
+                    external: None,
+                    patched: false,
+                },
+            );
+        }
+        let mut resolve = Self {
+            packages,
+            target: target.clone(),
+            unused_patches: vec![],
+        };
+        resolve.remove_unused_packages();
+        Ok(resolve)
+    }
+
+    /// Removes the packages that no workspace member depends on, which are the patches
+    /// that don't replace any dependency and the dependencies of these patches
+    fn remove_unused_packages(&mut self) {
+        let mut used = HashSet::new();
+        let mut pending: Vec<&str> = self
+            .packages
+            .values()
+            .filter(|p| p.source == PackageSource::Workspace)
+            .map(|p| p.name.as_str())
+            .collect();
+        while let Some(name) = pending.pop() {
+            if !used.insert(name.to_string()) {
+                continue;
+            }
+            if let Some(package) = self.packages.get(name) {
+                pending.extend(package.all_dependencies().map(String::as_str));
+            }
+        }
+        let unused: Vec<String> = self
+            .packages
+            .keys()
+            .filter(|name| !used.contains(*name))
+            .cloned()
+            .collect();
+        for name in unused {
+            if let Some(package) = self.packages.remove(&name) {
+                if package.patched {
+                    self.unused_patches.push(name);
+                }
+            }
+        }
+    }
+
+    /// Creates the lockfile that pins the packages of this graph
+    pub fn lockfile(&self) -> Lockfile {
+        let packages = self
+            .packages
+            .values()
+            .filter_map(|package| {
+                // the dependencies on the target and on the host
+                let mut dependencies = package.dependencies.clone();
+                for dependency in &package.host_dependencies {
+                    if !dependencies.contains(dependency) {
+                        dependencies.push(dependency.clone());
+                    }
+                }
+                Some(LockedPackage {
+                    name: package.name.clone(),
+                    version: package.version.clone(),
+                    source: package.source.lockfile_source()?,
+                    checksum: match &package.source {
+                        PackageSource::Registry { checksum } => Some(checksum.clone()),
+                        _ => None,
+                    },
+                    dependencies,
+                })
+            })
+            .collect();
+        Lockfile {
+            packages,
+            ..Default::default()
+        }
+    }
+
+    /// Collects the version requirements on every dependency of the graph,
+    /// with the names of the packages that require them
+    pub fn dependency_requirements(
+        &self,
+    ) -> Result<BTreeMap<String, Vec<(String, semver::VersionReq)>>, DependencyError> {
+        let mut requirements: BTreeMap<String, Vec<(String, semver::VersionReq)>> = BTreeMap::new();
+        let platform_targets = self.platform_targets();
+        for package in self.packages.values().filter(|p| p.external.is_none()) {
+            let mut package_requirements = vec![];
+            for target in &platform_targets {
+                let manifest = load_package_manifest(&package.path, target)?;
+                let is_member = package.source == PackageSource::Workspace;
+                for (name, dependency, _) in manifest_dependencies(&manifest, is_member) {
+                    let requirement = (name.clone(), dependency.version_requirement());
+                    if !package_requirements.contains(&requirement) {
+                        package_requirements.push(requirement);
+                    }
+                }
+            }
+            for (name, requirement) in package_requirements {
+                requirements
+                    .entry(name)
+                    .or_default()
+                    .push((package.name.clone(), requirement));
+            }
+        }
+        Ok(requirements)
+    }
+
+    /// Whether the graph was resolved for another target than the host, the build-dependencies
+    /// and the packages they depend on are then built for the host
+    pub fn cross_compiling(&self) -> bool {
+        !self.target.is_same_target(&TargetTriple::host())
+    }
+
+    /// The targets the packages are built for, which includes the host when cross-compiling
+    fn platform_targets(&self) -> Vec<TargetTriple> {
+        match self.cross_compiling() {
+            true => vec![self.target.clone(), TargetTriple::host()],
+            false => vec![self.target.clone()],
+        }
+    }
+
+    /// Returns the packages needed to build `roots` in build order (dependencies
+    /// and build-dependencies first), with whether they're built for the host
+    ///
+    /// A package needed by both the target and the host is listed twice when cross-compiling
+    pub fn build_order(
+        &self,
+        roots: &[String],
+    ) -> Result<Vec<(&ResolvedPackage, bool)>, DependencyError> {
+        self.dependency_order(roots, false, true)
+    }
+
+    /// Sorts `roots` and their dependencies so every package comes after its dependencies,
+    /// following the build-dependencies too when `with_build_dependencies` is set
+    ///
+    /// `roots` are built for the host when `for_host` is set
+    fn dependency_order(
+        &self,
+        roots: &[String],
+        for_host: bool,
+        with_build_dependencies: bool,
+    ) -> Result<Vec<(&ResolvedPackage, bool)>, DependencyError> {
+        let mut order = vec![];
+        let mut done = HashSet::new();
+        let mut visiting = vec![];
+        for root in roots {
+            self.visit(
+                (root, for_host),
+                with_build_dependencies,
+                &mut visiting,
+                &mut done,
+                &mut order,
+            )?;
+        }
+        Ok(order)
+    }
+
+    fn visit<'a>(
+        &'a self,
+        (name, for_host): (&str, bool),
+        with_build_dependencies: bool,
+        visiting: &mut Vec<(String, bool)>,
+        done: &mut HashSet<(String, bool)>,
+        order: &mut Vec<(&'a ResolvedPackage, bool)>,
+    ) -> Result<(), DependencyError> {
+        let key = (name.to_string(), for_host);
+        if done.contains(&key) {
+            return Ok(());
+        }
+        if visiting.contains(&key) {
+            visiting.push(key);
+            let cycle: Vec<&str> = visiting.iter().map(|(name, _)| name.as_str()).collect();
+            return Err(DependencyError::Cycle(cycle.join(" -> ")));
+        }
+        let package = self
+            .packages
+            .get(name)
+            .ok_or_else(|| DependencyError::UnknownPackage(name.to_string()))?;
+        visiting.push(key.clone());
+        for dependency in package.platform_dependencies(for_host) {
+            self.visit(
+                (dependency, for_host),
+                with_build_dependencies,
+                visiting,
+                done,
+                order,
+            )?;
+        }
+        if with_build_dependencies {
+            // build-dependencies run during the build, so they're built for the host
+            let build_for_host = for_host || self.cross_compiling();
+            for dependency in package.platform_build_dependencies(for_host) {
+                self.visit(
+                    (dependency, build_for_host),
+                    with_build_dependencies,
+                    visiting,
+                    done,
+                    order,
+                )?;
+            }
+        }
+        visiting.pop();
+        done.insert(key);
+        order.push((package, for_host));
+        Ok(())
+    }
+
+    /// Returns all the dependencies of a package in link order
+    /// (every package comes before the packages it depends on),
+    /// when it's built for the host or for the target
+    ///
+    /// Build-dependencies aren't linked, so they're not included
+    pub fn transitive_dependencies(
+        &self,
+        name: &str,
+        for_host: bool,
+    ) -> Result<Vec<&ResolvedPackage>, DependencyError> {
+        let mut order = self.dependency_order(&[name.to_string()], for_host, false)?;
+        order.pop();
+        order.reverse();
+        Ok(order.into_iter().map(|(package, _)| package).collect())
+    }
+}
+
+/// Finds the folder and the source of a path or git dependency, checking out git dependencies
+///
+/// Returns `None` for registry dependencies, path dependencies are relative to `package_path`
+fn fetch_dependency(
+    name: &str,
+    dependency: &Dependency,
+    package_path: &Path,
+    git_cache: &GitCache,
+    vendor: Option<&VendorDirectory>,
+    lockfile: Option<&Lockfile>,
+) -> Result<Option<(PathBuf, PackageSource)>, DependencyError> {
+    if let Some(path) = dependency.path() {
+        let dependency_path = normalize_package_path(&package_path.join(path));
+        return Ok(Some((dependency_path, PackageSource::Path)));
+    }
+    let Some((url, reference)) = dependency.git() else {
+        return match dependency.external_build() {
+            Some(_) => Err(DependencyError::ExternalWithoutSource(name.to_string())),
+            None => Ok(None),
+        };
+    };
+    let source_prefix = git_source_prefix(url, &reference);
+    let (commit, checkout_path) = match vendor {
+        Some(vendor) => vendor.git_package(name, &source_prefix)?,
+        None => {
+            let locked_commit = lockfile
+                .and_then(|l| l.package(name))
+                .and_then(|p| p.source.strip_prefix(&source_prefix));
+            git_cache.checkout_reference(url, &reference, locked_commit)?
+        }
+    };
+    let source = PackageSource::Git {
+        url: url.to_string(),
+        reference,
+        commit,
+    };
+    Ok(Some((checkout_path, source)))
+}
+
+/// The canonical path of a package folder, so the same folder is recognized however it's
+/// written (e.g. `/ws/lib` and `/ws/app/../lib`)
+///
+/// Folders that don't exist are kept as they are, loading their manifest reports them
+fn normalize_package_path(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// The dependencies and build-dependencies of a manifest, with whether they're build-dependencies,
+/// and the dev-dependencies when `include_dev` is set
+fn manifest_dependencies(
+    manifest: &Manifest,
+    include_dev: bool,
+) -> impl Iterator<Item = (&String, &Dependency, bool)> {
+    let dev_dependencies = manifest
+        .dev_dependencies
+        .iter()
+        .filter(move |_| include_dev);
+    let build_dependencies = manifest
+        .build_dependencies
+        .iter()
+        .map(|(name, dependency)| (name, dependency, true));
+    manifest
+        .dependencies
+        .iter()
+        .chain(dev_dependencies)
+        .map(|(name, dependency)| (name, dependency, false))
+        .chain(build_dependencies)
+}
+
+/// Loads the manifest of a package with the `[target.<key>]` sections of `target` applied
+fn load_package_manifest(
+    package_path: &Path,
+    target: &TargetTriple,
+) -> Result<Manifest, DependencyError> {
+    let manifest = Manifest::load_manifest_from_project_path(package_path).map_err(|error| {
+        DependencyError::InvalidManifest {
+            path: package_path.to_path_buf(),
+            error,
+        }
+    })?;
+    Ok(manifest.for_target(target)?)
+}
+
+fn check_local_version(
+    name: &str,
+    path: &Path,
+    manifest: &Manifest,
+    requirement: &semver::VersionReq,
+) -> Result<(), DependencyError> {
+    let package = manifest
+        .package
+        .as_ref()
+        .ok_or_else(|| DependencyError::PackageNotFound(path.to_path_buf()))?;
+    if package.name != *name {
+        return Err(DependencyError::NameMismatch {
+            name: name.to_string(),
+            found: package.name.clone(),
+            path: path.to_path_buf(),
+        });
+    }
+    if !requirement.matches(&package.version) {
+        return Err(DependencyError::NoMatchingVersion {
+            name: name.to_string(),
+            requirements: format!("{requirement} (found {} at {path:?})", package.version),
+        });
+    }
+    Ok(())
+}
+
+/// Chooses a version for every registry package required by the graph
+///
+/// The highest version that satisfies every requirement is chosen, unless the locked version
+/// still satisfies them. The dependencies of the chosen entries are the ones of `target`,
+/// and the ones of `host` for the packages required on the host when cross-compiling.
+/// Since the dependencies of the chosen versions add more requirements,
+/// the selection is repeated until it doesn't change anymore.
+fn select_registry_versions(
+    local_packages: &BTreeMap<String, ResolvedPackage>,
+    root_requirements: Vec<Requirement>,
+    target: &TargetTriple,
+    host: Option<&TargetTriple>,
+    registry: &Registry,
+    vendor: Option<&VendorDirectory>,
+    lockfile: Option<&Lockfile>,
+) -> Result<BTreeMap<String, SelectedVersion>, DependencyError> {
+    let mut selected: BTreeMap<String, SelectedVersion> = BTreeMap::new();
+    for _ in 0..MAX_RESOLVE_ITERATIONS {
+        let mut requirements: BTreeMap<&str, Vec<&Requirement>> = BTreeMap::new();
+        let transitive_requirements: Vec<Requirement> = selected
+            .iter()
+            .flat_map(|(required_by, version)| {
+                let target_requirements = version
+                    .target
+                    .iter()
+                    .flat_map(|d| platform_requirements(required_by, d, false, host.is_some()));
+                let host_requirements = version
+                    .host
+                    .iter()
+                    .flat_map(|d| platform_requirements(required_by, d, true, true));
+                target_requirements.chain(host_requirements)
+            })
+            .collect();
+        for requirement in root_requirements.iter().chain(&transitive_requirements) {
+            requirements
+                .entry(&requirement.name)
+                .or_default()
+                .push(requirement);
+        }
+        let mut new_selected = BTreeMap::new();
+        for (name, requirements) in requirements {
+            if let Some(local) = local_packages.get(name) {
+                if let Some(r) = requirements
+                    .iter()
+                    .find(|r| !r.requirement.matches(&local.version))
+                {
+                    return Err(DependencyError::NoMatchingVersion {
+                        name: name.to_string(),
+                        requirements: format!(
+                            "{} required by `{}` (found local package {} at {:?})",
+                            r.requirement, r.required_by, local.version, local.path
+                        ),
+                    });
+                }
+                continue;
+            }
+            let locked_version = lockfile
+                .and_then(|l| l.package(name))
+                .map(|p| p.version.clone());
+            let index: RegistryIndex = match vendor {
+                Some(vendor) => vendor.index(name)?,
+                None => registry.index(name)?,
+            };
+            let candidates: Vec<IndexEntry> = index
+                .versions
+                .into_iter()
+                .filter(|entry| !entry.yanked || Some(&entry.version) == locked_version.as_ref())
+                .filter(|entry| {
+                    requirements
+                        .iter()
+                        .all(|r| r.requirement.matches(&entry.version))
+                })
+                .collect();
+            let chosen = candidates
+                .iter()
+                .find(|entry| Some(&entry.version) == locked_version.as_ref())
+                .or_else(|| candidates.iter().max_by(|a, b| a.version.cmp(&b.version)))
+                .cloned()
+                .ok_or_else(|| DependencyError::NoMatchingVersion {
+                    name: name.to_string(),
+                    requirements: requirements
+                        .iter()
+                        .map(|r| format!("{} required by `{}`", r.requirement, r.required_by))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                })?;
+            let target_dependencies = match requirements.iter().any(|r| !r.for_host) {
+                true => Some(chosen.for_target(target)?),
+                false => None,
+            };
+            let host_dependencies = match host {
+                Some(host) if requirements.iter().any(|r| r.for_host) => {
+                    Some(chosen.for_target(host)?)
+                }
+                _ => None,
+            };
+            new_selected.insert(
+                name.to_string(),
+                SelectedVersion {
+                    entry: chosen,
+                    target: target_dependencies,
+                    host: host_dependencies,
+                },
+            );
+        }
+        let unchanged = new_selected.len() == selected.len()
+            && new_selected.iter().all(|(name, version)| {
+                selected.get(name).is_some_and(|s| {
+                    s.entry.version == version.entry.version
+                        && s.target.is_some() == version.target.is_some()
+                        && s.host.is_some() == version.host.is_some()
+                })
+            });
+        selected = new_selected;
+        if unchanged {
+            return Ok(selected);
+        }
+    }
+    Err(DependencyError::ResolutionDidNotConverge)
+}
+
+/// The requirements of the dependencies of a registry version built for the host or the target,
+/// build-dependencies are required on the host when cross-compiling
+fn platform_requirements(
+    required_by: &str,
+    dependencies: &IndexTargetEntry,
+    for_host: bool,
+    cross_compiling: bool,
+) -> Vec<Requirement> {
+    let build_dependencies = dependencies
+        .build_dependencies
+        .iter()
+        .map(|(name, requirement)| (name, requirement, for_host || cross_compiling));
+    dependencies
+        .dependencies
+        .iter()
+        .map(|(name, requirement)| (name, requirement, for_host))
+        .chain(build_dependencies)
+        .map(|(name, requirement, for_host)| Requirement {
+            name: name.clone(),
+            requirement: requirement.clone(),
+            required_by: required_by.to_string(),
+            for_host,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A temporary workspace folder, removed when dropped
+    struct TemporaryWorkspace(PathBuf);
+
+    impl TemporaryWorkspace {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("cpm-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path.canonicalize().unwrap())
+        }
+
+        /// Writes the manifest of a package in `folder`, with `dependencies` as its
+        /// `[dependencies]` section
+        fn add_package(&self, folder: &str, name: &str, dependencies: &str) -> PathBuf {
+            let package_path = self.0.join(folder);
+            std::fs::create_dir_all(&package_path).unwrap();
+            std::fs::write(
+                package_path.join("cpm.toml"),
+                format!(
+                    "[package]\nname = \"{name}\"\nversion = \"1.0.0\"\n\n[dependencies]\n{dependencies}"
+                ),
+            )
+            .unwrap();
+            package_path
+        }
+
+        /// Appends sections to the manifest of the package in `folder`
+        fn append_to_manifest(&self, folder: &str, sections: &str) {
+            let manifest_path = self.0.join(folder).join("cpm.toml");
+            let content = std::fs::read_to_string(&manifest_path).unwrap();
+            std::fs::write(manifest_path, content + sections).unwrap();
+        }
+
+        /// Writes the registry index of a package
+        fn add_index(&self, name: &str, index: &str) {
+            let index_path = self.registry().index_path(name);
+            std::fs::create_dir_all(index_path.parent().unwrap()).unwrap();
+            std::fs::write(index_path, index).unwrap();
+        }
+
+        fn registry(&self) -> Registry {
+            Registry::new(self.0.join("registry"))
+        }
+
+        fn resolve(
+            &self,
+            member_paths: &[PathBuf],
+            patches: &BTreeMap<String, Dependency>,
+        ) -> Result<Resolve, DependencyError> {
+            self.resolve_for_target(member_paths, patches, &TargetTriple::host())
+        }
+
+        fn resolve_for_target(
+            &self,
+            member_paths: &[PathBuf],
+            patches: &BTreeMap<String, Dependency>,
+            target: &TargetTriple,
+        ) -> Result<Resolve, DependencyError> {
+            Resolve::new(
+                member_paths,
+                patches,
+                target,
+                &self.registry(),
+                &GitCache::new(true),
+                None,
+                None,
+            )
+        }
+
+        /// Selects the registry versions required by `requirements`
+        /// (name, version requirement and whether it's required on the host)
+        fn select_versions(
+            &self,
+            requirements: &[(&str, &str, bool)],
+            target: &TargetTriple,
+            lockfile: Option<&Lockfile>,
+        ) -> Result<BTreeMap<String, SelectedVersion>, DependencyError> {
+            let requirements = requirements
+                .iter()
+                .map(|(name, requirement, for_host)| Requirement {
+                    name: name.to_string(),
+                    requirement: semver::VersionReq::parse(requirement).unwrap(),
+                    required_by: "app".to_string(),
+                    for_host: *for_host,
+                })
+                .collect();
+            let host = TargetTriple::host();
+            select_registry_versions(
+                &BTreeMap::new(),
+                requirements,
+                target,
+                Some(&host).filter(|host| !host.is_same_target(target)),
+                &self.registry(),
+                None,
+                lockfile,
+            )
+        }
+    }
+
+    impl Drop for TemporaryWorkspace {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn path_dependency(path: PathBuf) -> Dependency {
+        Dependency::Detailed(Box::new(DetailedDependency {
+            path: Some(path),
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn member_used_as_path_dependency_is_resolved_once() {
+        let workspace = TemporaryWorkspace::new("member-dependency");
+        let app_path = workspace.add_package("app", "app", "lib = { path = \"../lib\" }\n");
+        let lib_path = workspace.add_package("lib", "lib", "");
+        let resolve = workspace
+            .resolve(&[app_path, workspace.0.join("./lib")], &BTreeMap::new())
+            .unwrap();
+        assert_eq!(resolve.packages.len(), 2);
+        assert_eq!(resolve.packages["lib"].path, lib_path);
+        assert_eq!(resolve.packages["lib"].source, PackageSource::Workspace);
+    }
+
+    #[test]
+    fn patch_of_the_folder_of_a_path_dependency_is_resolved_once() {
+        let workspace = TemporaryWorkspace::new("patch-path-dependency");
+        let app_path = workspace.add_package("app", "app", "lib = { path = \"../lib\" }\n");
+        let lib_path = workspace.add_package("lib", "lib", "");
+        let patches = BTreeMap::from([(
+            "lib".to_string(),
+            path_dependency(workspace.0.join("app").join("..").join("lib")),
+        )]);
+        let resolve = workspace.resolve(&[app_path], &patches).unwrap();
+        assert_eq!(resolve.packages.len(), 2);
+        assert_eq!(resolve.packages["lib"].path, lib_path);
+        assert!(resolve.packages["lib"].patched);
+        assert!(resolve.unused_patches.is_empty());
+    }
+
+    #[test]
+    fn different_folders_with_the_same_package_are_rejected() {
+        let workspace = TemporaryWorkspace::new("duplicate-package");
+        let app_path = workspace.add_package("app", "app", "lib = { path = \"../lib\" }\n");
+        workspace.add_package("lib", "lib", "");
+        let other_lib_path = workspace.add_package("other/lib", "lib", "");
+        let result = workspace.resolve(&[app_path, other_lib_path], &BTreeMap::new());
+        assert!(matches!(
+            result,
+            Err(DependencyError::DuplicatePackage { name, .. }) if name == "lib"
+        ));
+    }
+
+    #[test]
+    fn build_dependencies_are_resolved_for_the_host() {
+        let workspace = TemporaryWorkspace::new("host-build-dependencies");
+        let app_path = workspace.add_package("app", "app", "common = { path = \"../common\" }\n");
+        workspace.append_to_manifest(
+            "app",
+            "\n[build-dependencies]\ntool = { path = \"../tool\" }\n",
+        );
+        workspace.add_package("tool", "tool", "common = { path = \"../common\" }\n");
+        workspace.append_to_manifest(
+            "tool",
+            "\n[target.'cfg(target_os = \"none\")'.dependencies]\n\
+             bare = { path = \"../bare\" }\n\
+             \n[target.'cfg(not(target_os = \"none\"))'.dependencies]\n\
+             hosted = { path = \"../hosted\" }\n",
+        );
+        workspace.add_package("common", "common", "");
+        workspace.add_package("bare", "bare", "");
+        workspace.add_package("hosted", "hosted", "");
+        let target = TargetTriple::parse("arm-none-eabi").unwrap();
+        let resolve = workspace
+            .resolve_for_target(&[app_path], &BTreeMap::new(), &target)
+            .unwrap();
+        assert!(!resolve.packages.contains_key("bare"));
+        assert_eq!(
+            resolve.packages["tool"].host_dependencies,
+            ["common", "hosted"]
+        );
+        assert_eq!(resolve.packages["app"].build_dependencies, ["tool"]);
+        let build_order: Vec<(&str, bool)> = resolve
+            .build_order(&["app".to_string()])
+            .unwrap()
+            .into_iter()
+            .map(|(package, for_host)| (package.name.as_str(), for_host))
+            .collect();
+        assert_eq!(
+            build_order,
+            [
+                ("common", false),
+                ("common", true),
+                ("hosted", true),
+                ("tool", true),
+                ("app", false)
+            ]
+        );
+    }
+
+    #[test]
+    fn build_dependencies_are_built_for_the_target_without_cross_compiling() {
+        let workspace = TemporaryWorkspace::new("native-build-dependencies");
+        let app_path = workspace.add_package("app", "app", "");
+        workspace.append_to_manifest(
+            "app",
+            "\n[build-dependencies]\ntool = { path = \"../tool\" }\n",
+        );
+        workspace.add_package("tool", "tool", "");
+        let resolve = workspace.resolve(&[app_path], &BTreeMap::new()).unwrap();
+        assert!(resolve.packages["tool"].host_dependencies.is_empty());
+        let build_order: Vec<(&str, bool)> = resolve
+            .build_order(&["app".to_string()])
+            .unwrap()
+            .into_iter()
+            .map(|(package, for_host)| (package.name.as_str(), for_host))
+            .collect();
+        assert_eq!(build_order, [("tool", false), ("app", false)]);
+    }
+
+    const ZLIB_INDEX: &str = r#"
+[[versions]]
+version = "1.2.0"
+checksum = "a"
+
+[[versions]]
+version = "1.3.0"
+checksum = "b"
+
+[[versions]]
+version = "1.4.0"
+checksum = "c"
+yanked = true
+
+[[versions]]
+version = "2.0.0"
+checksum = "d"
+"#;
+
+    #[test]
+    fn highest_matching_version_is_selected() {
+        let workspace = TemporaryWorkspace::new("highest-version");
+        workspace.add_index("zlib", ZLIB_INDEX);
+        let selected = workspace
+            .select_versions(&[("zlib", "^1.2", false)], &TargetTriple::host(), None)
+            .unwrap();
+        assert_eq!(
+            selected["zlib"].entry.version,
+            semver::Version::new(1, 3, 0)
+        );
+    }
+
+    #[test]
+    fn locked_version_is_kept_while_it_matches() {
+        let workspace = TemporaryWorkspace::new("locked-version");
+        workspace.add_index("zlib", ZLIB_INDEX);
+        let locked = |version| Lockfile {
+            packages: vec![LockedPackage {
+                name: "zlib".to_string(),
+                version,
+                source: "registry".to_string(),
+                checksum: None,
+                dependencies: vec![],
+            }],
+            ..Default::default()
+        };
+        let target = TargetTriple::host();
+        let lockfile = locked(semver::Version::new(1, 2, 0));
+        let selected = workspace
+            .select_versions(&[("zlib", "^1.2", false)], &target, Some(&lockfile))
+            .unwrap();
+        assert_eq!(
+            selected["zlib"].entry.version,
+            semver::Version::new(1, 2, 0)
+        );
+        // a yanked version is only used when it's locked
+        let lockfile = locked(semver::Version::new(1, 4, 0));
+        let selected = workspace
+            .select_versions(&[("zlib", "^1.2", false)], &target, Some(&lockfile))
+            .unwrap();
+        assert_eq!(
+            selected["zlib"].entry.version,
+            semver::Version::new(1, 4, 0)
+        );
+        let selected = workspace
+            .select_versions(&[("zlib", "^2", false)], &target, Some(&lockfile))
+            .unwrap();
+        assert_eq!(
+            selected["zlib"].entry.version,
+            semver::Version::new(2, 0, 0)
+        );
+    }
+
+    #[test]
+    fn requirements_of_selected_versions_are_combined() {
+        let workspace = TemporaryWorkspace::new("transitive-requirements");
+        workspace.add_index("zlib", ZLIB_INDEX);
+        workspace.add_index(
+            "png",
+            "[[versions]]\nversion = \"1.0.0\"\nchecksum = \"e\"\n\
+             [versions.dependencies]\nzlib = \"~1.2\"\n",
+        );
+        let selected = workspace
+            .select_versions(
+                &[("png", "^1", false), ("zlib", "^1", false)],
+                &TargetTriple::host(),
+                None,
+            )
+            .unwrap();
+        assert_eq!(
+            selected["zlib"].entry.version,
+            semver::Version::new(1, 2, 0)
+        );
+    }
+
+    #[test]
+    fn conflicting_requirements_are_reported() {
+        let workspace = TemporaryWorkspace::new("conflicting-requirements");
+        workspace.add_index("zlib", ZLIB_INDEX);
+        let result = workspace.select_versions(
+            &[("zlib", "^1", false), ("zlib", "^2", false)],
+            &TargetTriple::host(),
+            None,
+        );
+        assert!(matches!(
+            result,
+            Err(DependencyError::NoMatchingVersion { name, .. }) if name == "zlib"
+        ));
+    }
+
+    #[test]
+    fn registry_versions_get_the_dependencies_of_their_platforms() {
+        let workspace = TemporaryWorkspace::new("registry-platforms");
+        workspace.add_index(
+            "tool",
+            "[[versions]]\nversion = \"1.0.0\"\nchecksum = \"f\"\n\
+             [versions.target.'cfg(target_os = \"none\")'.dependencies]\nbare = \"^1\"\n\
+             [versions.target.'cfg(not(target_os = \"none\"))'.dependencies]\nhosted = \"^1\"\n",
+        );
+        workspace.add_index(
+            "hosted",
+            "[[versions]]\nversion = \"1.0.0\"\nchecksum = \"g\"\n",
+        );
+        let target = TargetTriple::parse("arm-none-eabi").unwrap();
+        let selected = workspace
+            .select_versions(&[("tool", "^1", true)], &target, None)
+            .unwrap();
+        assert!(selected["tool"].target.is_none());
+        assert!(selected["hosted"].host.is_some());
+        assert!(!selected.contains_key("bare"));
+    }
+}
@@ -0,0 +1,58 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::{
+    git::errors::GitError, lockfile::errors::LockfileError, manifest::errors::ManifestLoadError,
+    registry::errors::RegistryError, target::errors::TargetError, vendor::errors::VendorError,
+};
+
+#[derive(Error, Debug)]
+pub enum DependencyError {
+    #[error("invalid manifest at {path:?}: {error}")]
+    InvalidManifest {
+        path: PathBuf,
+        error: ManifestLoadError,
+    },
+    #[error("`package` section not found in the manifest at {0:?}")]
+    PackageNotFound(PathBuf),
+    #[error("package `{name}` is defined twice (at {first_path:?} and {second_path:?})")]
+    DuplicatePackage {
+        name: String,
+        first_path: PathBuf,
+        second_path: PathBuf,
+    },
+    #[error("dependency `{name}` points to {path:?}, which contains the package `{found}`")]
+    NameMismatch {
+        name: String,
+        found: String,
+        path: PathBuf,
+    },
+    #[error("no version of `{name}` satisfies the requirements: {requirements}")]
+    NoMatchingVersion { name: String, requirements: String },
+    #[error("the checksum of `{name}` {version} in the registry differs from the lockfile")]
+    LockedChecksumMismatch {
+        name: String,
+        version: semver::Version,
+    },
+    #[error("dependency `{0}` uses a build system, it must be a path or git dependency")]
+    ExternalWithoutSource(String),
+    #[error("patch `{0}` must be a path or git dependency")]
+    PatchWithoutSource(String),
+    #[error("dependency resolution did not converge")]
+    ResolutionDidNotConverge,
+    #[error("unknown package `{0}`")]
+    UnknownPackage(String),
+    #[error("cyclic dependency: {0}")]
+    Cycle(String),
+    #[error("{0}")]
+    RegistryError(#[from] RegistryError),
+    #[error("{0}")]
+    LockfileError(#[from] LockfileError),
+    #[error("{0}")]
+    GitError(#[from] GitError),
+    #[error("{0}")]
+    VendorError(#[from] VendorError),
+    #[error("{0}")]
+    TargetError(#[from] TargetError),
+}
@@ -0,0 +1,527 @@
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+};
+
+use walkdir::WalkDir;
+
+use crate::{
+    compiler::Compiler,
+    manifest::{self, BuildSystem, DetailedDependency, PackageKind},
+    package::builder::{create_parent_folder, file_needs_rebuild, DependencyArtifact},
+    profile::BuildProfile,
+    toolchain::Toolchain,
+};
+
+pub mod errors;
+use errors::ExternalBuildError;
+
+/// File written in the dependency folder after a successful build
+///
+/// It contains the build settings, so changing them triggers a rebuild
+const BUILD_STAMP_FILE_NAME: &str = ".cpm-built";
+
+/// Builds a dependency that isn't a cpm package with its own build system
+///
+/// Everything is placed in `target/deps/<name>` (in the target folder of the build profile and toolchain):
+/// - `build/`: the build folder, a copy of the dependency for `make` and `autotools`
+/// - `install/`: the install prefix for `cmake` and `autotools`
+pub struct ExternalBuilder<'a> {
+    name: &'a str,
+    source_path: &'a Path,
+    settings: &'a DetailedDependency,
+    folder_path: PathBuf,
+    profile: &'a BuildProfile,
+    toolchain: &'a Toolchain,
+}
+
+impl<'a> ExternalBuilder<'a> {
+    /// Creates a builder for the dependency `name` whose sources are at `source_path`
+    ///
+    /// # Panics
+    /// If `settings` doesn't have a build system
+    pub fn new(
+        name: &'a str,
+        source_path: &'a Path,
+        settings: &'a DetailedDependency,
+        workspace_path: &Path,
+        profile: &'a BuildProfile,
+        toolchain: &'a Toolchain,
+    ) -> Self {
+        assert!(
+            settings.build.is_some(),
+            "external dependencies must have a build system"
+        );
+        Self {
+            name,
+            source_path,
+            settings,
+            folder_path: profile
+                .target_folder_path(workspace_path, toolchain)
+                .join("deps")
+                .join(name),
+            profile,
+            toolchain,
+        }
+    }
+
+    fn build_system(&self) -> BuildSystem {
+        self.settings
+            .build
+            .expect("build system was checked in the constructor")
+    }
+
+    fn build_folder_path(&self) -> PathBuf {
+        self.folder_path.join("build")
+    }
+
+    fn install_folder_path(&self) -> PathBuf {
+        self.folder_path.join("install")
+    }
+
+    /// The folder `include` and `libs` are relative to
+    fn output_folder_path(&self) -> PathBuf {
+        match self.build_system() {
+            BuildSystem::CMake | BuildSystem::Autotools => self.install_folder_path(),
+            BuildSystem::Make => self.build_folder_path(),
+            BuildSystem::Sources => self.source_path.to_path_buf(),
+        }
+    }
+
+    fn static_library_file_name(&self) -> String {
+        format!(
+            "lib{}.{}",
+            self.name,
+            self.toolchain.target.static_library_extension()
+        )
+    }
+
+    /// The include folders and libraries exposed to the dependents
+    pub fn artifact(&self) -> DependencyArtifact {
+        let output_folder_path = self.output_folder_path();
+        let include_folders = if self.settings.include.is_empty() {
+            vec![output_folder_path.join("include")]
+        } else {
+            self.settings
+                .include
+                .iter()
+                .map(|include| output_folder_path.join(include))
+                .collect()
+        };
+        let library_paths = match self.build_system() {
+            BuildSystem::Sources => vec![self
+                .build_folder_path()
+                .join(self.static_library_file_name())],
+            _ if !self.settings.libs.is_empty() => self
+                .settings
+                .libs
+                .iter()
+                .map(|lib| output_folder_path.join(lib))
+                .collect(),
+            BuildSystem::Make => vec![output_folder_path.join(self.static_library_file_name())],
+            BuildSystem::CMake | BuildSystem::Autotools => vec![output_folder_path
+                .join("lib")
+                .join(self.static_library_file_name())],
+        };
+        DependencyArtifact {
+            include_folders,
+            library_paths,
+            system_libs: vec![],
+        }
+    }
+
+    /// The flags passed to the compiler of the build system
+    ///
+    /// Code is always position independent, so the libraries can be linked into dynamic libraries.
+    /// The libraries are instrumented like the cpm packages when sanitizers are enabled
+    fn compiler_flags(&self) -> Vec<String> {
+        let mut flags = self.toolchain.compiler_flags();
+        flags.extend(self.profile.flags());
+        flags.extend(self.profile.sanitizer_compiler_flags());
+        flags.push("-fPIC".to_string());
+        flags
+    }
+
+    /// The flags passed to the linker of the build system
+    fn linker_flags(&self) -> Vec<String> {
+        let mut flags = self.toolchain.linker_flags();
+        flags.extend(self.profile.linker_flags.iter().cloned());
+        flags.extend(self.profile.sanitizer_flags());
+        flags
+    }
+
+    /// The content of the build stamp, a description of everything that affects the build
+    fn build_stamp(&self, compiler: &dyn Compiler) -> String {
+        format!(
+            "build = {:?}\ntarget = {}\ncc = {}\nar = {}\ncflags = {:?}\nldflags = {:?}\nargs = {:?}\nsources = {:?}\n",
+            self.build_system(),
+            self.toolchain.target,
+            compiler.program(self.toolchain),
+            self.toolchain.archiver_program(),
+            self.compiler_flags(),
+            self.linker_flags(),
+            self.settings.build_args,
+            self.settings.sources,
+        )
+    }
+
+    /// Checks if the dependency must be rebuilt
+    ///
+    /// That's the case when its build settings changed or when one of its files
+    /// was modified after the last build
+    pub fn needs_rebuild(&self, compiler: &dyn Compiler) -> bool {
+        let stamp_path = self.folder_path.join(BUILD_STAMP_FILE_NAME);
+        if std::fs::read_to_string(&stamp_path).ok() != Some(self.build_stamp(compiler)) {
+            return true;
+        }
+        source_files(self.source_path).any(|file| file_needs_rebuild(file, &stamp_path))
+    }
+
+    /// Builds the dependency if needed and returns its artifact
+    ///
+    /// When the build settings changed, the build and install folders are removed first,
+    /// so nothing built with the previous settings (e.g. objects) is reused
+    pub fn build(&self, compiler: &dyn Compiler) -> Result<DependencyArtifact, ExternalBuildError> {
+        if self.needs_rebuild(compiler) {
+            let stamp_path = self.folder_path.join(BUILD_STAMP_FILE_NAME);
+            if std::fs::read_to_string(&stamp_path).ok() != Some(self.build_stamp(compiler)) {
+                for folder_path in [self.build_folder_path(), self.install_folder_path()] {
+                    if folder_path.exists() {
+                        std::fs::remove_dir_all(folder_path)?;
+                    }
+                }
+            }
+            std::fs::create_dir_all(self.build_folder_path())?;
+            match self.build_system() {
+                BuildSystem::CMake => self.build_cmake(compiler)?,
+                BuildSystem::Make => self.build_make(compiler)?,
+                BuildSystem::Autotools => self.build_autotools(compiler)?,
+                BuildSystem::Sources => self.build_sources(compiler)?,
+            }
+            std::fs::write(
+                self.folder_path.join(BUILD_STAMP_FILE_NAME),
+                self.build_stamp(compiler),
+            )?;
+        }
+        let artifact = self.artifact();
+        if let Some(missing) = artifact
+            .include_folders
+            .iter()
+            .chain(&artifact.library_paths)
+            .find(|path| !path.exists())
+        {
+            return Err(ExternalBuildError::MissingOutput(missing.clone()));
+        }
+        Ok(artifact)
+    }
+
+    fn build_cmake(&self, compiler: &dyn Compiler) -> Result<(), ExternalBuildError> {
+        let build_folder_path = self.build_folder_path();
+        let mut configure = Command::new("cmake");
+        if self.toolchain.is_cross_compiling() {
+            configure
+                .arg(format!(
+                    "-DCMAKE_SYSTEM_NAME={}",
+                    cmake_system_name(&self.toolchain.target.os)
+                ))
+                .arg(format!(
+                    "-DCMAKE_SYSTEM_PROCESSOR={}",
+                    self.toolchain.target.arch
+                ));
+        }
+        run(configure
+            .arg("-S")
+            .arg(self.source_path)
+            .arg("-B")
+            .arg(&build_folder_path)
+            .arg(format!(
+                "-DCMAKE_C_COMPILER={}",
+                compiler.program(self.toolchain)
+            ))
+            .arg(format!("-DCMAKE_AR={}", self.toolchain.archiver_program()))
+            .arg(format!(
+                "-DCMAKE_C_FLAGS={}",
+                self.compiler_flags().join(" ")
+            ))
+            .arg(format!(
+                "-DCMAKE_EXE_LINKER_FLAGS={}",
+                self.linker_flags().join(" ")
+            ))
+            .arg(format!(
+                "-DCMAKE_INSTALL_PREFIX={}",
+                self.install_folder_path().display()
+            ))
+            .args([
+                "-DCMAKE_INSTALL_LIBDIR=lib",
+                "-DBUILD_SHARED_LIBS=OFF",
+                "-DCMAKE_POSITION_INDEPENDENT_CODE=ON",
+            ])
+            .args(&self.settings.build_args))?;
+        run(Command::new("cmake").arg("--build").arg(&build_folder_path))?;
+        run(Command::new("cmake")
+            .arg("--install")
+            .arg(&build_folder_path))
+    }
+
+    /// Runs `make` in a copy of the dependency, with `CC` and `AR` set on the command line
+    /// and `CFLAGS` and `LDFLAGS` in the environment
+    fn build_make(&self, compiler: &dyn Compiler) -> Result<(), ExternalBuildError> {
+        let build_folder_path = self.build_folder_path();
+        copy_sources(self.source_path, &build_folder_path)?;
+        run(Command::new("make")
+            .arg("-C")
+            .arg(&build_folder_path)
+            .arg(format!("CC={}", compiler.program(self.toolchain)))
+            .arg(format!("AR={}", self.toolchain.archiver_program()))
+            .args(&self.settings.build_args)
+            .env("CFLAGS", self.compiler_flags().join(" "))
+            .env("LDFLAGS", self.linker_flags().join(" ")))
+    }
+
+    fn build_autotools(&self, compiler: &dyn Compiler) -> Result<(), ExternalBuildError> {
+        let build_folder_path = self.build_folder_path();
+        copy_sources(self.source_path, &build_folder_path)?;
+        if !build_folder_path.join("configure").exists() {
+            run(Command::new("autoreconf")
+                .arg("--install")
+                .current_dir(&build_folder_path))?;
+        }
+        let mut configure = Command::new(build_folder_path.join("configure"));
+        if self.toolchain.is_cross_compiling() {
+            configure.arg(format!("--host={}", self.toolchain.target));
+        }
+        run(configure
+            .arg(format!("--prefix={}", self.install_folder_path().display()))
+            .args(["--disable-shared", "--enable-static"])
+            .arg(format!("CC={}", compiler.program(self.toolchain)))
+            .arg(format!("AR={}", self.toolchain.archiver_program()))
+            .arg(format!("CFLAGS={}", self.compiler_flags().join(" ")))
+            .arg(format!("LDFLAGS={}", self.linker_flags().join(" ")))
+            .args(&self.settings.build_args)
+            .current_dir(&build_folder_path))?;
+        run(Command::new("make").current_dir(&build_folder_path))?;
+        run(Command::new("make")
+            .arg("install")
+            .current_dir(&build_folder_path))
+    }
+
+    /// Compiles the `sources` with cpm's compiler into a static library
+    fn build_sources(&self, compiler: &dyn Compiler) -> Result<(), ExternalBuildError> {
+        let mut source_files = vec![];
+        for pattern in &self.settings.sources {
+            let absolute_pattern = self.source_path.join(pattern).to_string_lossy().to_string();
+            let paths = glob::glob(&absolute_pattern).map_err(|error| {
+                ExternalBuildError::InvalidSourcePattern {
+                    pattern: pattern.clone(),
+                    error,
+                }
+            })?;
+            source_files.extend(paths.filter_map(|p| p.ok()));
+        }
+        source_files.sort();
+        source_files.dedup();
+        if source_files.is_empty() {
+            return Err(ExternalBuildError::NoSources(self.settings.sources.clone()));
+        }
+        let package = self.synthetic_package();
+        let objects_folder_path = self.build_folder_path().join("objects");
+        let mut object_files = vec![];
+        for source_file in source_files {
+            let relative_path = source_file
+                .strip_prefix(self.source_path)
+                .unwrap_or(&source_file);
+            let object_file_path = objects_folder_path.join(relative_path).with_extension("o");
+            create_parent_folder(&object_file_path)?;
+            if file_needs_rebuild(&source_file, &object_file_path) {
+                run(&mut compiler.compile_command(
+                    self.source_path.to_path_buf(),
+                    source_file,
+                    object_file_path.clone(),
+                    &package,
+                    self.profile,
+                    self.toolchain,
+                ))?;
+            }
+            object_files.push(object_file_path);
+        }
+        let library_path = self
+            .build_folder_path()
+            .join(self.static_library_file_name());
+        if library_path.exists() {
+            std::fs::remove_file(&library_path)?;
+        }
+        run(&mut compiler.link_command(
+            self.source_path.to_path_buf(),
+            object_files,
+            library_path,
+            &package,
+            self.profile,
+            self.toolchain,
+        ))
+    }
+
+    /// The package info used to compile the `sources` with the [`Compiler`] trait
+    fn synthetic_package(&self) -> manifest::Package {
+        let mut include_folders = self.artifact().include_folders.into_iter();
+        let include_folder = include_folders
+            .next()
+            .unwrap_or_else(|| self.source_path.join("include"));
+        let mut additional_compiler_flags = vec!["-fPIC".to_string()];
+        additional_compiler_flags
+            .extend(include_folders.map(|folder| format!("-I{}", folder.display())));
+        additional_compiler_flags.extend(self.settings.build_args.iter().cloned());
+        manifest::Package {
+            name: self.name.to_string(),
+            version: semver::Version::new(0, 0, 0),
+            description: None,
+            src_folder: PathBuf::new(),
+            include_folder,
+            additional_compiler_flags,
+            additional_linker_flags: vec![],
+            defines: vec![],
+            sources: vec![],
+            enable_math_library: false,
+            enable_pthread_library: false,
+            freestanding: false,
+            linker_script: None,
+            entry: None,
+            map_file: false,
+            images: vec![],
+            size_budget: None,
+            kind: PackageKind::StaticLibrary,
+        }
+    }
+}
+
+/// The `CMAKE_SYSTEM_NAME` of an operating system of a target triple
+fn cmake_system_name(os: &str) -> &str {
+    match os {
+        "linux" => "Linux",
+        "windows" => "Windows",
+        "macos" => "Darwin",
+        "freebsd" => "FreeBSD",
+        "android" => "Android",
+        "none" => "Generic",
+        os => os,
+    }
+}
+
+/// All the files of a dependency, except the git metadata
+fn source_files(source_path: &Path) -> impl Iterator<Item = PathBuf> {
+    WalkDir::new(source_path)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".git")
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.path().to_path_buf())
+}
+
+/// Copies the files of a dependency that changed since the last copy to `destination`
+fn copy_sources(source_path: &Path, destination: &Path) -> std::io::Result<()> {
+    for file in source_files(source_path) {
+        let relative_path = file
+            .strip_prefix(source_path)
+            .expect("walked files must be inside the walked folder");
+        let destination_path = destination.join(relative_path);
+        if file_needs_rebuild(&file, &destination_path) {
+            create_parent_folder(&destination_path)?;
+            std::fs::copy(&file, &destination_path)?;
+        }
+    }
+    Ok(())
+}
+
+fn run(command: &mut Command) -> Result<(), ExternalBuildError> {
+    let status = command
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .map_err(|error| match error.kind() {
+            std::io::ErrorKind::NotFound => ExternalBuildError::ProgramNotInstalled {
+                program: command.get_program().to_string_lossy().to_string(),
+            },
+            _ => ExternalBuildError::IOError(error),
+        })?;
+    if !status.success() {
+        return Err(ExternalBuildError::CommandFailed {
+            command: format!("{command:?}"),
+            exit_code: status,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler::GCC, profile::Sanitizer};
+
+    #[test]
+    fn sanitizers_instrument_external_libraries_without_linking_libfuzzer() {
+        let settings = DetailedDependency {
+            build: Some(BuildSystem::Make),
+            ..Default::default()
+        };
+        let mut profile = BuildProfile::default();
+        profile
+            .set_sanitizers(vec![Sanitizer::Address, Sanitizer::Fuzzer])
+            .unwrap();
+        let toolchain = Toolchain::host();
+        let builder = ExternalBuilder::new(
+            "zlib",
+            Path::new("zlib"),
+            &settings,
+            Path::new("workspace"),
+            &profile,
+            &toolchain,
+        );
+        let sanitizer_flag = "-fsanitize=address,fuzzer-no-link".to_string();
+        assert!(builder.compiler_flags().contains(&sanitizer_flag));
+        assert!(builder.linker_flags().contains(&sanitizer_flag));
+        assert!(!builder
+            .compiler_flags()
+            .iter()
+            .chain(&builder.linker_flags())
+            .any(|flag| flag.contains("fuzzer,") || flag.ends_with("=fuzzer")));
+    }
+
+    #[test]
+    fn objects_are_recompiled_when_the_build_settings_change() {
+        let workspace_path =
+            std::env::temp_dir().join(format!("cpm-external-stamp-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&workspace_path);
+        let source_path = workspace_path.join("value");
+        std::fs::create_dir_all(source_path.join("include")).unwrap();
+        std::fs::write(
+            source_path.join("value.c"),
+            "int value(void) { return VALUE; }\n",
+        )
+        .unwrap();
+        let mut settings = DetailedDependency {
+            build: Some(BuildSystem::Sources),
+            sources: vec!["*.c".to_string()],
+            build_args: vec!["-DVALUE=1".to_string()],
+            ..Default::default()
+        };
+        let profile = BuildProfile::default();
+        let toolchain = Toolchain::host();
+        let object_modified = |settings: &DetailedDependency| {
+            let builder = ExternalBuilder::new(
+                "value",
+                &source_path,
+                settings,
+                &workspace_path,
+                &profile,
+                &toolchain,
+            );
+            builder.build(&GCC).unwrap();
+            let object_path = builder.build_folder_path().join("objects/value.o");
+            std::fs::metadata(object_path).unwrap().modified().unwrap()
+        };
+        let first_build = object_modified(&settings);
+        assert_eq!(object_modified(&settings), first_build);
+        settings.build_args = vec!["-DVALUE=2".to_string()];
+        assert!(object_modified(&settings) > first_build);
+        std::fs::remove_dir_all(workspace_path).unwrap();
+    }
+}
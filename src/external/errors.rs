@@ -0,0 +1,25 @@
+use std::{path::PathBuf, process::ExitStatus};
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ExternalBuildError {
+    #[error("`{program}` is not installed")]
+    ProgramNotInstalled { program: String },
+    #[error("`{command}` failed (exit code {exit_code:?})")]
+    CommandFailed {
+        command: String,
+        exit_code: ExitStatus,
+    },
+    #[error("invalid source pattern `{pattern}`: {error}")]
+    InvalidSourcePattern {
+        pattern: String,
+        error: glob::PatternError,
+    },
+    #[error("no source files matched by {0:?}")]
+    NoSources(Vec<String>),
+    #[error("the build did not produce {0:?}, set `include` and `libs` in the dependency")]
+    MissingOutput(PathBuf),
+    #[error("Io error: {0}")]
+    IOError(#[from] std::io::Error),
+}
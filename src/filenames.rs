@@ -1,19 +1,23 @@
-#[cfg(os = "windows")]
-pub const EXECUTABLE_EXTENSION: &str = "exe";
+use std::path::PathBuf;
 
-#[cfg(not(os = "windows"))]
-pub const EXECUTABLE_EXTENSION: &str = "";
-
-#[cfg(os = "windows")]
-pub const STATIC_LIB_EXTENSION: &str = "lib";
-
-#[cfg(not(os = "windows"))]
-pub const STATIC_LIB_EXTENSION: &str = "a";
+pub const MANIFEST_FILE_NAME: &str = "cpm.toml";
 
-#[cfg(os = "windows")]
-pub const DYN_LIB_EXTENSION: &str = "dll";
+pub const LOCKFILE_NAME: &str = "cpm.lock";
 
-#[cfg(not(os = "windows"))]
-pub const DYN_LIB_EXTENSION: &str = "so";
+/// File of the workspace folder with the `[toolchain.<triple>]` sections of the local machine
+pub const TOOLCHAIN_FILE_NAME: &str = "cpm-toolchain.toml";
 
-pub const MANIFEST_FILE_NAME: &str = "cpm.toml";
+/// The folder where cpm keeps its global data (registry, caches, ...)
+///
+/// It's `$CPM_HOME` when set, `~/.cpm` otherwise
+pub fn cpm_home_path() -> PathBuf {
+    if let Some(cpm_home) = std::env::var_os("CPM_HOME") {
+        return cpm_home.into();
+    }
+    let mut home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from)
+        .unwrap_or_default();
+    home.push(".cpm");
+    home
+}
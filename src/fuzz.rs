@@ -0,0 +1,77 @@
+use std::{
+    fmt::Write as _,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::package::builder::DevTarget;
+
+/// The folder of the inputs that a fuzz target explores, kept between runs
+/// (`fuzz/corpus/<target>`)
+pub fn corpus_folder_path(package_path: &Path, target: &str) -> PathBuf {
+    package_path
+        .join(DevTarget::Fuzz.folder_name())
+        .join("corpus")
+        .join(target)
+}
+
+/// The folder where libFuzzer writes the crash inputs of a fuzz target
+/// (`fuzz/artifacts/<target>`)
+pub fn artifacts_folder_path(package_path: &Path, target: &str) -> PathBuf {
+    package_path
+        .join(DevTarget::Fuzz.folder_name())
+        .join("artifacts")
+        .join(target)
+}
+
+/// The file name of the regression test of a crash input, in the `tests` folder
+///
+/// It contains the start of the SHA-256 of the input, so every input gets its own test
+pub fn regression_test_file_name(target: &str, input: &[u8]) -> String {
+    let hash: String = Sha256::digest(input)
+        .iter()
+        .take(4)
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("fuzz_{target}_{hash}.c")
+}
+
+/// The source of a test that runs the fuzz target `target` on `input`
+///
+/// The test includes the fuzz target, so it's built by `cpm test` without libFuzzer,
+/// and fails as long as the input crashes the target
+pub fn regression_test_source(target: &str, input_name: &str, input: &[u8]) -> String {
+    let mut bytes = String::new();
+    for chunk in input.chunks(12) {
+        let line: Vec<String> = chunk.iter().map(|byte| format!("0x{byte:02x}")).collect();
+        let _ = writeln!(bytes, "    {},", line.join(", "));
+    }
+    if input.is_empty() {
+        // C doesn't allow empty arrays
+        bytes.push_str("    0x00,\n");
+    }
+    format!(
+        r#"/* Regression test of the fuzz target `{target}`, generated by `cpm fuzz tmin` from `{input_name}` */
+#include <stddef.h>
+#include <stdint.h>
+
+#include "../{folder}/{target}.c"
+
+__attribute__((weak)) int LLVMFuzzerInitialize(int *argc, char ***argv);
+
+static const uint8_t input[] = {{
+{bytes}}};
+
+int main(int argc, char **argv) {{
+    if (LLVMFuzzerInitialize) {{
+        LLVMFuzzerInitialize(&argc, &argv);
+    }}
+    LLVMFuzzerTestOneInput(input, {length});
+    return 0;
+}}
+"#,
+        folder = DevTarget::Fuzz.folder_name(),
+        length = input.len(),
+    )
+}
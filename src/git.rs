@@ -0,0 +1,409 @@
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use sha2::{Digest, Sha256};
+
+use crate::filenames::cpm_home_path;
+
+pub mod errors;
+use errors::GitError;
+
+/// The revision of a git dependency to use
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum GitReference {
+    /// A commit hash or any other revision understood by `git rev-parse`
+    Rev(String),
+    Tag(String),
+    Branch(String),
+    /// The branch `HEAD` points to in the remote repository
+    DefaultBranch,
+}
+
+impl GitReference {
+    /// The revision passed to `git rev-parse` to find the commit of this reference
+    fn revision(&self) -> String {
+        match self {
+            GitReference::Rev(rev) => rev.clone(),
+            GitReference::Tag(tag) => format!("refs/tags/{tag}"),
+            GitReference::Branch(branch) => format!("refs/heads/{branch}"),
+            GitReference::DefaultBranch => "HEAD".to_string(),
+        }
+    }
+
+    /// The query written in the lockfile source of a git package (e.g. `branch=main`)
+    pub fn query(&self) -> Option<String> {
+        match self {
+            GitReference::Rev(rev) => Some(format!("rev={rev}")),
+            GitReference::Tag(tag) => Some(format!("tag={tag}")),
+            GitReference::Branch(branch) => Some(format!("branch={branch}")),
+            GitReference::DefaultBranch => None,
+        }
+    }
+}
+
+/// The shared cache of git repositories at `~/.cpm/git`
+///
+/// Every repository is mirrored once in `db/` and each used commit is checked out
+/// in `checkouts/`, so the same repository is only downloaded once for all the workspaces
+pub struct GitCache {
+    path: PathBuf,
+    offline: bool,
+}
+
+impl GitCache {
+    /// Opens the cache, when `offline` is set the network is never accessed
+    pub fn new(offline: bool) -> Self {
+        Self {
+            path: cpm_home_path().join("git"),
+            offline,
+        }
+    }
+
+    fn database_path(&self, url: &str) -> PathBuf {
+        self.path.join("db").join(repository_folder_name(url))
+    }
+
+    fn checkout_path(&self, url: &str, commit: &str) -> PathBuf {
+        self.path
+            .join("checkouts")
+            .join(repository_folder_name(url))
+            .join(commit)
+    }
+
+    /// The file created once a checkout is complete, next to it so the checkout
+    /// only contains the files of the repository
+    fn checkout_marker_path(&self, url: &str, commit: &str) -> PathBuf {
+        self.path
+            .join("checkouts")
+            .join(repository_folder_name(url))
+            .join(format!("{commit}.cpm-ok"))
+    }
+
+    /// Finds the commit of `reference`, fetching the repository unless `locked_commit`
+    /// is already in the cache, and returns the folder where that commit is checked out
+    pub fn checkout_reference(
+        &self,
+        url: &str,
+        reference: &GitReference,
+        locked_commit: Option<&str>,
+    ) -> Result<(String, PathBuf), GitError> {
+        let database_path = self.database_path(url);
+        let commit = match locked_commit {
+            Some(commit) if has_commit(&database_path, commit) => commit.to_string(),
+            _ => {
+                self.fetch(url)?;
+                let revision = locked_commit
+                    .map(String::from)
+                    .unwrap_or_else(|| reference.revision());
+                rev_parse(&database_path, &revision).ok_or_else(|| match self.offline {
+                    // the cache wasn't updated, the reference may exist in the remote repository
+                    true => GitError::ReferenceNotCachedOffline {
+                        url: url.to_string(),
+                        reference: revision,
+                    },
+                    false => GitError::ReferenceNotFound {
+                        url: url.to_string(),
+                        reference: revision,
+                    },
+                })?
+            }
+        };
+        let checkout_path = self.checkout(url, &commit)?;
+        Ok((commit, checkout_path))
+    }
+
+    /// Clones or updates the mirror of a repository
+    fn fetch(&self, url: &str) -> Result<(), GitError> {
+        let database_path = self.database_path(url);
+        if self.offline {
+            if database_path.exists() {
+                return Ok(());
+            }
+            return Err(GitError::NotCachedOffline {
+                url: url.to_string(),
+            });
+        }
+        if database_path.exists() {
+            run_git(
+                Command::new("git")
+                    .arg("-C")
+                    .arg(&database_path)
+                    .args(["fetch", "--quiet", "--prune", "--tags", "origin"]),
+            )
+        } else {
+            std::fs::create_dir_all(self.path.join("db"))?;
+            run_git(
+                Command::new("git")
+                    .args(["clone", "--quiet", "--mirror", url])
+                    .arg(&database_path),
+            )
+        }
+    }
+
+    fn checkout(&self, url: &str, commit: &str) -> Result<PathBuf, GitError> {
+        let checkout_path = self.checkout_path(url, commit);
+        let ok_marker_path = self.checkout_marker_path(url, commit);
+        if ok_marker_path.exists() && checkout_path.exists() {
+            return Ok(checkout_path);
+        }
+        if checkout_path.exists() {
+            std::fs::remove_dir_all(&checkout_path)?;
+        }
+        std::fs::create_dir_all(&checkout_path)?;
+        run_git(
+            Command::new("git")
+                .args(["clone", "--quiet", "--shared", "--no-checkout"])
+                .arg(self.database_path(url))
+                .arg(&checkout_path),
+        )?;
+        run_git(
+            Command::new("git")
+                .arg("-C")
+                .arg(&checkout_path)
+                .args(["checkout", "--quiet", "--detach", commit]),
+        )?;
+        std::fs::write(ok_marker_path, "")?;
+        Ok(checkout_path)
+    }
+}
+
+/// A readable and unique folder name for a repository (e.g. `zlib-1a2b3c4d5e6f7a8b`)
+fn repository_folder_name(url: &str) -> String {
+    let name = url
+        .trim_end_matches('/')
+        .rsplit('/')
+        .next()
+        .unwrap_or("repository")
+        .trim_end_matches(".git");
+    let hash: String = Sha256::digest(url.as_bytes())
+        .iter()
+        .take(8)
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    format!("{name}-{hash}")
+}
+
+fn has_commit(database_path: &Path, commit: &str) -> bool {
+    database_path.exists()
+        && Command::new("git")
+            .arg("-C")
+            .arg(database_path)
+            .args(["cat-file", "-e", &format!("{commit}^{{commit}}")])
+            .output()
+            .is_ok_and(|output| output.status.success())
+}
+
+fn rev_parse(database_path: &Path, revision: &str) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(database_path)
+        .args(["rev-parse", "--verify", "--quiet"])
+        .arg(format!("{revision}^{{commit}}"))
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+fn run_git(command: &mut Command) -> Result<(), GitError> {
+    let output = command.output().map_err(|error| match error.kind() {
+        std::io::ErrorKind::NotFound => GitError::GitNotInstalled,
+        _ => GitError::IOError(error),
+    })?;
+    if !output.status.success() {
+        return Err(GitError::CommandFailed {
+            command: format!("{command:?}"),
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A bare repository served with a `file://` URL and a git cache, in a new temporary folder
+    ///
+    /// The repository has a `v1` tag on its first commit, a second commit on `main`
+    /// and a third one on the `feature` branch, each commit writes its name in `version.txt`
+    struct TemporaryRepository {
+        folder: PathBuf,
+        url: String,
+    }
+
+    impl TemporaryRepository {
+        fn new(name: &str) -> Self {
+            let folder =
+                std::env::temp_dir().join(format!("cpm-git-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&folder);
+            let work_path = folder.join("work");
+            std::fs::create_dir_all(&work_path).unwrap();
+            let repository = Self {
+                url: format!("file://{}", folder.join("remote.git").display()),
+                folder,
+            };
+            repository.git(&["init", "--quiet", "--initial-branch=main"]);
+            repository.commit("first");
+            repository.git(&["tag", "v1"]);
+            repository.commit("second");
+            repository.git(&["checkout", "--quiet", "-b", "feature"]);
+            repository.commit("third");
+            repository.git(&["checkout", "--quiet", "main"]);
+            let remote_path = repository.folder.join("remote.git");
+            run_git(
+                Command::new("git")
+                    .args(["clone", "--quiet", "--bare"])
+                    .arg(&work_path)
+                    .arg(remote_path),
+            )
+            .unwrap();
+            repository
+        }
+
+        fn git(&self, args: &[&str]) {
+            run_git(
+                Command::new("git")
+                    .arg("-C")
+                    .arg(self.folder.join("work"))
+                    .args(["-c", "user.name=cpm", "-c", "user.email=cpm@example.com"])
+                    .args(args),
+            )
+            .unwrap();
+        }
+
+        fn commit(&self, name: &str) {
+            std::fs::write(self.folder.join("work/version.txt"), name).unwrap();
+            self.git(&["add", "version.txt"]);
+            self.git(&["commit", "--quiet", "-m", name]);
+        }
+
+        /// Commits on `main` and pushes the commit to the bare repository
+        fn push_commit(&self, name: &str) {
+            self.commit(name);
+            let remote_path = self.folder.join("remote.git");
+            self.git(&["push", "--quiet", &remote_path.to_string_lossy(), "main"]);
+        }
+
+        fn commit_of(&self, revision: &str) -> String {
+            rev_parse(&self.folder.join("work"), revision).unwrap()
+        }
+
+        fn cache(&self, offline: bool) -> GitCache {
+            GitCache {
+                path: self.folder.join("cache"),
+                offline,
+            }
+        }
+
+        fn checkout(
+            &self,
+            offline: bool,
+            reference: GitReference,
+            locked_commit: Option<&str>,
+        ) -> Result<(String, String), GitError> {
+            let (commit, checkout_path) =
+                self.cache(offline)
+                    .checkout_reference(&self.url, &reference, locked_commit)?;
+            let version = std::fs::read_to_string(checkout_path.join("version.txt")).unwrap();
+            Ok((commit, version))
+        }
+    }
+
+    impl Drop for TemporaryRepository {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.folder);
+        }
+    }
+
+    #[test]
+    fn references_are_checked_out_from_file_urls() {
+        let repository = TemporaryRepository::new("references");
+        let first = repository.commit_of("v1");
+        let checkouts = [
+            (GitReference::DefaultBranch, "second"),
+            (GitReference::Tag("v1".to_string()), "first"),
+            (GitReference::Branch("feature".to_string()), "third"),
+            (GitReference::Rev(first.clone()), "first"),
+            (GitReference::Rev(first[..10].to_string()), "first"),
+        ];
+        for (reference, expected_version) in checkouts {
+            let (commit, version) = repository.checkout(false, reference.clone(), None).unwrap();
+            assert_eq!(version, expected_version, "{reference:?}");
+            assert_eq!(commit.len(), 40);
+        }
+        let (commit, _) = repository
+            .checkout(false, GitReference::Tag("v1".to_string()), None)
+            .unwrap();
+        assert_eq!(commit, first);
+        // the checkout only contains the files of the repository
+        let checkout_path = repository
+            .cache(false)
+            .checkout_path(&repository.url, &first);
+        let mut files: Vec<String> = std::fs::read_dir(checkout_path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        assert_eq!(files, [".git", "version.txt"]);
+    }
+
+    #[test]
+    fn locked_commits_are_kept_when_the_branch_moves() {
+        let repository = TemporaryRepository::new("locked");
+        let (locked, _) = repository
+            .checkout(false, GitReference::DefaultBranch, None)
+            .unwrap();
+        repository.push_commit("fourth");
+        let (commit, version) = repository
+            .checkout(false, GitReference::DefaultBranch, Some(&locked))
+            .unwrap();
+        assert_eq!(
+            (commit.as_str(), version.as_str()),
+            (locked.as_str(), "second")
+        );
+        // without the lock, the new commit is fetched
+        let (_, version) = repository
+            .checkout(false, GitReference::DefaultBranch, None)
+            .unwrap();
+        assert_eq!(version, "fourth");
+    }
+
+    #[test]
+    fn unknown_references_are_reported() {
+        let repository = TemporaryRepository::new("unknown");
+        assert!(matches!(
+            repository.checkout(false, GitReference::Tag("v9".to_string()), None),
+            Err(GitError::ReferenceNotFound { reference, .. }) if reference == "refs/tags/v9"
+        ));
+    }
+
+    #[test]
+    fn offline_checkouts_only_use_the_cache() {
+        let repository = TemporaryRepository::new("offline");
+        assert!(matches!(
+            repository.checkout(true, GitReference::DefaultBranch, None),
+            Err(GitError::NotCachedOffline { url }) if url == repository.url
+        ));
+        repository
+            .checkout(false, GitReference::DefaultBranch, None)
+            .unwrap();
+        let (_, version) = repository
+            .checkout(true, GitReference::Tag("v1".to_string()), None)
+            .unwrap();
+        assert_eq!(version, "first");
+        // a tag pushed after the last fetch may exist, so it's not reported as missing
+        repository.git(&["tag", "v2"]);
+        let remote_path = repository.folder.join("remote.git");
+        repository.git(&["push", "--quiet", &remote_path.to_string_lossy(), "v2"]);
+        assert!(matches!(
+            repository.checkout(true, GitReference::Tag("v2".to_string()), None),
+            Err(GitError::ReferenceNotCachedOffline { reference, .. })
+                if reference == "refs/tags/v2"
+        ));
+    }
+}
@@ -0,0 +1,19 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum GitError {
+    #[error("`git` is not installed")]
+    GitNotInstalled,
+    #[error("`{command}` failed: {stderr}")]
+    CommandFailed { command: String, stderr: String },
+    #[error("`{reference}` not found in {url}")]
+    ReferenceNotFound { url: String, reference: String },
+    #[error("{url} is not in the git cache and cpm is offline")]
+    NotCachedOffline { url: String },
+    #[error(
+        "`{reference}` is not in the git cache of {url} and cpm is offline, run without `--offline` to fetch it"
+    )]
+    ReferenceNotCachedOffline { url: String, reference: String },
+    #[error("Io error: {0}")]
+    IOError(#[from] std::io::Error),
+}
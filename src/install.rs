@@ -0,0 +1,360 @@
+use std::path::{Path, PathBuf};
+
+use walkdir::WalkDir;
+
+use crate::{
+    manifest::PackageKind,
+    package::builder::{create_parent_folder, PackageBuilder},
+};
+
+pub mod errors;
+use errors::InstallError;
+
+/// The default installation prefix when `--prefix` isn't specified
+pub const DEFAULT_PREFIX: &str = "/usr/local";
+
+/// The standard folders of a prefix, which are kept when uninstalling leaves them empty
+const STANDARD_FOLDERS: &[&str] = &["bin", "lib", "lib/pkgconfig", "include", "share"];
+
+/// Where the files of a package are installed
+pub struct InstallLocation {
+    /// The prefix the installed files will be used from (e.g. `/usr/local`)
+    pub prefix: PathBuf,
+    /// Staging folder prepended to every installed path, used when packaging (e.g. `$DESTDIR`)
+    pub destdir: Option<PathBuf>,
+}
+
+impl InstallLocation {
+    /// Path of `relative_path` inside the prefix, as seen by the users of the installed files
+    pub fn prefix_path(&self, relative_path: impl AsRef<Path>) -> PathBuf {
+        self.prefix.join(relative_path)
+    }
+
+    /// Path of `relative_path` inside the prefix, with `destdir` prepended
+    pub fn staged_path(&self, relative_path: impl AsRef<Path>) -> PathBuf {
+        let prefix_path = self.prefix_path(relative_path);
+        match &self.destdir {
+            Some(destdir) => destdir.join(
+                prefix_path
+                    .strip_prefix("/")
+                    .unwrap_or(prefix_path.as_path()),
+            ),
+            None => prefix_path,
+        }
+    }
+
+    /// Path of the file that lists the files installed for the package `package_name`
+    pub fn install_manifest_path(&self, package_name: &str) -> PathBuf {
+        self.staged_path(format!("share/cpm/installed/{package_name}.txt"))
+    }
+}
+
+/// Installs the artifacts of a package that was already built and returns the installed files
+///
+/// The list of installed files is also written to the install manifest, relative to the prefix,
+/// so the package can be removed with [`uninstall_package`] (with or without `destdir`)
+pub fn install_package(
+    package_builder: &PackageBuilder,
+    location: &InstallLocation,
+) -> Result<Vec<PathBuf>, InstallError> {
+    let package = package_builder.package();
+    // the paths relative to the prefix
+    let mut installed_files = vec![];
+    // the artifacts keep the file names given by the target (e.g. `libname.a` or `name.lib`)
+    let artifact_path = package_builder.output_path();
+    let artifact_name = artifact_path
+        .file_name()
+        .expect("output path must have a file name");
+    let artifact_folder = match package.kind {
+        PackageKind::Executable => Path::new("bin"),
+        PackageKind::StaticLibrary | PackageKind::DynamicLibrary => Path::new("lib"),
+    };
+    match package_builder.soname_link_path() {
+        // ELF dynamic libraries are installed under their full version
+        // (e.g. `libname.so.1.2.3`), with links named after their soname and the library
+        Some(soname_link_path) => {
+            let mut real_name = artifact_name.to_os_string();
+            real_name.push(format!(".{}", package.version));
+            copy_file(
+                &artifact_path,
+                &location.staged_path(artifact_folder.join(&real_name)),
+            )?;
+            installed_files.push(artifact_folder.join(&real_name));
+            let soname = soname_link_path
+                .file_name()
+                .expect("soname link path must have a file name");
+            for link_name in [soname, artifact_name] {
+                let link_path = artifact_folder.join(link_name);
+                create_symlink(Path::new(&real_name), &location.staged_path(&link_path))?;
+                installed_files.push(link_path);
+            }
+        }
+        None => {
+            let destination = artifact_folder.join(artifact_name);
+            copy_file(&artifact_path, &location.staged_path(&destination))?;
+            installed_files.push(destination);
+        }
+    }
+    if package.kind != PackageKind::Executable {
+        let include_folder_path = package_builder.package_path().join(&package.include_folder);
+        let include_destination = Path::new("include").join(&package.name);
+        for entry in WalkDir::new(&include_folder_path) {
+            let entry = entry.map_err(std::io::Error::from)?;
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let relative_path = entry
+                .path()
+                .strip_prefix(&include_folder_path)
+                .expect("walkdir entries must be inside the include folder");
+            let destination = include_destination.join(relative_path);
+            copy_file(entry.path(), &location.staged_path(&destination))?;
+            installed_files.push(destination);
+        }
+
+        let pc_file_content = package_builder.pc_file_content(
+            &location.prefix_path("lib"),
+            &location.prefix_path("include").join(&package.name),
+        );
+        let pc_file_path = Path::new("lib/pkgconfig").join(format!("{}.pc", package.name));
+        let staged_pc_file_path = location.staged_path(&pc_file_path);
+        create_parent_folder(&staged_pc_file_path)?;
+        std::fs::write(&staged_pc_file_path, pc_file_content)?;
+        installed_files.push(pc_file_path);
+    }
+
+    let install_manifest_path = location.install_manifest_path(&package.name);
+    create_parent_folder(&install_manifest_path)?;
+    let install_manifest_content: String = installed_files
+        .iter()
+        .map(|path| format!("{}\n", path.display()))
+        .collect();
+    std::fs::write(install_manifest_path, install_manifest_content)?;
+    Ok(installed_files
+        .iter()
+        .map(|path| location.staged_path(path))
+        .collect())
+}
+
+/// Removes the files listed in the install manifest of the package `package_name`
+/// and returns the removed files
+///
+/// Folders left empty by the removal are removed as well, except the [`STANDARD_FOLDERS`]
+pub fn uninstall_package(
+    package_name: &str,
+    location: &InstallLocation,
+) -> Result<Vec<PathBuf>, InstallError> {
+    let install_manifest_path = location.install_manifest_path(package_name);
+    let install_manifest = match std::fs::read_to_string(&install_manifest_path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(InstallError::NotInstalled {
+                package_name: package_name.to_string(),
+                install_manifest_path,
+            })
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let mut removed_files = vec![];
+    for installed_file in install_manifest
+        .lines()
+        .map(|line| location.staged_path(line))
+    {
+        match std::fs::remove_file(&installed_file) {
+            Ok(()) => removed_files.push(installed_file.clone()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        remove_empty_parent_folders(&installed_file, location);
+    }
+    std::fs::remove_file(&install_manifest_path)?;
+    remove_empty_parent_folders(&install_manifest_path, location);
+    Ok(removed_files)
+}
+
+fn copy_file(from: &Path, to: &Path) -> Result<(), InstallError> {
+    create_parent_folder(to)?;
+    std::fs::copy(from, to).map_err(|error| InstallError::CopyFailed {
+        from: from.to_path_buf(),
+        to: to.to_path_buf(),
+        error,
+    })?;
+    Ok(())
+}
+
+fn create_symlink(target: &Path, link_path: &Path) -> Result<(), InstallError> {
+    if link_path.symlink_metadata().is_ok() {
+        std::fs::remove_file(link_path)?;
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::symlink(target, link_path)?;
+    #[cfg(not(unix))]
+    std::fs::copy(link_path.with_file_name(target), link_path)?;
+    Ok(())
+}
+
+/// Removes the empty folders of the prefix that contain `path`, from the innermost one
+fn remove_empty_parent_folders(path: &Path, location: &InstallLocation) {
+    let stop_folder = location.staged_path("");
+    let standard_folders: Vec<PathBuf> = STANDARD_FOLDERS
+        .iter()
+        .map(|folder| location.staged_path(folder))
+        .collect();
+    let mut folder = path.parent();
+    while let Some(current_folder) = folder {
+        if !current_folder.starts_with(&stop_folder)
+            || current_folder == stop_folder
+            || standard_folders
+                .iter()
+                .any(|folder| folder == current_folder)
+        {
+            break;
+        }
+        if std::fs::remove_dir(current_folder).is_err() {
+            break;
+        }
+        folder = current_folder.parent();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{target::TargetTriple, toolchain::Toolchain};
+
+    /// A library package with a public header and a (fake) built artifact, and a staging
+    /// folder, in a new temporary folder removed when dropped
+    struct TemporaryInstall {
+        folder: PathBuf,
+        package_builder: PackageBuilder,
+    }
+
+    impl TemporaryInstall {
+        fn new(name: &str, kind: &str, target: Option<&str>) -> Self {
+            let folder =
+                std::env::temp_dir().join(format!("cpm-install-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&folder);
+            let package_path = folder.join("json");
+            std::fs::create_dir_all(package_path.join("include")).unwrap();
+            std::fs::write(
+                package_path.join("cpm.toml"),
+                format!("[package]\nname = \"json\"\nversion = \"1.2.3\"\nkind = \"{kind}\"\n"),
+            )
+            .unwrap();
+            std::fs::write(package_path.join("include/json.h"), "int parse(void);\n").unwrap();
+            let mut package_builder = PackageBuilder::new(&package_path, &package_path).unwrap();
+            if let Some(target) = target {
+                let toolchain = Toolchain {
+                    target: TargetTriple::parse(target).unwrap(),
+                    explicit_target: true,
+                    ..Toolchain::host()
+                };
+                package_builder.set_toolchain(toolchain).unwrap();
+            }
+            let artifact_path = package_builder.output_path();
+            create_parent_folder(&artifact_path).unwrap();
+            std::fs::write(artifact_path, "artifact").unwrap();
+            Self {
+                folder,
+                package_builder,
+            }
+        }
+
+        fn location(&self) -> InstallLocation {
+            InstallLocation {
+                prefix: PathBuf::from("/usr/local"),
+                destdir: Some(self.folder.join("stage")),
+            }
+        }
+
+        fn staged_files(&self) -> Vec<String> {
+            let stage_path = self.folder.join("stage/usr/local");
+            let mut files: Vec<String> = WalkDir::new(&stage_path)
+                .into_iter()
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| !entry.file_type().is_dir())
+                .map(|entry| {
+                    let path = entry.path().strip_prefix(&stage_path).unwrap();
+                    path.display().to_string()
+                })
+                .collect();
+            files.sort();
+            files
+        }
+    }
+
+    impl Drop for TemporaryInstall {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.folder);
+        }
+    }
+
+    #[test]
+    fn packages_are_staged_in_destdir_and_uninstalled() {
+        let install = TemporaryInstall::new("destdir", "staticlib", None);
+        let location = install.location();
+        let installed_files = install_package(&install.package_builder, &location).unwrap();
+        assert!(installed_files
+            .iter()
+            .all(|path| path.starts_with(install.folder.join("stage/usr/local"))));
+        assert_eq!(
+            install.staged_files(),
+            [
+                "include/json/json.h",
+                "lib/libjson.a",
+                "lib/pkgconfig/json.pc",
+                "share/cpm/installed/json.txt"
+            ]
+        );
+        // the installed files are used from the prefix, without the staging folder
+        let pc_file =
+            std::fs::read_to_string(location.staged_path("lib/pkgconfig/json.pc")).unwrap();
+        assert!(pc_file.starts_with("libdir=/usr/local/lib\n"), "{pc_file}");
+        let install_manifest =
+            std::fs::read_to_string(location.install_manifest_path("json")).unwrap();
+        assert!(
+            install_manifest.starts_with("lib/libjson.a\n"),
+            "{install_manifest}"
+        );
+
+        let removed_files = uninstall_package("json", &location).unwrap();
+        assert_eq!(removed_files.len(), 3);
+        assert!(install.staged_files().is_empty());
+        // the standard folders are kept, the folders of the package are removed
+        assert!(location.staged_path("lib/pkgconfig").is_dir());
+        assert!(!location.staged_path("include/json").exists());
+        assert!(matches!(
+            uninstall_package("json", &location),
+            Err(InstallError::NotInstalled { .. })
+        ));
+    }
+
+    #[test]
+    fn elf_dynamic_libraries_are_installed_with_their_soname() {
+        let install = TemporaryInstall::new("soname", "dynlib", Some("x86_64-linux-gnu"));
+        install_package(&install.package_builder, &install.location()).unwrap();
+        let lib_path = install.location().staged_path("lib");
+        assert!(lib_path.join("libjson.so.1.2.3").is_file());
+        for link_name in ["libjson.so.1", "libjson.so"] {
+            let link_target = std::fs::read_link(lib_path.join(link_name)).unwrap();
+            assert_eq!(link_target, Path::new("libjson.so.1.2.3"));
+        }
+    }
+
+    #[test]
+    fn libraries_keep_the_file_names_of_their_target() {
+        let install = TemporaryInstall::new("dylib", "dynlib", Some("aarch64-apple-darwin"));
+        install_package(&install.package_builder, &install.location()).unwrap();
+        assert!(install
+            .staged_files()
+            .contains(&"lib/libjson.dylib".to_string()));
+        assert!(!install
+            .staged_files()
+            .iter()
+            .any(|file| file.contains(".so")));
+
+        let install = TemporaryInstall::new("msvc", "staticlib", Some("x86_64-pc-windows-msvc"));
+        install_package(&install.package_builder, &install.location()).unwrap();
+        assert!(install.staged_files().contains(&"lib/json.lib".to_string()));
+    }
+}
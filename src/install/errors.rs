@@ -0,0 +1,22 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum InstallError {
+    #[error("failed to copy {from:?} to {to:?}: {error}")]
+    CopyFailed {
+        from: PathBuf,
+        to: PathBuf,
+        error: std::io::Error,
+    },
+    #[error(
+        "package `{package_name}` is not installed ({install_manifest_path:?} does not exist)"
+    )]
+    NotInstalled {
+        package_name: String,
+        install_manifest_path: PathBuf,
+    },
+    #[error("Io error: {0}")]
+    IOError(#[from] std::io::Error),
+}
@@ -0,0 +1,147 @@
+use std::{io::ErrorKind, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::filenames::LOCKFILE_NAME;
+
+pub mod errors;
+use errors::LockfileError;
+
+const LOCKFILE_VERSION: u32 = 1;
+
+/// The `cpm.lock` file of a workspace
+///
+/// It pins the exact version and checksum of every dependency that doesn't come
+/// from the workspace itself, so builds are reproducible
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
+pub struct Lockfile {
+    pub version: u32,
+    #[serde(default, rename = "package")]
+    pub packages: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Clone, Debug)]
+pub struct LockedPackage {
+    pub name: String,
+    pub version: semver::Version,
+    /// Where the package comes from (e.g. `registry` or `git+<url>#<commit>`)
+    pub source: String,
+    /// SHA-256 of the package tarball
+    pub checksum: Option<String>,
+    /// Names of the dependencies of this package
+    #[serde(default)]
+    pub dependencies: Vec<String>,
+}
+
+impl Default for Lockfile {
+    fn default() -> Self {
+        Self {
+            version: LOCKFILE_VERSION,
+            packages: vec![],
+        }
+    }
+}
+
+impl Lockfile {
+    /// Loads the lockfile of a workspace, returns `None` when it doesn't exist
+    pub fn load(workspace_path: &Path) -> Result<Option<Self>, LockfileError> {
+        let lockfile_path = workspace_path.join(LOCKFILE_NAME);
+        let content = match std::fs::read_to_string(&lockfile_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        toml::from_str(&content)
+            .map(Some)
+            .map_err(|error| LockfileError::Invalid {
+                lockfile_path,
+                error,
+            })
+    }
+
+    /// Writes the lockfile of a workspace
+    pub fn save(&self, workspace_path: &Path) -> Result<(), LockfileError> {
+        let content = format!(
+            "# This file is generated by cpm, do not edit it by hand.\n{}",
+            toml::to_string(self)?
+        );
+        std::fs::write(workspace_path.join(LOCKFILE_NAME), content)?;
+        Ok(())
+    }
+
+    /// Finds the locked package with the specified name
+    pub fn package(&self, name: &str) -> Option<&LockedPackage> {
+        self.packages.iter().find(|p| p.name == name)
+    }
+
+    /// Removes the locked package with the specified name and returns it
+    pub fn remove_package(&mut self, name: &str) -> Option<LockedPackage> {
+        let index = self.packages.iter().position(|p| p.name == name)?;
+        Some(self.packages.remove(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOCKFILE: &str = r#"# This file is generated by cpm, do not edit it by hand.
+version = 1
+
+[[package]]
+name = "json"
+version = "1.2.0"
+source = "registry"
+checksum = "ab12"
+dependencies = ["zlib"]
+
+[[package]]
+name = "zlib"
+version = "1.3.1"
+source = "git+https://github.com/madler/zlib?tag=v1.3.1#51b7f2abdade71cd9bb0e7a373ef2610ec6f9daf"
+dependencies = []
+"#;
+
+    #[test]
+    fn lockfiles_are_saved_as_they_were_loaded() {
+        let workspace_path =
+            std::env::temp_dir().join(format!("cpm-lockfile-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&workspace_path);
+        std::fs::create_dir_all(&workspace_path).unwrap();
+        let lockfile_path = workspace_path.join(LOCKFILE_NAME);
+        std::fs::write(&lockfile_path, LOCKFILE).unwrap();
+
+        let lockfile = Lockfile::load(&workspace_path).unwrap().unwrap();
+        assert_eq!(lockfile.version, LOCKFILE_VERSION);
+        assert_eq!(lockfile.package("json").unwrap().dependencies, ["zlib"]);
+        assert_eq!(lockfile.package("zlib").unwrap().checksum, None);
+        lockfile.save(&workspace_path).unwrap();
+        assert_eq!(std::fs::read_to_string(&lockfile_path).unwrap(), LOCKFILE);
+        assert_eq!(Lockfile::load(&workspace_path).unwrap(), Some(lockfile));
+        std::fs::remove_dir_all(workspace_path).unwrap();
+    }
+
+    #[test]
+    fn missing_lockfiles_are_not_an_error() {
+        let workspace_path =
+            std::env::temp_dir().join(format!("cpm-missing-lockfile-{}", std::process::id()));
+        assert_eq!(Lockfile::load(&workspace_path).unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_lockfiles_are_reported() {
+        let workspace_path =
+            std::env::temp_dir().join(format!("cpm-invalid-lockfile-{}", std::process::id()));
+        std::fs::create_dir_all(&workspace_path).unwrap();
+        std::fs::write(
+            workspace_path.join(LOCKFILE_NAME),
+            "version = 1\n[[package]]\nname = \"json\"\nversion = \"latest\"\n",
+        )
+        .unwrap();
+        assert!(matches!(
+            Lockfile::load(&workspace_path),
+            Err(LockfileError::Invalid { .. })
+        ));
+        std::fs::remove_dir_all(workspace_path).unwrap();
+    }
+}
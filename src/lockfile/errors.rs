@@ -0,0 +1,16 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum LockfileError {
+    #[error("invalid lockfile {lockfile_path:?}: {error}")]
+    Invalid {
+        lockfile_path: PathBuf,
+        error: toml::de::Error,
+    },
+    #[error("failed to serialize lockfile: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Io error: {0}")]
+    IOError(#[from] std::io::Error),
+}
@@ -1,22 +1,320 @@
+mod bench;
 mod commands;
 mod compiler;
+mod coverage;
+mod dependencies;
+mod external;
 mod filenames;
+mod fuzz;
+mod git;
+mod install;
+mod lockfile;
 mod manifest;
+mod metadata;
 mod package;
+mod packaging;
+mod pgo;
+mod pkg_config;
+mod process;
+mod profile;
+mod registry;
+mod size;
+mod target;
+mod toolchain;
+mod vendor;
+mod watch;
 use std::path::PathBuf;
 
-use clap::{Parser, Subcommand};
+use clap::{Args, Parser, Subcommand};
+use commands::WatchMode;
+
+/// Options shared by the commands that build packages
+#[derive(Args, Debug, Clone, Default)]
+pub struct BuildOptions {
+    /// Don't access the network, fail when a dependency isn't already cached
+    #[arg(long)]
+    pub offline: bool,
+    /// Build with the `release` profile
+    #[arg(long, conflicts_with = "profile")]
+    pub release: bool,
+    /// Build with the specified profile
+    #[arg(long, value_name = "NAME")]
+    pub profile: Option<String>,
+    /// Build vendored dependencies even when they don't match their checksums
+    #[arg(long)]
+    pub allow_modified_vendor: bool,
+    /// Build for the target triple with its `[toolchain.<triple>]`, instead of the host
+    #[arg(long, value_name = "TRIPLE")]
+    pub target: Option<String>,
+    /// Build with sanitizers, separated by commas (e.g. `address,undefined`)
+    #[arg(long, value_enum, value_delimiter = ',', value_name = "SANITIZERS")]
+    pub sanitize: Vec<profile::Sanitizer>,
+    /// Instrument the build for coverage, set by `cpm test --coverage`
+    #[arg(skip)]
+    pub coverage: bool,
+}
+
+impl BuildOptions {
+    /// The name of the selected build profile
+    pub fn profile_name(&self) -> &str {
+        match (&self.profile, self.release) {
+            (Some(profile), _) => profile,
+            (None, true) => profile::RELEASE_PROFILE,
+            (None, false) => profile::DEFAULT_PROFILE,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum FuzzCommand {
+    #[command(about = "List the fuzz targets of the packages")]
+    List {
+        #[arg(short, long)]
+        package: Option<String>,
+    },
+    #[command(
+        about = "Build a fuzz target with Clang and run it on its corpus in fuzz/corpus/<TARGET>"
+    )]
+    Run {
+        /// The fuzz target `fuzz/<TARGET>.c`
+        #[arg(value_name = "TARGET")]
+        fuzz_target: String,
+        #[arg(short, long)]
+        package: Option<String>,
+        /// Arguments passed to libFuzzer, after `--` (e.g. `-max_total_time=60`)
+        #[arg(last = true)]
+        args: Vec<String>,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(about = "Minimize a crash input of a fuzz target and add it as a regression test")]
+    Tmin {
+        /// The fuzz target `fuzz/<TARGET>.c`
+        #[arg(value_name = "TARGET")]
+        fuzz_target: String,
+        /// The crash input, usually in fuzz/artifacts/<TARGET>
+        input: PathBuf,
+        #[arg(short, long)]
+        package: Option<String>,
+        /// Number of attempts to minimize the input
+        #[arg(long, default_value_t = 255)]
+        runs: u32,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+}
 
 #[derive(Subcommand, Debug, Clone)]
 pub enum CPMOperation {
     #[command(about = "Create a new executable package")]
     Init { path: PathBuf },
     #[command(about = "Build a package")]
-    Build,
+    Build {
+        #[arg(short, long)]
+        package: Option<String>,
+        /// Rebuild the packages when their sources, headers or manifest change
+        #[arg(long)]
+        watch: bool,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
     #[command(about = "Build and run a executable package")]
     Run {
         #[arg(short, long)]
         package: Option<String>,
+        /// Run the example `examples/<NAME>.c` of the package
+        #[arg(long, value_name = "NAME")]
+        example: Option<String>,
+        /// Arguments passed to the program, after `--`
+        #[arg(last = true)]
+        args: Vec<String>,
+        /// Rebuild the package when its sources, headers or manifest change, and restart the program
+        #[arg(long)]
+        watch: bool,
+        /// Only build the program, used by `--watch` before restarting it
+        #[arg(long, hide = true, conflicts_with = "watch")]
+        build_only: bool,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(about = "Build and run the tests of the packages (each file of their tests folder)")]
+    Test {
+        #[arg(short, long)]
+        package: Option<String>,
+        /// Only run the tests whose name contains this string
+        filter: Option<String>,
+        /// Measure the lines and branches executed by the tests, reported in `target/coverage`
+        #[arg(long)]
+        coverage: bool,
+        /// Fail when the line coverage of the tested packages is below this percentage
+        #[arg(long, value_name = "PERCENT", requires = "coverage")]
+        fail_under: Option<f64>,
+        /// Run the tests again when the sources, headers, tests or manifest of a package change
+        #[arg(long)]
+        watch: bool,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(about = "Build with the release profile and run the benchmarks of the packages")]
+    Bench {
+        #[arg(short, long)]
+        package: Option<String>,
+        /// Only run the benchmarks whose name or file name contains this string
+        filter: Option<String>,
+        /// Save the results as this baseline and compare them with its previous results
+        #[arg(long, value_name = "NAME", conflicts_with = "baseline")]
+        save_baseline: Option<String>,
+        /// Compare the results with this saved baseline, without replacing it
+        #[arg(long, value_name = "NAME")]
+        baseline: Option<String>,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(
+        about = "Build the packages with profile-guided optimization, trained by their [pgo] workloads",
+        long_about = "Build the packages with profile-guided optimization, trained by their [pgo] workloads\n\n\
+            The optimized outputs are written to the pgo-use folder of the profile output folder \
+            (e.g. target/release/pgo-use), the outputs of `cpm build --release` are left unchanged"
+    )]
+    Pgo {
+        #[arg(short, long)]
+        package: Option<String>,
+        /// Use the profiles of the previous training instead of training again
+        #[arg(long)]
+        skip_training: bool,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(about = "Fuzz the packages with libFuzzer (each file of their fuzz folder)")]
+    Fuzz {
+        #[command(subcommand)]
+        command: FuzzCommand,
+    },
+    #[command(about = "Create the source tarball of the packages in target/package")]
+    Package {
+        #[arg(short, long)]
+        package: Option<String>,
+        /// Don't check that the tarball builds on its own
+        #[arg(long)]
+        no_verify: bool,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(about = "Package and publish the packages to the registry")]
+    Publish {
+        #[arg(short, long)]
+        package: Option<String>,
+        /// Don't check that the tarball builds on its own
+        #[arg(long)]
+        no_verify: bool,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(about = "Build and install the packages of the workspace")]
+    Install {
+        #[arg(short, long)]
+        package: Option<String>,
+        #[arg(long, default_value = install::DEFAULT_PREFIX)]
+        prefix: PathBuf,
+        #[arg(long, env = "DESTDIR")]
+        destdir: Option<PathBuf>,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(about = "Add a dependency to the manifest of a package")]
+    Add {
+        /// The dependency, with an optional version requirement (e.g. `json_parser@1.2`)
+        dependency: String,
+        /// Path of the dependency, relative to the package folder
+        #[arg(long)]
+        path: Option<PathBuf>,
+        /// URL of the git repository of the dependency
+        #[arg(long, conflicts_with = "path")]
+        git: Option<String>,
+        /// Commit of the git repository to use
+        #[arg(long, requires = "git", conflicts_with_all = ["tag", "branch"])]
+        rev: Option<String>,
+        /// Tag of the git repository to use
+        #[arg(long, requires = "git", conflicts_with = "branch")]
+        tag: Option<String>,
+        /// Branch of the git repository to use
+        #[arg(long, requires = "git")]
+        branch: Option<String>,
+        /// The package to add the dependency to, the package of the current folder by default
+        #[arg(short, long)]
+        package: Option<String>,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(about = "Remove a dependency from the manifest of a package")]
+    Remove {
+        dependency: String,
+        /// The package to remove the dependency from, the package of the current folder by default
+        #[arg(short, long)]
+        package: Option<String>,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(about = "Update the versions pinned in cpm.lock")]
+    Update {
+        /// Only update this package
+        #[arg(short, long)]
+        package: Option<String>,
+        /// Pin the package to this version (or commit for git packages)
+        #[arg(long, requires = "package", value_name = "VERSION")]
+        precise: Option<String>,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(about = "Show the registry dependencies that have newer versions")]
+    Outdated {
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(about = "Show the dependency tree of the workspace packages")]
+    Tree {
+        /// Only show the tree of this package
+        #[arg(short, long)]
+        package: Option<String>,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(about = "Print the resolved dependency graph of the workspace as JSON")]
+    Metadata {
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(
+        about = "Build the packages and show the sizes of their sections and largest symbols"
+    )]
+    Size {
+        #[arg(short, long)]
+        package: Option<String>,
+        /// Number of symbols to show
+        #[arg(long, default_value_t = 10, value_name = "COUNT")]
+        symbols: usize,
+        /// Show the sizes of every object file and dependency linked into the package
+        #[arg(long)]
+        by_object: bool,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(about = "Copy the registry and git dependencies into the workspace")]
+    Vendor {
+        /// Folder of the vendored dependencies, relative to the workspace
+        #[arg(default_value = vendor::DEFAULT_VENDOR_FOLDER)]
+        path: PathBuf,
+        #[command(flatten)]
+        options: BuildOptions,
+    },
+    #[command(about = "Remove the files installed by `cpm install`")]
+    Uninstall {
+        #[arg(short, long)]
+        package: Option<String>,
+        #[arg(long, default_value = install::DEFAULT_PREFIX)]
+        prefix: PathBuf,
+        #[arg(long, env = "DESTDIR")]
+        destdir: Option<PathBuf>,
     },
 }
 #[derive(Parser, Debug, Clone)]
@@ -30,9 +328,148 @@ fn main() {
     let args = CPMArguments::parse();
     match args.op {
         CPMOperation::Init { path } => commands::init(path),
-        CPMOperation::Build => {
-            let _ = commands::build_project();
-        }
-        CPMOperation::Run { package } => commands::run_project(package),
+        CPMOperation::Build {
+            package,
+            watch: true,
+            options,
+        } => commands::watch_project(WatchMode::Build, package, options),
+        CPMOperation::Build {
+            package,
+            watch: false,
+            options,
+        } => commands::build_project(package, options),
+        CPMOperation::Run {
+            package,
+            example,
+            args,
+            watch: true,
+            options,
+            ..
+        } => commands::watch_project(WatchMode::Run { example, args }, package, options),
+        CPMOperation::Run {
+            package,
+            example: Some(example),
+            args,
+            build_only,
+            options,
+            ..
+        } => commands::run_example(package, example, args, build_only, options),
+        CPMOperation::Run {
+            package,
+            example: None,
+            args,
+            build_only,
+            options,
+            ..
+        } => commands::run_project(package, args, build_only, options),
+        CPMOperation::Test {
+            package,
+            watch: true,
+            options,
+            ..
+        } => commands::watch_project(WatchMode::Test, package, options),
+        CPMOperation::Test {
+            package,
+            filter,
+            coverage,
+            fail_under,
+            options,
+            ..
+        } => commands::test_project(package, filter, coverage, fail_under, options),
+        CPMOperation::Bench {
+            package,
+            filter,
+            save_baseline,
+            baseline,
+            options,
+        } => commands::bench_project(package, filter, save_baseline, baseline, options),
+        CPMOperation::Pgo {
+            package,
+            skip_training,
+            options,
+        } => commands::pgo_project(package, skip_training, options),
+        CPMOperation::Fuzz { command } => match command {
+            FuzzCommand::List { package } => commands::list_fuzz_targets(package),
+            FuzzCommand::Run {
+                fuzz_target,
+                package,
+                args,
+                options,
+            } => commands::run_fuzz_target(package, fuzz_target, args, options),
+            FuzzCommand::Tmin {
+                fuzz_target,
+                input,
+                package,
+                runs,
+                options,
+            } => commands::minimize_fuzz_input(package, fuzz_target, input, runs, options),
+        },
+        CPMOperation::Package {
+            package,
+            no_verify,
+            options,
+        } => commands::package_project(package, no_verify, options),
+        CPMOperation::Publish {
+            package,
+            no_verify,
+            options,
+        } => commands::publish_project(package, no_verify, options),
+        CPMOperation::Install {
+            package,
+            prefix,
+            destdir,
+            options,
+        } => commands::install_project(
+            package,
+            install::InstallLocation { prefix, destdir },
+            options,
+        ),
+        CPMOperation::Add {
+            dependency,
+            path,
+            git,
+            rev,
+            tag,
+            branch,
+            package,
+            options,
+        } => commands::add_dependency(
+            package,
+            dependency,
+            manifest::edit::DependencyEntry {
+                version: None,
+                path,
+                git,
+                rev,
+                tag,
+                branch,
+            },
+            options,
+        ),
+        CPMOperation::Remove {
+            dependency,
+            package,
+            options,
+        } => commands::remove_dependency(package, dependency, options),
+        CPMOperation::Update {
+            package,
+            precise,
+            options,
+        } => commands::update_dependencies(package, precise, options),
+        CPMOperation::Outdated { options } => commands::outdated_dependencies(options),
+        CPMOperation::Tree { package, options } => commands::print_tree(package, options),
+        CPMOperation::Metadata { options } => commands::print_metadata(options),
+        CPMOperation::Size {
+            package,
+            symbols,
+            by_object,
+            options,
+        } => commands::print_sizes(package, symbols, by_object, options),
+        CPMOperation::Vendor { path, options } => commands::vendor_project(path, options),
+        CPMOperation::Uninstall {
+            package,
+            prefix,
+            destdir,
+        } => commands::uninstall_project(package, install::InstallLocation { prefix, destdir }),
     }
 }
@@ -1,6 +1,14 @@
-use std::{io::ErrorKind, path::PathBuf};
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
 
 use serde::{Deserialize, Serialize};
+
+use crate::git::GitReference;
+use crate::target::{cfg::target_section_matches, errors::TargetError, TargetTriple};
+pub mod edit;
 pub mod errors;
 fn default_src_folder() -> PathBuf {
     "src".into()
@@ -8,7 +16,7 @@ fn default_src_folder() -> PathBuf {
 fn default_include_folder() -> PathBuf {
     "include".into()
 }
-#[derive(Deserialize, Serialize, Default, PartialEq, Eq)]
+#[derive(Deserialize, Serialize, Default, PartialEq, Eq, Clone, Copy, Debug)]
 pub enum PackageKind {
     #[default]
     #[serde(rename = "exe")]
@@ -18,13 +26,18 @@ pub enum PackageKind {
     #[serde(rename = "dynlib")]
     DynamicLibrary,
 }
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Package {
     /// This name of this package
     /// It must be in **snake_case**
     pub name: String,
     /// Semver version of the package
     pub version: semver::Version,
+    /// A short description of the package
+    ///
+    /// Used in the generated pkg-config file of library packages
+    #[serde(default)]
+    pub description: Option<String>,
     /// The folder that contains all the source files
     /// to compile
     #[serde(default = "default_src_folder")]
@@ -42,30 +55,494 @@ pub struct Package {
     /// of this package
     #[serde(default)]
     pub additional_linker_flags: Vec<String>,
+    /// Macros defined when compiling this package (e.g. `NDEBUG` or `LOG_LEVEL=2`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub defines: Vec<String>,
+    /// Glob patterns of additional source files to compile, relative to the package folder
+    /// (e.g. `platform/linux/*.c`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
     /// Links with math library when set to `true`
     #[serde(default)]
     pub enable_math_library: bool,
     /// Links with pthread library when set to `true`
     #[serde(default)]
     pub enable_pthread_library: bool,
-    /// Disables linking with the std library when set to `true`
+    /// Builds the package for an environment without the C standard library (e.g. firmware)
+    ///
+    /// Sources are compiled with `-ffreestanding` and programs are linked with `-nostdlib`
+    #[serde(default, alias = "disable_std_library")]
+    pub freestanding: bool,
+    /// Linker script of the program, relative to the package folder
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub linker_script: Option<PathBuf>,
+    /// Symbol where the program starts, instead of the default entry point
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub entry: Option<String>,
+    /// Writes the map file of the program next to it (`<name>.map`) when set to `true`
     #[serde(default)]
-    pub disable_std_library: bool,
+    pub map_file: bool,
+    /// Images created from the executable with `objcopy` after linking it,
+    /// next to it (e.g. `["bin", "hex"]`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub images: Vec<ImageFormat>,
+    /// Maximum sizes of the sections of the output, in bytes
+    ///
+    /// The build fails when a section exceeds its limit
+    #[serde(
+        default,
+        rename = "size-budget",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub size_budget: Option<SizeBudget>,
 
     /// Sets the output artifact of this package (e.g. lib or exe)
     #[serde(default)]
     pub kind: PackageKind,
 }
 
-#[derive(Deserialize, Serialize)]
+/// A code generation rule (e.g. bison, flex or xxd)
+///
+/// Every file matched by `input` is turned into the files described by `outputs`
+/// by running `command`. The following placeholders are replaced in `command` and `outputs`:
+/// - `{input}`: absolute path of the matched input file
+/// - `{stem}`: file name of the input without its extension
+/// - `{name}`: file name of the input
+/// - `{out_dir}`: folder where the generated files must be placed
+///
+/// Generated `.c` files are compiled with the package and the output folder
+/// is added to the include folders.
+#[derive(Deserialize, Serialize, Clone)]
+pub struct Rule {
+    /// Glob pattern, relative to the package folder, that matches the inputs of this rule
+    pub input: String,
+    /// The program to run followed by its arguments
+    pub command: Vec<String>,
+    /// Files produced by the command, relative to `{out_dir}`
+    pub outputs: Vec<String>,
+}
+
+/// A dependency of a package
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+pub enum Dependency {
+    /// A version requirement resolved against the registry (e.g. `json_parser = "1.2"`)
+    Version(semver::VersionReq),
+    /// A dependency with more details (e.g. `json_parser = { path = "../json_parser" }`)
+    Detailed(Box<DetailedDependency>),
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct DetailedDependency {
+    /// Version requirement of the dependency
+    ///
+    /// Required for registry dependencies, checked against the package version for other sources
+    pub version: Option<semver::VersionReq>,
+    /// Path to the folder of the dependency, relative to the package folder
+    pub path: Option<PathBuf>,
+    /// URL of the git repository of the dependency
+    pub git: Option<String>,
+    /// Commit (or other revision) of the git repository to use
+    pub rev: Option<String>,
+    /// Tag of the git repository to use
+    pub tag: Option<String>,
+    /// Branch of the git repository to use
+    pub branch: Option<String>,
+    /// Build system of a dependency that isn't a cpm package
+    ///
+    /// The dependency must be a path or git dependency, it's built in `target/deps/<name>`
+    pub build: Option<BuildSystem>,
+    /// Include folders produced by the build system, relative to its output folder
+    ///
+    /// The output folder is the install prefix for `cmake` and `autotools`, the copy of the
+    /// dependency built by `make` and the dependency folder itself for `sources`.
+    /// Defaults to `["include"]`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<PathBuf>,
+    /// Libraries produced by the build system, relative to its output folder
+    ///
+    /// Defaults to `["lib/lib<name>.a"]` for `cmake` and `autotools` and `["lib<name>.a"]` for `make`.
+    /// Ignored for `sources`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub libs: Vec<PathBuf>,
+    /// Glob patterns of the files compiled with `build = "sources"`, relative to the dependency folder
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
+    /// Additional arguments passed to the build system (e.g. `["-DZLIB_BUILD_EXAMPLES=OFF"]` for cmake)
+    ///
+    /// They're passed to `cmake` when configuring, to `make`, to `./configure`,
+    /// or to the compiler for `sources`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub build_args: Vec<String>,
+}
+
+/// The build system used to build a dependency that isn't a cpm package
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildSystem {
+    /// Configured, built and installed with `cmake`
+    CMake,
+    /// Built with `make` in a copy of the dependency
+    Make,
+    /// Configured with `./configure` (generated with `autoreconf` if needed) then built with `make install`
+    Autotools,
+    /// The listed `sources` are compiled by cpm into a static library
+    Sources,
+}
+
+/// Maximum sizes of the sections of a package output (`[package.size-budget]`), in bytes
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SizeBudget {
+    /// Code and read-only data (flash)
+    pub text: Option<u64>,
+    /// Initialized data (flash and RAM)
+    pub data: Option<u64>,
+    /// Zero-initialized data (RAM)
+    pub bss: Option<u64>,
+}
+
+/// The format of an image created from an executable with `objcopy`
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ImageFormat {
+    /// Raw binary (`.bin`)
+    Bin,
+    /// Intel HEX (`.hex`)
+    Hex,
+}
+
+impl ImageFormat {
+    /// The name of the format for `objcopy -O`
+    pub fn objcopy_format(self) -> &'static str {
+        match self {
+            ImageFormat::Bin => "binary",
+            ImageFormat::Hex => "ihex",
+        }
+    }
+
+    /// The extension of the image, without the dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Bin => "bin",
+            ImageFormat::Hex => "hex",
+        }
+    }
+}
+
+impl Dependency {
+    /// The version requirement of this dependency, `*` if there's none
+    pub fn version_requirement(&self) -> semver::VersionReq {
+        match self {
+            Dependency::Version(requirement) => requirement.clone(),
+            Dependency::Detailed(detailed) => detailed.version.clone().unwrap_or_default(),
+        }
+    }
+
+    /// The path of this dependency, if it's a path dependency
+    pub fn path(&self) -> Option<&PathBuf> {
+        match self {
+            Dependency::Version(_) => None,
+            Dependency::Detailed(detailed) => detailed.path.as_ref(),
+        }
+    }
+
+    /// This dependency with its path, if it's a path dependency, resolved against `base_path`
+    pub fn relative_to(&self, base_path: &Path) -> Self {
+        match self {
+            Dependency::Detailed(detailed) if detailed.path.is_some() => {
+                let mut detailed = detailed.clone();
+                detailed.path = detailed.path.map(|path| base_path.join(path));
+                Dependency::Detailed(detailed)
+            }
+            _ => self.clone(),
+        }
+    }
+
+    /// The detailed settings of this dependency, if it's built by another build system
+    pub fn external_build(&self) -> Option<&DetailedDependency> {
+        match self {
+            Dependency::Detailed(detailed) if detailed.build.is_some() => Some(detailed),
+            _ => None,
+        }
+    }
+
+    /// The repository URL and revision of this dependency, if it's a git dependency
+    pub fn git(&self) -> Option<(&str, GitReference)> {
+        let Dependency::Detailed(detailed) = self else {
+            return None;
+        };
+        let reference = if let Some(rev) = &detailed.rev {
+            GitReference::Rev(rev.clone())
+        } else if let Some(tag) = &detailed.tag {
+            GitReference::Tag(tag.clone())
+        } else if let Some(branch) = &detailed.branch {
+            GitReference::Branch(branch.clone())
+        } else {
+            GitReference::DefaultBranch
+        };
+        Some((detailed.git.as_deref()?, reference))
+    }
+}
+
+/// Settings of a `[target.<key>]` section, which only apply when building for the targets
+/// that match the key
+///
+/// The key is a target triple (e.g. `aarch64-unknown-linux-gnu`) or a `cfg(...)` expression
+/// (e.g. `cfg(target_os = "linux")`), see [`crate::target::cfg::CfgExpression`]
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct TargetSettings {
+    /// Added to `additional_compiler_flags`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_compiler_flags: Vec<String>,
+    /// Added to `additional_linker_flags`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub additional_linker_flags: Vec<String>,
+    /// Added to `defines`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub defines: Vec<String>,
+    /// Added to `sources`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub sources: Vec<String>,
+    /// Overrides `enable_math_library`
+    pub enable_math_library: Option<bool>,
+    /// Overrides `enable_pthread_library`
+    pub enable_pthread_library: Option<bool>,
+    /// Added to the dependencies, replacing the dependencies of the same name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, Dependency>,
+    /// Added to the dev-dependencies, replacing the dev-dependencies of the same name
+    #[serde(
+        default,
+        rename = "dev-dependencies",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub dev_dependencies: BTreeMap<String, Dependency>,
+    /// Added to the build-dependencies, replacing the build-dependencies of the same name
+    #[serde(
+        default,
+        rename = "build-dependencies",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub build_dependencies: BTreeMap<String, Dependency>,
+}
+
+/// Settings of a build profile (`[profile.<name>]`)
+///
+/// Profiles are read from the workspace manifest
+#[derive(Deserialize, Serialize, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct Profile {
+    /// The profile this profile takes its unset settings from
+    ///
+    /// Required for profiles other than `dev` and `release`
+    pub inherits: Option<String>,
+    /// Optimization level (`0`, `1`, `2`, `3`, `s`, `z` or `g`)
+    pub opt_level: Option<String>,
+    /// Generate debug information when set to `true`
+    pub debug: Option<bool>,
+    /// Additional flags for the compiler when using this profile
+    #[serde(default)]
+    pub compiler_flags: Vec<String>,
+    /// Additional flags for the linker when using this profile
+    #[serde(default)]
+    pub linker_flags: Vec<String>,
+    /// Link-time optimization (`off`, `thin` or `fat`)
+    pub lto: Option<Lto>,
+    /// The linker used by the compiler driver (`bfd`, `gold`, `lld` or `mold`)
+    pub linker: Option<Linker>,
+    /// What is stripped from executables and dynamic libraries (`none`, `debuginfo` or `symbols`)
+    pub strip: Option<Strip>,
+    /// Write the debug information to `.dwo` files next to the objects instead of linking it
+    pub split_debuginfo: Option<bool>,
+}
+
+/// Link-time optimization of a profile
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Lto {
+    #[default]
+    Off,
+    /// Summary-based optimization of the whole program, in parallel (ThinLTO, Clang only)
+    Thin,
+    /// Optimization of the whole program as a single unit
+    Fat,
+}
+
+/// A linker selected with `-fuse-ld=`
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Linker {
+    /// The GNU linker
+    Bfd,
+    /// The GNU gold linker
+    Gold,
+    /// The LLVM linker
+    Lld,
+    /// The mold linker
+    Mold,
+}
+
+impl Linker {
+    /// The name of the linker for `-fuse-ld=`
+    pub fn name(self) -> &'static str {
+        match self {
+            Linker::Bfd => "bfd",
+            Linker::Gold => "gold",
+            Linker::Lld => "lld",
+            Linker::Mold => "mold",
+        }
+    }
+}
+
+/// What a profile strips from the linked outputs
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Strip {
+    #[default]
+    None,
+    /// The debug information
+    Debuginfo,
+    /// The debug information and the symbol table
+    Symbols,
+}
+
+/// Settings of the toolchain used to build for a target (`[toolchain.<triple>]`)
+///
+/// Toolchains are read from the workspace manifest and from `cpm-toolchain.toml`
+#[derive(Deserialize, Serialize, Default, Clone)]
+#[serde(rename_all = "kebab-case")]
+pub struct ToolchainSettings {
+    /// Prefix of the programs of the toolchain (e.g. `aarch64-linux-gnu-`)
+    #[serde(default)]
+    pub prefix: String,
+    /// The compiler, `gcc` with the prefix by default
+    pub compiler: Option<String>,
+    /// The archiver that creates static libraries, `ar` with the prefix by default
+    pub archiver: Option<String>,
+    /// The program that creates images from executables, `objcopy` with the prefix by default
+    pub objcopy: Option<String>,
+    /// The program that links executables and dynamic libraries, the compiler by default
+    pub linker: Option<String>,
+    /// Root folder of the headers and libraries of the target, relative to the workspace folder
+    pub sysroot: Option<PathBuf>,
+    /// Additional flags for the compiler when building for the target
+    #[serde(default)]
+    pub compiler_flags: Vec<String>,
+    /// Additional flags for the linker when building for the target
+    #[serde(default)]
+    pub linker_flags: Vec<String>,
+}
+
+/// Settings of the package registry
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct RegistrySettings {
+    /// Path of the registry folder, relative to the workspace folder
+    pub path: Option<PathBuf>,
+}
+
+/// Settings of the vendored dependencies, written by `cpm vendor`
+#[derive(Deserialize, Serialize, Clone)]
+pub struct VendorSettings {
+    /// Path of the vendor folder, relative to the workspace folder
+    ///
+    /// When set, registry and git dependencies are taken from this folder
+    pub path: PathBuf,
+}
+
+/// Settings used by `cpm run` to run an executable package
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct RunSettings {
+    /// Program (and its arguments) that runs the executable,
+    /// e.g. `["valgrind", "--error-exitcode=1"]`
+    #[serde(default)]
+    pub runner: Vec<String>,
+    /// Additional environment variables for the executable
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Working directory of the executable, relative to the package folder
+    pub cwd: Option<PathBuf>,
+}
+
+/// The training workload run by `cpm pgo` to record the execution profile of a package
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct PgoSettings {
+    /// Arguments of the executable package for the training run, which uses the `[run]` settings
+    pub run: Option<Vec<String>>,
+    /// Run the benchmarks of the package (`benches` folder) as the training workload
+    #[serde(default)]
+    pub bench: bool,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Workspace {
     /// Paths to the children packages of this workspace
     pub members: Vec<PathBuf>,
 }
-#[derive(Deserialize, Serialize)]
+#[derive(Deserialize, Serialize, Clone)]
 pub struct Manifest {
     pub workspace: Option<Workspace>,
     pub package: Option<Package>,
+    /// Code generation rules that run before the package is compiled
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rules: Vec<Rule>,
+    /// Libraries installed on the system, found with `pkg-config`
+    ///
+    /// Maps the pkg-config module name to the required version (e.g. `zlib = ">=1.2"`)
+    #[serde(
+        default,
+        rename = "system-dependencies",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub system_dependencies: BTreeMap<String, semver::VersionReq>,
+    /// Settings used when running this package with `cpm run`
+    #[serde(default)]
+    pub run: RunSettings,
+    /// The training workload of the profile-guided optimization of this package with `cpm pgo`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pgo: Option<PgoSettings>,
+    /// Other cpm packages this package depends on
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, Dependency>,
+    /// Dependencies only used by the tests, examples and benchmarks of this package
+    ///
+    /// They're never linked into the package itself and only the ones of the workspace
+    /// members are resolved
+    #[serde(
+        default,
+        rename = "dev-dependencies",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub dev_dependencies: BTreeMap<String, Dependency>,
+    /// Dependencies built for the host and used while building this package
+    ///
+    /// The folders of their executables are added to `PATH` when running the code generation rules,
+    /// they're never linked into the package
+    #[serde(
+        default,
+        rename = "build-dependencies",
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub build_dependencies: BTreeMap<String, Dependency>,
+    /// Settings that only apply to some targets, by target triple or `cfg(...)` expression
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub target: BTreeMap<String, TargetSettings>,
+    /// Registry used to resolve the dependencies of the workspace
+    pub registry: Option<RegistrySettings>,
+    /// Vendor folder used instead of the registry and the git repositories
+    pub vendor: Option<VendorSettings>,
+    /// Dependencies that replace the registry and git dependencies of the same name
+    /// everywhere in the dependency graph of the workspace
+    ///
+    /// Read from the workspace manifest, paths are relative to the workspace
+    /// (e.g. `json_parser = { path = "../json_parser" }`)
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub patch: BTreeMap<String, Dependency>,
+    /// Build profiles of the workspace
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub profile: BTreeMap<String, Profile>,
+    /// Toolchains of the workspace, by target triple
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub toolchain: BTreeMap<String, ToolchainSettings>,
 }
 
 impl Manifest {
@@ -80,11 +557,56 @@ version = "0.1.0"
 # additional_linker_flags = [...]
 # enable_pthread_library = false
 # enable_math_library = false
-# disable_std_library = false
-# kind = "exe" or "lib"
+# freestanding = false
+# kind = "exe", "staticlib" or "dynlib"
+
+# the dependencies, profiles and other settings are described in the README of cpm
 "#
         )
     }
+    /// Returns this manifest with the `[target.<key>]` sections that match `target`
+    /// merged into the package settings and the dependencies
+    pub fn for_target(&self, target: &TargetTriple) -> Result<Self, TargetError> {
+        let mut manifest = self.clone();
+        for (key, settings) in &self.target {
+            if !target_section_matches(key, target)? {
+                continue;
+            }
+            if let Some(package) = manifest.package.as_mut() {
+                package
+                    .additional_compiler_flags
+                    .extend(settings.additional_compiler_flags.iter().cloned());
+                package
+                    .additional_linker_flags
+                    .extend(settings.additional_linker_flags.iter().cloned());
+                package.defines.extend(settings.defines.iter().cloned());
+                package.sources.extend(settings.sources.iter().cloned());
+                if let Some(enable) = settings.enable_math_library {
+                    package.enable_math_library = enable;
+                }
+                if let Some(enable) = settings.enable_pthread_library {
+                    package.enable_pthread_library = enable;
+                }
+            }
+            let dependency_tables = [
+                (&mut manifest.dependencies, &settings.dependencies),
+                (&mut manifest.dev_dependencies, &settings.dev_dependencies),
+                (
+                    &mut manifest.build_dependencies,
+                    &settings.build_dependencies,
+                ),
+            ];
+            for (dependencies, target_dependencies) in dependency_tables {
+                dependencies.extend(
+                    target_dependencies
+                        .iter()
+                        .map(|(name, dependency)| (name.clone(), dependency.clone())),
+                );
+            }
+        }
+        Ok(manifest)
+    }
+
     pub fn load_manifest_from_file_path(
         file_path: impl Into<PathBuf>,
     ) -> Result<Self, errors::ManifestLoadError> {
@@ -98,10 +620,41 @@ version = "0.1.0"
                 }
             }
         };
-        match toml::from_str(&manifest_string_content) {
-            Ok(result) => Ok(result),
-            Err(e) => Err(errors::ManifestLoadError::Invalid(e)),
+        let manifest: Self =
+            toml::from_str(&manifest_string_content).map_err(errors::ManifestLoadError::Invalid)?;
+        manifest.check_git_references()?;
+        Ok(manifest)
+    }
+
+    /// Checks that the git dependencies specify at most one of `rev`, `tag` and `branch`
+    fn check_git_references(&self) -> Result<(), errors::ManifestLoadError> {
+        let target_tables = self.target.values().flat_map(|settings| {
+            [
+                &settings.dependencies,
+                &settings.dev_dependencies,
+                &settings.build_dependencies,
+            ]
+        });
+        let tables = [
+            &self.dependencies,
+            &self.dev_dependencies,
+            &self.build_dependencies,
+            &self.patch,
+        ]
+        .into_iter()
+        .chain(target_tables);
+        for (name, dependency) in tables.flatten() {
+            let Dependency::Detailed(detailed) = dependency else {
+                continue;
+            };
+            let references = [&detailed.rev, &detailed.tag, &detailed.branch];
+            if references.iter().filter(|r| r.is_some()).count() > 1 {
+                return Err(errors::ManifestLoadError::AmbiguousGitReference(
+                    name.clone(),
+                ));
+            }
         }
+        Ok(())
     }
     pub fn load_manifest_from_project_path(
         project_path: impl Into<PathBuf>,
@@ -0,0 +1,283 @@
+use std::path::{Path, PathBuf};
+
+use toml_edit::{DocumentMut, InlineTable, Item, Table, Value};
+
+use crate::filenames::MANIFEST_FILE_NAME;
+
+use super::errors::ManifestEditError;
+
+/// A dependency written in a manifest by `cpm add`
+#[derive(Default)]
+pub struct DependencyEntry {
+    /// Version requirement, written as typed by the user (e.g. `1.2`)
+    pub version: Option<String>,
+    pub path: Option<PathBuf>,
+    pub git: Option<String>,
+    pub rev: Option<String>,
+    pub tag: Option<String>,
+    pub branch: Option<String>,
+}
+
+impl DependencyEntry {
+    /// The value written in `[dependencies]`: a string when there's only a version requirement,
+    /// an inline table otherwise
+    fn to_value(&self) -> Value {
+        let has_details = self.path.is_some() || self.git.is_some();
+        if let (Some(version), false) = (&self.version, has_details) {
+            return Value::from(version.as_str());
+        }
+        let mut table = InlineTable::new();
+        if let Some(version) = &self.version {
+            table.insert("version", Value::from(version.as_str()));
+        }
+        if let Some(path) = &self.path {
+            table.insert("path", Value::from(path.to_string_lossy().as_ref()));
+        }
+        let git_settings = [
+            ("git", &self.git),
+            ("rev", &self.rev),
+            ("tag", &self.tag),
+            ("branch", &self.branch),
+        ];
+        for (key, setting) in git_settings {
+            if let Some(setting) = setting {
+                table.insert(key, Value::from(setting.as_str()));
+            }
+        }
+        Value::InlineTable(table)
+    }
+}
+
+/// Edits the manifest of a package while keeping its comments, formatting and ordering
+pub struct ManifestEditor {
+    manifest_path: PathBuf,
+    original_content: String,
+    document: DocumentMut,
+}
+
+impl ManifestEditor {
+    /// Opens the manifest at `$package_path/cpm.toml`
+    pub fn open(package_path: &Path) -> Result<Self, ManifestEditError> {
+        let manifest_path = package_path.join(MANIFEST_FILE_NAME);
+        let original_content = std::fs::read_to_string(&manifest_path)?;
+        let document = original_content.parse()?;
+        Ok(Self {
+            manifest_path,
+            original_content,
+            document,
+        })
+    }
+
+    /// Adds a dependency, or replaces it if it already exists, and returns `true` if it was replaced
+    ///
+    /// Dependencies stay sorted if they were sorted
+    pub fn add_dependency(
+        &mut self,
+        name: &str,
+        entry: &DependencyEntry,
+    ) -> Result<bool, ManifestEditError> {
+        if !self.document.contains_key("dependencies") {
+            // the comments at the end of the document stay above the new table
+            let mut table = Table::new();
+            let prefix = match self
+                .document
+                .trailing()
+                .as_str()
+                .unwrap_or_default()
+                .trim_end()
+            {
+                "" => "\n".to_string(),
+                comments => format!("{comments}\n\n"),
+            };
+            table.decor_mut().set_prefix(prefix);
+            self.document.set_trailing("");
+            self.document.insert("dependencies", Item::Table(table));
+        }
+        let dependencies = self
+            .document
+            .get_mut("dependencies")
+            .and_then(Item::as_table_like_mut)
+            .ok_or(ManifestEditError::InvalidDependencies)?;
+        let was_sorted = dependencies
+            .iter()
+            .zip(dependencies.iter().skip(1))
+            .all(|((a, _), (b, _))| a <= b);
+        let replaced = dependencies
+            .insert(name, Item::Value(entry.to_value()))
+            .is_some();
+        if was_sorted {
+            dependencies.sort_values();
+        }
+        Ok(replaced)
+    }
+
+    /// Removes a dependency
+    pub fn remove_dependency(&mut self, name: &str) -> Result<(), ManifestEditError> {
+        let dependencies = self
+            .document
+            .get_mut("dependencies")
+            .and_then(Item::as_table_like_mut)
+            .ok_or_else(|| ManifestEditError::NotADependency(name.to_string()))?;
+        dependencies
+            .remove(name)
+            .ok_or_else(|| ManifestEditError::NotADependency(name.to_string()))?;
+        Ok(())
+    }
+
+    /// Writes the edited manifest
+    pub fn save(&self) -> std::io::Result<()> {
+        std::fs::write(&self.manifest_path, self.document.to_string())
+    }
+
+    /// Writes back the manifest as it was when it was opened
+    pub fn restore(&self) -> std::io::Result<()> {
+        std::fs::write(&self.manifest_path, &self.original_content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `content` as the manifest of a temporary package, applies `edit` to it
+    /// and returns the saved manifest
+    fn edit_manifest(
+        name: &str,
+        content: &str,
+        edit: impl FnOnce(&mut ManifestEditor) -> Result<(), ManifestEditError>,
+    ) -> Result<String, ManifestEditError> {
+        let package_path =
+            std::env::temp_dir().join(format!("cpm-edit-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&package_path)?;
+        std::fs::write(package_path.join(MANIFEST_FILE_NAME), content)?;
+        let result = ManifestEditor::open(&package_path).and_then(|mut editor| {
+            edit(&mut editor)?;
+            editor.save()?;
+            Ok(std::fs::read_to_string(
+                package_path.join(MANIFEST_FILE_NAME),
+            )?)
+        });
+        let _ = std::fs::remove_dir_all(&package_path);
+        result
+    }
+
+    fn version(version: &str) -> DependencyEntry {
+        DependencyEntry {
+            version: Some(version.to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sorted_dependencies_stay_sorted_with_their_comments() {
+        let manifest = "\
+[package]
+name = \"app\" # the name
+
+[dependencies]
+# compression
+zlib = \"1.2\"
+abc = \"1\"
+";
+        let unsorted = edit_manifest("unsorted", manifest, |editor| {
+            editor.add_dependency("png", &version("1.6"))?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            unsorted,
+            manifest.to_string() + "png = \"1.6\"\n",
+            "unsorted dependencies get the new one at the end"
+        );
+        let sorted_manifest = manifest.replace("abc = \"1\"\n", "");
+        let sorted = edit_manifest("sorted", &sorted_manifest, |editor| {
+            editor.add_dependency("png", &version("1.6"))?;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            sorted,
+            "\
+[package]
+name = \"app\" # the name
+
+[dependencies]
+png = \"1.6\"
+# compression
+zlib = \"1.2\"
+"
+        );
+    }
+
+    #[test]
+    fn dependencies_table_is_added_after_the_trailing_comments() {
+        let manifest = "[package]\nname = \"app\"\n\n# [dependencies]\n# zlib = \"1.2\"\n";
+        let edited = edit_manifest("new-table", manifest, |editor| {
+            let entry = DependencyEntry {
+                path: Some(PathBuf::from("../lib")),
+                ..Default::default()
+            };
+            assert!(!editor.add_dependency("lib", &entry)?);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            edited,
+            "[package]\nname = \"app\"\n\n# [dependencies]\n# zlib = \"1.2\"\n\n\
+             [dependencies]\nlib = { path = \"../lib\" }\n"
+        );
+    }
+
+    #[test]
+    fn existing_dependencies_are_replaced() {
+        let manifest = "[dependencies]\nzlib = \"1.2\"\n";
+        let edited = edit_manifest("replace", manifest, |editor| {
+            let entry = DependencyEntry {
+                git: Some("https://example.com/zlib.git".to_string()),
+                tag: Some("v1.3".to_string()),
+                ..Default::default()
+            };
+            assert!(editor.add_dependency("zlib", &entry)?);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(
+            edited,
+            "[dependencies]\nzlib = { git = \"https://example.com/zlib.git\", tag = \"v1.3\" }\n"
+        );
+    }
+
+    #[test]
+    fn removed_dependencies_must_exist() {
+        let manifest = "[dependencies]\npng = \"1.6\"\nzlib = \"1.2\"\n";
+        let edited = edit_manifest("remove", manifest, |editor| editor.remove_dependency("png"));
+        assert_eq!(edited.unwrap(), "[dependencies]\nzlib = \"1.2\"\n");
+        let result = edit_manifest("remove-unknown", manifest, |editor| {
+            editor.remove_dependency("libm")
+        });
+        assert!(matches!(result, Err(ManifestEditError::NotADependency(name)) if name == "libm"));
+        let result = edit_manifest("remove-without-table", "", |editor| {
+            editor.remove_dependency("png")
+        });
+        assert!(matches!(result, Err(ManifestEditError::NotADependency(_))));
+    }
+
+    #[test]
+    fn restore_writes_back_the_original_manifest() {
+        let package_path =
+            std::env::temp_dir().join(format!("cpm-edit-restore-{}", std::process::id()));
+        let manifest_path = package_path.join(MANIFEST_FILE_NAME);
+        let manifest = "[dependencies]\nzlib = \"1.2\" # compression\n";
+        std::fs::create_dir_all(&package_path).unwrap();
+        std::fs::write(&manifest_path, manifest).unwrap();
+        let mut editor = ManifestEditor::open(&package_path).unwrap();
+        editor.remove_dependency("zlib").unwrap();
+        editor.save().unwrap();
+        let edited = std::fs::read_to_string(&manifest_path).unwrap();
+        editor.restore().unwrap();
+        let restored = std::fs::read_to_string(&manifest_path).unwrap();
+        let _ = std::fs::remove_dir_all(&package_path);
+        assert_eq!(edited, "[dependencies]\n");
+        assert_eq!(restored, manifest);
+    }
+}
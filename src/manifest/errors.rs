@@ -8,4 +8,18 @@ pub enum ManifestLoadError {
     IOError(std::io::Error),
     #[error("invalid manifest file")]
     Invalid(toml::de::Error),
+    #[error("dependency `{0}` must specify only one of `rev`, `tag` and `branch`")]
+    AmbiguousGitReference(String),
+}
+
+#[derive(Error, Debug)]
+pub enum ManifestEditError {
+    #[error("failed to read manifest file: IO Error: {0}")]
+    IOError(#[from] std::io::Error),
+    #[error("invalid manifest file: {0}")]
+    Invalid(#[from] toml_edit::TomlError),
+    #[error("`dependencies` is not a table")]
+    InvalidDependencies,
+    #[error("`{0}` is not a dependency of this package")]
+    NotADependency(String),
 }
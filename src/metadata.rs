@@ -0,0 +1,171 @@
+use std::{collections::HashSet, path::PathBuf};
+
+use serde::Serialize;
+
+use crate::dependencies::{PackageSource, Resolve, ResolvedPackage};
+
+/// The version of the format printed by `cpm metadata`
+const METADATA_FORMAT_VERSION: u32 = 1;
+
+/// The resolved dependency graph of a workspace, printed as JSON by `cpm metadata`
+#[derive(Serialize)]
+pub struct Metadata {
+    pub format_version: u32,
+    pub workspace_root: PathBuf,
+    /// Names of the workspace members
+    pub members: Vec<String>,
+    pub packages: Vec<PackageMetadata>,
+}
+
+#[derive(Serialize)]
+pub struct PackageMetadata {
+    pub name: String,
+    pub version: semver::Version,
+    /// `workspace`, `path`, `registry` or `git+<url>[?<reference>]#<commit>`
+    pub source: String,
+    pub path: PathBuf,
+    pub dependencies: Vec<String>,
+    pub dev_dependencies: Vec<String>,
+    pub build_dependencies: Vec<String>,
+    /// Whether the package replaces a dependency through the `[patch]` section
+    pub patched: bool,
+    /// Whether the package is built by another build system
+    pub external: bool,
+}
+
+impl Metadata {
+    pub fn new(workspace_root: PathBuf, resolve: &Resolve) -> Self {
+        Self {
+            format_version: METADATA_FORMAT_VERSION,
+            workspace_root,
+            members: workspace_members(resolve),
+            packages: resolve
+                .packages
+                .values()
+                .map(|package| PackageMetadata {
+                    name: package.name.clone(),
+                    version: package.version.clone(),
+                    source: source_name(&package.source),
+                    path: package.path.clone(),
+                    dependencies: package.dependencies.clone(),
+                    dev_dependencies: package.dev_dependencies.clone(),
+                    build_dependencies: package.build_dependencies.clone(),
+                    patched: package.patched,
+                    external: package.external.is_some(),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The names of the workspace members of a graph
+pub fn workspace_members(resolve: &Resolve) -> Vec<String> {
+    resolve
+        .packages
+        .values()
+        .filter(|package| package.source == PackageSource::Workspace)
+        .map(|package| package.name.clone())
+        .collect()
+}
+
+fn source_name(source: &PackageSource) -> String {
+    match source {
+        PackageSource::Workspace => "workspace".to_string(),
+        PackageSource::Path => "path".to_string(),
+        _ => source.lockfile_source().unwrap_or_default(),
+    }
+}
+
+/// Renders the dependencies of `root` as a tree, one package per line
+///
+/// Build-dependencies and dev-dependencies are listed under a `[build-dependencies]`
+/// and a `[dev-dependencies]` line. Packages already displayed are marked with `(*)`
+/// and their dependencies aren't repeated. When cross-compiling, the build-dependencies are
+/// displayed with their dependencies on the host
+pub fn dependency_tree(resolve: &Resolve, root: &str) -> String {
+    let mut lines = vec![];
+    let mut displayed = HashSet::new();
+    if let Some(package) = resolve.packages.get(root) {
+        push_tree_lines(
+            resolve,
+            (package, false),
+            "",
+            "",
+            &mut displayed,
+            &mut lines,
+        );
+    }
+    lines.join("\n")
+}
+
+fn push_tree_lines(
+    resolve: &Resolve,
+    (package, for_host): (&ResolvedPackage, bool),
+    prefix: &str,
+    children_prefix: &str,
+    displayed: &mut HashSet<(String, bool)>,
+    lines: &mut Vec<String>,
+) {
+    let first_time = displayed.insert((package.name.clone(), for_host));
+    let mut line = format!("{prefix}{} v{}", package.name, package.version);
+    match &package.source {
+        PackageSource::Workspace | PackageSource::Path => {
+            line.push_str(&format!(" ({})", package.path.display()))
+        }
+        PackageSource::Git { url, commit, .. } => {
+            line.push_str(&format!(" ({url}#{})", &commit[..commit.len().min(7)]))
+        }
+        PackageSource::Registry { .. } => {}
+    }
+    if package.patched {
+        line.push_str(" (patched)");
+    }
+    if !first_time && package.all_dependencies().next().is_some() {
+        line.push_str(" (*)");
+    }
+    lines.push(line);
+    if !first_time {
+        return;
+    }
+    let sections = [
+        (None, package.platform_dependencies(for_host), for_host),
+        (
+            Some("[build-dependencies]"),
+            package.platform_build_dependencies(for_host),
+            for_host || resolve.cross_compiling(),
+        ),
+        (
+            Some("[dev-dependencies]"),
+            &package.dev_dependencies[..],
+            false,
+        ),
+    ];
+    for (title, names, children_for_host) in sections {
+        if names.is_empty() {
+            continue;
+        }
+        if let Some(title) = title {
+            lines.push(format!("{children_prefix}{title}"));
+        }
+        let dependencies: Vec<&ResolvedPackage> = names
+            .iter()
+            .filter_map(|name| resolve.packages.get(name))
+            .collect();
+        for (i, dependency) in dependencies.iter().enumerate() {
+            let last = i + 1 == dependencies.len();
+            let (branch, indent) = if last {
+                ("└── ", "    ")
+            } else {
+                ("├── ", "│   ")
+            };
+            push_tree_lines(
+                resolve,
+                (dependency, children_for_host),
+                &format!("{children_prefix}{branch}"),
+                &format!("{children_prefix}{indent}"),
+                displayed,
+                lines,
+            );
+        }
+    }
+}
@@ -1,19 +1,81 @@
+use crate::bench;
 use crate::compiler::Compiler;
+use crate::pkg_config::{self, SystemLibrary};
+use crate::profile::BuildProfile;
+use crate::size::{self, SizeHistory};
 use walkdir::WalkDir;
 
 use crate::{
-    filenames::{DYN_LIB_EXTENSION, EXECUTABLE_EXTENSION, STATIC_LIB_EXTENSION},
-    manifest::{self, Manifest, PackageKind},
+    manifest::{self, ImageFormat, Manifest, PackageKind},
+    target::{errors::TargetError, TargetTriple},
+    toolchain::Toolchain,
+};
+use std::{
+    cell::OnceCell,
+    collections::HashSet,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
 };
-use std::{collections::HashSet, path::PathBuf, process::Stdio};
 pub mod errors;
 use errors::*;
+
+/// The file of the objects folder that describes the settings of the last build
+const BUILD_STAMP_FILE_NAME: &str = ".cpm-build-stamp";
+
+/// The outputs of a built dependency that are used to compile and link its dependents
+#[derive(Clone)]
+pub struct DependencyArtifact {
+    /// The folders that contain the public headers of the dependency
+    pub include_folders: Vec<PathBuf>,
+    /// The libraries produced by the dependency
+    pub library_paths: Vec<PathBuf>,
+    /// The linker flags of the system libraries the dependency uses (e.g. `-lz`),
+    /// linked after its libraries
+    pub system_libs: Vec<String>,
+}
+
+/// The programs of a package that are compiled separately from the package,
+/// each `.c` file of their folder is its own executable
+///
+/// They're linked with the package and can use its dev-dependencies
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DevTarget {
+    /// The `tests` folder, run by `cpm test`
+    Tests,
+    /// The `examples` folder, run by `cpm run --example <name>`
+    Examples,
+    /// The `benches` folder, run by `cpm bench`, which can include the `cpm_bench.h` harness
+    Benches,
+    /// The `fuzz` folder, run by `cpm fuzz`, whose files define `LLVMFuzzerTestOneInput`
+    Fuzz,
+}
+
+impl DevTarget {
+    /// The folder of these programs, relative to the package folder
+    pub fn folder_name(self) -> &'static str {
+        match self {
+            DevTarget::Tests => "tests",
+            DevTarget::Examples => "examples",
+            DevTarget::Benches => "benches",
+            DevTarget::Fuzz => "fuzz",
+        }
+    }
+}
+
 /// Reads the manifest of the package and workspace and compiles a package
 pub struct PackageBuilder {
     package_path: PathBuf,
     package_manifest: manifest::Manifest,
+    target_manifest: manifest::Manifest,
+    toolchain: Toolchain,
     workspace_path: PathBuf,
     workspace_info: Option<manifest::Workspace>,
+    dependency_artifacts: Vec<DependencyArtifact>,
+    dev_dependency_artifacts: Vec<DependencyArtifact>,
+    build_tool_paths: Vec<PathBuf>,
+    profile: BuildProfile,
+    /// The libraries of `[system-dependencies]`, found on the first call of `system_libraries`
+    system_libraries: OnceCell<Vec<SystemLibrary>>,
 }
 #[allow(dead_code)]
 impl PackageBuilder {
@@ -31,14 +93,154 @@ impl PackageBuilder {
         }
         let workspace_manifest = Manifest::load_manifest_from_project_path(&workspace_path)
             .map_err(errors::CreatePackageCompilerError::InvalidWorkspaceManifest)?;
+        let toolchain = Toolchain::host();
+        let target_manifest = package_manifest.for_target(&toolchain.target)?;
         Ok(Self {
             package_path,
             workspace_path,
             package_manifest,
+            target_manifest,
+            toolchain,
             workspace_info: workspace_manifest.workspace,
+            dependency_artifacts: vec![],
+            dev_dependency_artifacts: vec![],
+            build_tool_paths: vec![],
+            profile: BuildProfile::default(),
+            system_libraries: OnceCell::new(),
         })
     }
 
+    /// Sets the toolchain used to build this package, the host toolchain by default
+    ///
+    /// The `[target.<key>]` sections of the manifest that match the target of the toolchain are applied
+    pub fn set_toolchain(&mut self, toolchain: Toolchain) -> Result<(), TargetError> {
+        self.target_manifest = self.package_manifest.for_target(&toolchain.target)?;
+        self.toolchain = toolchain;
+        Ok(())
+    }
+
+    /// The toolchain used to build this package
+    pub fn toolchain(&self) -> &Toolchain {
+        &self.toolchain
+    }
+
+    /// The target this package is built for
+    pub fn target(&self) -> &TargetTriple {
+        &self.toolchain.target
+    }
+
+    /// Sets the build profile used to compile and link this package
+    pub fn set_profile(&mut self, profile: BuildProfile) {
+        self.profile = profile;
+    }
+
+    /// The build profile used to compile and link this package
+    pub fn profile(&self) -> &BuildProfile {
+        &self.profile
+    }
+
+    /// Sets the artifacts of the (already built) dependencies of this package, in link order
+    ///
+    /// Their include folders are added when compiling and their libraries when linking
+    pub fn set_dependency_artifacts(&mut self, dependency_artifacts: Vec<DependencyArtifact>) {
+        self.dependency_artifacts = dependency_artifacts;
+    }
+
+    /// The artifacts of the dependencies of this package, in link order
+    pub fn dependency_artifacts(&self) -> &[DependencyArtifact] {
+        &self.dependency_artifacts
+    }
+
+    /// Sets the artifacts of the (already built) dev-dependencies of this package, in link order
+    ///
+    /// They're only used by the [`DevTarget`] programs, never by the package itself
+    pub fn set_dev_dependency_artifacts(
+        &mut self,
+        dev_dependency_artifacts: Vec<DependencyArtifact>,
+    ) {
+        self.dev_dependency_artifacts = dev_dependency_artifacts;
+    }
+
+    /// The artifacts of the dev-dependencies of this package, in link order
+    pub fn dev_dependency_artifacts(&self) -> &[DependencyArtifact] {
+        &self.dev_dependency_artifacts
+    }
+
+    /// The folders of the dynamic libraries loaded by the programs of this package: the package
+    /// itself when it's a dynamic library, and its dependencies and dev-dependencies that are
+    /// dynamic libraries
+    pub fn runtime_library_folders(&self) -> Vec<PathBuf> {
+        let extension = self.target().dynamic_library_extension();
+        let mut library_paths = vec![];
+        if self.package().kind == PackageKind::DynamicLibrary {
+            library_paths.push(self.output_path());
+        }
+        library_paths.extend(
+            self.dev_dependency_artifacts
+                .iter()
+                .chain(&self.dependency_artifacts)
+                .flat_map(|artifact| artifact.library_paths.iter().cloned()),
+        );
+        let mut folders: Vec<PathBuf> = vec![];
+        for library_path in library_paths {
+            if library_path.extension().is_none_or(|e| e != extension) {
+                continue;
+            }
+            if let Some(folder) = library_path.parent() {
+                if !folders.iter().any(|f| f == folder) {
+                    folders.push(folder.to_path_buf());
+                }
+            }
+        }
+        folders
+    }
+
+    /// Sets the executables of the (already built) build-dependencies of this package
+    ///
+    /// Their folders are added to `PATH` when running the code generation rules
+    pub fn set_build_tool_paths(&mut self, build_tool_paths: Vec<PathBuf>) {
+        self.build_tool_paths = build_tool_paths;
+    }
+
+    /// The executables of the build-dependencies of this package
+    pub fn build_tool_paths(&self) -> &[PathBuf] {
+        &self.build_tool_paths
+    }
+
+    /// The artifact of this package to be used by its dependents
+    ///
+    /// It includes the system libraries of the package (from pkg-config, and the math and
+    /// pthread libraries when they're enabled), which its dependents must link with
+    pub fn artifact(&self) -> Result<DependencyArtifact, errors::BuildPackageError> {
+        let mut include_folder = self.package_path();
+        include_folder.push(&self.package().include_folder);
+        let mut system_libs: Vec<String> = self
+            .system_libraries()?
+            .iter()
+            .flat_map(|library| library.libs.iter().cloned())
+            .collect();
+        system_libs.extend(self.private_libraries().into_iter().map(String::from));
+        Ok(DependencyArtifact {
+            include_folders: vec![include_folder],
+            library_paths: vec![self.output_path()],
+            system_libs,
+        })
+    }
+
+    /// The libraries enabled in the package settings that the package links with,
+    /// written to `Libs.private` of its pkg-config file
+    fn private_libraries(&self) -> Vec<&'static str> {
+        let package = self.package();
+        let mut private_libs = vec![];
+        if package.enable_math_library {
+            private_libs.push("-lm");
+        }
+        if package.enable_pthread_library {
+            private_libs.push("-lpthread");
+        }
+        private_libs
+    }
+
     /// The path of the workspace. Will be the same as package_path if `workspace_info` is `None`
     ///
     /// Compilation outputs will be placed at `$workspace_path/target`
@@ -46,6 +248,15 @@ impl PackageBuilder {
         self.workspace_path.clone()
     }
 
+    /// The folder where the outputs of the build profile and the toolchain are placed
+    ///
+    /// It's `$workspace_path/target` for the `dev` profile and `$workspace_path/target/<profile>` otherwise,
+    /// with the triple in between for targets selected with `--target` (e.g. `target/<triple>/<profile>`)
+    pub fn target_folder_path(&self) -> PathBuf {
+        self.profile
+            .target_folder_path(&self.workspace_path, &self.toolchain)
+    }
+
     /// The workspace section of the manifest file at `$workspace_path/cpm.toml`
     pub fn workspace(&self) -> Option<&manifest::Workspace> {
         self.workspace_info.as_ref()
@@ -58,7 +269,7 @@ impl PackageBuilder {
 
     /// Package info of the manifest file at `$package_path/cpm.toml`
     pub fn package(&self) -> &manifest::Package {
-        self.package_manifest.package.as_ref().expect(
+        self.target_manifest.package.as_ref().expect(
             "package manifest must have package info because it was checked in the constructor",
         )
     }
@@ -68,13 +279,17 @@ impl PackageBuilder {
         &self.package_manifest
     }
 
+    /// The manifest with the `[target.<key>]` sections of the target applied
+    pub fn target_manifest(&self) -> &Manifest {
+        &self.target_manifest
+    }
+
     /// Generates the output folder path for this package.
     ///
     /// It does not create the folder,
     /// use `create_output_folder` to create the necessary folders
     pub fn output_folder_path(&self) -> PathBuf {
-        let mut output_path = self.workspace_path();
-        output_path.push("target");
+        let mut output_path = self.target_folder_path();
         match self.package().kind {
             PackageKind::Executable => output_path.push("executables"),
             PackageKind::StaticLibrary => output_path.push("staticlibs"),
@@ -97,82 +312,488 @@ impl PackageBuilder {
 
     /// Generates the output path for this package.
     ///
+    /// Libraries are named like the linker expects them (e.g. `libname.a`).
     /// It does not create the folder,
     /// use `create_output_folder` to create the necessary folders
     pub fn output_path(&self) -> PathBuf {
         let mut output_path = self.output_folder_path();
-        output_path.push(&self.package().name);
         match self.package().kind {
-            PackageKind::Executable => output_path.set_extension(EXECUTABLE_EXTENSION),
-            PackageKind::StaticLibrary => output_path.set_extension(STATIC_LIB_EXTENSION),
-            PackageKind::DynamicLibrary => output_path.set_extension(DYN_LIB_EXTENSION),
+            PackageKind::Executable => output_path.push(&self.package().name),
+            PackageKind::StaticLibrary | PackageKind::DynamicLibrary => output_path.push(format!(
+                "{}{}",
+                self.target().library_prefix(),
+                self.package().name
+            )),
+        }
+        match self.package().kind {
+            PackageKind::Executable => {
+                output_path.set_extension(self.target().executable_extension())
+            }
+            PackageKind::StaticLibrary => {
+                output_path.set_extension(self.target().static_library_extension())
+            }
+            PackageKind::DynamicLibrary => {
+                output_path.set_extension(self.target().dynamic_library_extension())
+            }
         };
         output_path
     }
+    /// The link to the dynamic library of this package named after its soname
+    /// (e.g. `libname.so.1`), which the dynamic loader looks for when running its dependents
+    ///
+    /// Only ELF dynamic libraries have one
+    pub fn soname_link_path(&self) -> Option<PathBuf> {
+        if self.package().kind != PackageKind::DynamicLibrary
+            || self.target().dynamic_library_extension() != "so"
+        {
+            return None;
+        }
+        let output_path = self.output_path();
+        let mut file_name = output_path.file_name()?.to_os_string();
+        file_name.push(format!(".{}", self.package().version.major));
+        Some(output_path.with_file_name(file_name))
+    }
+
+    /// Points the soname link of the dynamic library to the output, if it has one
+    fn create_soname_link(&self) -> std::io::Result<()> {
+        let Some(link_path) = self.soname_link_path() else {
+            return Ok(());
+        };
+        if link_path.symlink_metadata().is_ok() {
+            std::fs::remove_file(&link_path)?;
+        }
+        let output_path = self.output_path();
+        let target = output_path
+            .file_name()
+            .expect("output path must have a file name");
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(target, &link_path)?;
+        #[cfg(not(unix))]
+        std::fs::copy(&output_path, &link_path)?;
+        Ok(())
+    }
+
+    /// Generates the path of the pkg-config file of this package.
+    ///
+    /// It's placed alongside the library artifact in the output folder
+    pub fn pc_file_path(&self) -> PathBuf {
+        let mut pc_file_path = self.output_folder_path();
+        pc_file_path.push(format!("{}.pc", self.package().name));
+        pc_file_path
+    }
+
+    /// Generates the contents of the pkg-config file of this package
+    ///
+    /// `libdir` is the folder that contains the library (`lib<name>.a` or `lib<name>.so`)
+    /// and `includedir` is the folder that contains the public headers
+    pub fn pc_file_content(&self, libdir: &Path, includedir: &Path) -> String {
+        let package = self.package();
+        let dependencies = self
+            .target_manifest
+            .dependencies
+            .iter()
+            .map(|(name, dependency)| (name, dependency.version_requirement()));
+        let system_dependencies = self
+            .target_manifest
+            .system_dependencies
+            .iter()
+            .map(|(name, requirement)| (name, requirement.clone()));
+        let requires = dependencies
+            .chain(system_dependencies)
+            .flat_map(|(name, requirement)| pkg_config::requires_entries(name, &requirement))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let private_libs = self.private_libraries();
+        let mut content = format!(
+            "libdir={}\nincludedir={}\n\nName: {}\nDescription: {}\nVersion: {}\n",
+            libdir.display(),
+            includedir.display(),
+            package.name,
+            package.description.as_deref().unwrap_or(&package.name),
+            package.version,
+        );
+        if !requires.is_empty() {
+            content.push_str(&format!("Requires.private: {requires}\n"));
+        }
+        content.push_str("Cflags: -I${includedir}\n");
+        content.push_str(&format!("Libs: -L${{libdir}} -l{}\n", package.name));
+        if !private_libs.is_empty() {
+            content.push_str(&format!("Libs.private: {}\n", private_libs.join(" ")));
+        }
+        content
+    }
+
+    /// Writes the pkg-config file of this package if it's a library
+    pub fn write_pc_file(&self) -> std::io::Result<()> {
+        if self.package().kind == PackageKind::Executable {
+            return Ok(());
+        }
+        let mut include_folder_path = self.package_path();
+        include_folder_path.push(&self.package().include_folder);
+        let content = self.pc_file_content(&self.output_folder_path(), &include_folder_path);
+        std::fs::write(self.pc_file_path(), content)
+    }
+
     pub fn absolute_path_of_src_folder(&self) -> PathBuf {
         let mut package_src_folder_path = self.package_path();
         package_src_folder_path.push(&self.package().src_folder);
         package_src_folder_path
     }
+    /// Generates the folder path where the files produced by the code generation rules are placed.
+    ///
+    /// This folder is added to the include folders when compiling this package
+    pub fn generated_folder_path(&self) -> PathBuf {
+        let mut generated_folder_path = self.target_folder_path();
+        generated_folder_path.push("generated");
+        generated_folder_path.push(format!(
+            "{}-{}",
+            self.package().name,
+            self.package().version
+        ));
+        generated_folder_path
+    }
+
+    /// Gets all the input files matched by the code generation rules, with the index of their rule
+    pub fn rule_inputs(&self) -> Result<Vec<(usize, PathBuf)>, RuleError> {
+        let mut inputs = vec![];
+        for (rule_index, rule) in self.target_manifest.rules.iter().enumerate() {
+            let mut pattern = self.package_path();
+            pattern.push(&rule.input);
+            let pattern = pattern.to_string_lossy().to_string();
+            let paths = glob::glob(&pattern).map_err(|error| RuleError::InvalidInputPattern {
+                pattern: rule.input.clone(),
+                error,
+            })?;
+            inputs.extend(paths.filter_map(|p| p.ok()).map(|p| (rule_index, p)));
+        }
+        Ok(inputs)
+    }
+
+    /// Runs the code generation rules and returns the generated source files that must be compiled
+    ///
+    /// A rule only runs for an input when one of its outputs is missing or older than the input
+    /// or the package manifest.
+    pub fn run_rules(&self) -> Result<Vec<PathBuf>, RuleError> {
+        let out_dir = self.generated_folder_path();
+        let mut manifest_path = self.package_path();
+        manifest_path.push("cpm.toml");
+        let mut generated_src_files = vec![];
+        for (rule_index, input_path) in self.rule_inputs()? {
+            let rule = &self.target_manifest.rules[rule_index];
+            let output_paths: Vec<PathBuf> = rule
+                .outputs
+                .iter()
+                .map(|output| {
+                    let mut output_path = out_dir.clone();
+                    output_path.push(expand_rule_template(output, &input_path, &out_dir));
+                    output_path
+                })
+                .collect();
+            let needs_rebuild = output_paths.iter().any(|output_path| {
+                file_needs_rebuild(&input_path, output_path)
+                    || file_needs_rebuild(&manifest_path, output_path)
+            });
+            if needs_rebuild {
+                for output_path in &output_paths {
+                    create_parent_folder(output_path).map_err(RuleError::IOError)?;
+                }
+                let mut args = rule
+                    .command
+                    .iter()
+                    .map(|arg| expand_rule_template(arg, &input_path, &out_dir));
+                let program = args.next().ok_or_else(|| RuleError::EmptyCommand {
+                    pattern: rule.input.clone(),
+                })?;
+                let mut command = Command::new(program);
+                if let Some(path) = self.rule_path_variable() {
+                    command.env("PATH", path);
+                }
+                let status = command
+                    .args(args)
+                    .current_dir(self.package_path())
+                    .stdout(Stdio::inherit())
+                    .stderr(Stdio::inherit())
+                    .status()
+                    .map_err(RuleError::IOError)?;
+                if !status.success() {
+                    return Err(RuleError::CommandFailed {
+                        input_path,
+                        exit_code: status,
+                    });
+                }
+                if let Some(output_path) = output_paths.iter().find(|p| !p.exists()) {
+                    return Err(RuleError::MissingOutput {
+                        input_path,
+                        output_path: output_path.clone(),
+                    });
+                }
+            }
+            generated_src_files.extend(
+                output_paths
+                    .into_iter()
+                    .filter(|p| p.extension().is_some_and(|ext| ext == "c")),
+            );
+        }
+        Ok(generated_src_files)
+    }
+
+    /// The `PATH` of the code generation rules: the folders of the build-dependency executables
+    /// followed by the current `PATH`, `None` when there are no build-dependencies
+    fn rule_path_variable(&self) -> Option<std::ffi::OsString> {
+        if self.build_tool_paths.is_empty() {
+            return None;
+        }
+        let mut folders: Vec<PathBuf> = self
+            .build_tool_paths
+            .iter()
+            .filter_map(|tool| tool.parent().map(Path::to_path_buf))
+            .collect();
+        if let Some(current) = std::env::var_os("PATH") {
+            folders.extend(std::env::split_paths(&current));
+        }
+        std::env::join_paths(folders).ok()
+    }
+
+    /// Finds the system libraries listed in `[system-dependencies]` with pkg-config
+    ///
+    /// They're only searched once, later calls return the same libraries
+    pub fn system_libraries(&self) -> Result<&[SystemLibrary], errors::BuildPackageError> {
+        if let Some(libraries) = self.system_libraries.get() {
+            return Ok(libraries);
+        }
+        let mut libraries = vec![];
+        for (name, requirement) in &self.target_manifest.system_dependencies {
+            libraries.push(pkg_config::find_system_library(name, requirement)?);
+        }
+        Ok(self.system_libraries.get_or_init(|| libraries))
+    }
+
     /// Gets all the paths to the source files in the source folder recursively
-    /// using walkdir library.
+    /// using walkdir library, followed by the files matched by the `sources` patterns
     pub fn src_files(&self) -> impl std::iter::Iterator<Item = PathBuf> {
         let package_src_folder_path = self.absolute_path_of_src_folder();
         let src_walkdir = WalkDir::new(&package_src_folder_path)
             .contents_first(true)
             .into_iter();
 
-        src_walkdir
+        let src_files: Vec<PathBuf> = src_walkdir
             .filter_entry(|e| e.path().extension().is_some_and(|ext| ext == "c"))
             .filter_map(|e| e.ok())
             .map(|e| e.path().to_path_buf())
+            .collect();
+        let additional_src_files: Vec<PathBuf> = self
+            .package()
+            .sources
+            .iter()
+            .filter_map(|pattern| {
+                glob::glob(&self.package_path().join(pattern).to_string_lossy()).ok()
+            })
+            .flat_map(|paths| paths.filter_map(|p| p.ok()))
+            .filter(|path| !src_files.contains(path))
+            .collect();
+        src_files.into_iter().chain(additional_src_files)
     }
 
     /// Returns all the files the output depends on
     /// Includes manifest files, source files and other libraries
-    pub fn inputs(&self) -> HashSet<PathBuf> {
+    ///
+    /// Fails when the input pattern of a code generation rule is invalid
+    pub fn inputs(&self) -> Result<HashSet<PathBuf>, RuleError> {
         let mut inputs = HashSet::<PathBuf>::new();
         let mut package_manifest_path = self.package_path();
         package_manifest_path.push("cpm.toml");
         inputs.insert(package_manifest_path);
 
         inputs.extend(self.src_files());
-        inputs
+        if let Some(linker_script) = &self.package().linker_script {
+            inputs.insert(self.package_path().join(linker_script));
+        }
+        inputs.extend(
+            self.dependency_artifacts
+                .iter()
+                .flat_map(|artifact| artifact.library_paths.iter().cloned()),
+        );
+        inputs.extend(self.rule_inputs()?.into_iter().map(|(_, input)| input));
+        Ok(inputs)
+    }
+
+    /// The file that describes the settings of the last build of this package (its resolved
+    /// profile and its toolchain), in its objects folder
+    ///
+    /// It's only rewritten when the settings change, so the objects and the output that are
+    /// older than it were built with other settings
+    pub fn build_stamp_path(&self) -> PathBuf {
+        self.objects_folder_path().join(BUILD_STAMP_FILE_NAME)
+    }
+
+    fn build_stamp(&self) -> String {
+        format!(
+            "profile = {:?}\ntoolchain = {:?}\n",
+            self.profile, self.toolchain
+        )
+    }
+
+    /// Writes the build stamp when the settings of the build changed since the last build
+    fn update_build_stamp(&self) -> std::io::Result<()> {
+        let stamp_path = self.build_stamp_path();
+        let stamp = self.build_stamp();
+        if std::fs::read_to_string(&stamp_path).ok().as_ref() == Some(&stamp) {
+            return Ok(());
+        }
+        create_parent_folder(&stamp_path)?;
+        std::fs::write(stamp_path, stamp)
     }
 
     /// Checks if the package needs recompilation
     ///
-    /// This only checks the inputs with the output path
-    pub fn needs_recompilation(&self) -> bool {
-        let inputs = self.inputs();
+    /// This only checks the inputs and the build stamp with the output path,
+    /// and the output with the images
+    pub fn needs_recompilation(&self) -> Result<bool, RuleError> {
+        let inputs = self.inputs()?;
         let output = self.output_path();
-        return inputs
+        Ok(inputs
+            .iter()
+            .chain([&self.build_stamp_path()])
+            .any(|input| file_needs_rebuild(input, &output))
+            || self
+                .image_paths()
+                .iter()
+                .any(|(_, image_path)| file_needs_rebuild(&output, image_path)))
+    }
+
+    /// The images created from the executable of this package with `objcopy`, next to it
+    ///
+    /// Libraries don't have images
+    pub fn image_paths(&self) -> Vec<(ImageFormat, PathBuf)> {
+        if self.package().kind != PackageKind::Executable {
+            return vec![];
+        }
+        let output_path = self.output_path();
+        self.package()
+            .images
             .iter()
-            .any(|input| file_needs_rebuild(input, &output));
+            .map(|format| (*format, output_path.with_extension(format.extension())))
+            .collect()
+    }
+
+    /// Path of the [`SizeHistory`] of the output, in the `size` folder of the target folder
+    pub fn size_history_path(&self) -> PathBuf {
+        let mut size_history_path = self.target_folder_path();
+        size_history_path.push("size");
+        size_history_path.push(format!(
+            "{}-{}.toml",
+            self.package().name,
+            self.package().version
+        ));
+        size_history_path
+    }
+
+    /// The sizes of the sections of the output and of its previous build
+    ///
+    /// The output is measured with the `size` program of the toolchain when it was linked
+    /// after the sizes were recorded
+    pub fn size_history(&self) -> Result<SizeHistory, errors::BuildPackageError> {
+        let history_path = self.size_history_path();
+        let output_path = self.output_path();
+        let modified = |path: &Path| path.metadata().and_then(|m| m.modified()).ok();
+        if let Some(history) = SizeHistory::load(&history_path) {
+            if modified(&history_path) >= modified(&output_path) {
+                return Ok(history);
+            }
+        }
+        let sizes = size::section_sizes(&self.toolchain, &output_path)?;
+        Ok(SizeHistory::record(&history_path, sizes)?)
+    }
+
+    /// Checks the sizes of the sections of the output against the size budget of the package
+    ///
+    /// The output is only measured when the package has a size budget
+    fn check_size_budget(&self) -> Result<(), errors::BuildPackageError> {
+        let Some(budget) = &self.package().size_budget else {
+            return Ok(());
+        };
+        match self.size_history()?.current.exceeded_budget(budget) {
+            Some((section, size, limit)) => Err(errors::BuildPackageError::SizeBudgetExceeded {
+                section: section.to_string(),
+                size,
+                limit,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Creates the images of the executable with the `objcopy` of the toolchain
+    fn create_images(&self) -> Result<(), errors::BuildPackageError> {
+        for (format, image_path) in self.image_paths() {
+            let status = Command::new(self.toolchain.objcopy_program())
+                .args(["-O", format.objcopy_format()])
+                .arg(self.output_path())
+                .arg(&image_path)
+                .stdout(Stdio::inherit())
+                .stderr(Stdio::inherit())
+                .status()?;
+            if !status.success() {
+                return Err(errors::BuildPackageError::ImageError {
+                    image_path,
+                    exit_code: status,
+                });
+            }
+        }
+        Ok(())
     }
 
     fn absolute_source_path_to_relative_path(&self, source_file: impl Into<PathBuf>) -> PathBuf {
         let source_file = source_file.into();
-        if source_file.is_absolute() {
-            let package_src_folder_path = self.absolute_path_of_src_folder();
-            source_file
-                .strip_prefix(&package_src_folder_path)
-                .expect("all source files must be inside the source folder")
-                .into()
+        if let Ok(generated_file) = source_file.strip_prefix(self.generated_folder_path()) {
+            let mut relative_path = PathBuf::from("generated");
+            relative_path.push(generated_file);
+            relative_path
+        } else if let Ok(src_file) = source_file.strip_prefix(self.absolute_path_of_src_folder()) {
+            src_file.into()
+        } else if let Ok(package_file) = source_file.strip_prefix(self.package_path()) {
+            // files matched by the `sources` patterns
+            let mut relative_path = PathBuf::from("sources");
+            relative_path.push(package_file);
+            relative_path
+        } else if source_file.is_absolute() {
+            let mut relative_path = PathBuf::from("sources");
+            relative_path.push(source_file.file_name().unwrap_or_default());
+            relative_path
         } else {
-            source_file.into()
+            source_file
         }
     }
 
-    pub fn object_file_folder_for_source_file(&self, source_file: impl Into<PathBuf>) -> PathBuf {
-        let source_file: PathBuf = source_file.into();
-        let path_from_source_folder = self.absolute_source_path_to_relative_path(source_file);
-        let mut objects_folder_path = PathBuf::from("target/objects");
+    /// The folder where the object files of this package are placed
+    pub fn objects_folder_path(&self) -> PathBuf {
+        let mut objects_folder_path = self.target_folder_path();
+        objects_folder_path.push("objects");
         objects_folder_path.push(format!(
             "{}-{}",
             self.package().name,
             self.package().version
         ));
+        objects_folder_path
+    }
+
+    /// The object files of the last build of this package, sorted by path
+    pub fn object_files(&self) -> Vec<PathBuf> {
+        let mut object_files: Vec<PathBuf> = WalkDir::new(self.objects_folder_path())
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|e| {
+                e.file_type().is_file() && e.path().extension().is_some_and(|ext| ext == "o")
+            })
+            .map(|e| e.path().to_path_buf())
+            .collect();
+        object_files.sort();
+        object_files
+    }
+
+    pub fn object_file_folder_for_source_file(&self, source_file: impl Into<PathBuf>) -> PathBuf {
+        let source_file: PathBuf = source_file.into();
+        let path_from_source_folder = self.absolute_source_path_to_relative_path(source_file);
+        let mut objects_folder_path = self.objects_folder_path();
         objects_folder_path.push(path_from_source_folder.parent().unwrap());
         objects_folder_path
     }
@@ -189,34 +810,43 @@ impl PackageBuilder {
     }
     /// Compiles the package
     pub fn compile(&self, compiler: &dyn Compiler) -> Result<(), errors::BuildPackageError> {
-        if !self.needs_recompilation() {
+        // the system libraries are checked even when the package is up to date
+        let system_libraries = self.system_libraries()?;
+        self.update_build_stamp()?;
+        if !self.needs_recompilation()? {
+            self.check_size_budget()?;
+            if !self.pc_file_path().exists() {
+                self.write_pc_file()?;
+            }
+            if self
+                .soname_link_path()
+                .is_some_and(|link_path| link_path.symlink_metadata().is_err())
+            {
+                self.create_soname_link()?;
+            }
             return Ok(());
         }
-        let src_files = self.src_files();
+        let generated_src_files = self.run_rules()?;
+        let src_files = self.src_files().chain(generated_src_files);
+        let build_stamp_path = self.build_stamp_path();
         let mut compilation_errors = vec![];
         let mut object_files = vec![];
         for src in src_files {
             let object_file_path = self.object_file_for_source_file(&src);
             create_parent_folder(&object_file_path)?;
             object_files.push(object_file_path.clone());
-            if file_needs_rebuild(&src, &object_file_path) {
-                let output = compiler
-                    .compile_command(
-                        self.package_path(),
-                        src.clone(),
-                        object_file_path.clone(),
-                        self.package(),
-                    )
-                    .stdout(Stdio::inherit())
-                    .stderr(Stdio::inherit())
-                    .output()?;
-                if !output.status.success() {
-                    compilation_errors.push(CompileFileError {
-                        exit_code: output.status,
-                        src_file_path: src,
-                        object_file_path,
-                    });
-                }
+            if file_needs_rebuild(&src, &object_file_path)
+                || file_needs_rebuild(&build_stamp_path, &object_file_path)
+            {
+                let command = self.compile_command(
+                    compiler,
+                    self.package(),
+                    &src,
+                    &object_file_path,
+                    system_libraries,
+                    &self.dependency_artifacts,
+                );
+                compilation_errors.extend(run_compile_command(command, src, object_file_path)?);
             }
         }
         if !compilation_errors.is_empty() {
@@ -229,13 +859,26 @@ impl PackageBuilder {
         }
         self.create_output_folder()?;
         let package_output_path = self.output_path();
-        let link_command_output = compiler
-            .link_command(
-                self.package_path(),
-                object_files,
-                package_output_path.clone(),
-                self.package(),
-            )
+        let mut link_command = compiler.link_command(
+            self.package_path(),
+            object_files,
+            package_output_path.clone(),
+            self.package(),
+            &self.profile,
+            &self.toolchain,
+        );
+        if self.package().kind != PackageKind::StaticLibrary {
+            for artifact in &self.dependency_artifacts {
+                link_command.args(&artifact.library_paths);
+            }
+            for library in system_libraries {
+                link_command.args(&library.libs);
+            }
+            for artifact in &self.dependency_artifacts {
+                link_command.args(&artifact.system_libs);
+            }
+        }
+        let link_command_output = link_command
             .stdout(Stdio::inherit())
             .stderr(Stdio::inherit())
             .output()?;
@@ -245,9 +888,221 @@ impl PackageBuilder {
                 exit_code: link_command_output.status,
             });
         }
+        self.create_soname_link()?;
+        self.check_size_budget()?;
+        self.create_images()?;
+        self.write_pc_file()?;
         Ok(())
     }
 }
+/// The methods that compile source files and build the [`DevTarget`] programs
+impl PackageBuilder {
+    /// Creates the command that compiles a source file of this package, or of one of its
+    /// [`DevTarget`] programs, with the include folders of `dependency_artifacts`
+    fn compile_command(
+        &self,
+        compiler: &dyn Compiler,
+        package: &manifest::Package,
+        src: &Path,
+        object_file_path: &Path,
+        system_libraries: &[SystemLibrary],
+        dependency_artifacts: &[DependencyArtifact],
+    ) -> Command {
+        let mut command = compiler.compile_command(
+            self.package_path(),
+            src.to_path_buf(),
+            object_file_path.to_path_buf(),
+            package,
+            &self.profile,
+            &self.toolchain,
+        );
+        if !self.target_manifest.rules.is_empty() {
+            command.arg(format!("-I{}", self.generated_folder_path().display()));
+        }
+        for include_folder in dependency_artifacts
+            .iter()
+            .flat_map(|artifact| &artifact.include_folders)
+        {
+            command.arg(format!("-I{}", include_folder.display()));
+        }
+        for library in system_libraries {
+            command.args(&library.cflags);
+        }
+        command
+    }
+
+    /// The source files of the programs of a [`DevTarget`], sorted by name
+    pub fn dev_target_sources(&self, target: DevTarget) -> Vec<PathBuf> {
+        let pattern = self
+            .package_path()
+            .join(target.folder_name())
+            .join("*.c")
+            .to_string_lossy()
+            .to_string();
+        let mut sources: Vec<PathBuf> = glob::glob(&pattern)
+            .map(|paths| paths.filter_map(|p| p.ok()).collect())
+            .unwrap_or_default();
+        sources.sort();
+        sources
+    }
+
+    /// The executable built from a source file of a [`DevTarget`]
+    ///
+    /// It's placed in `target/<folder>/<name>-<version>/<stem>`
+    pub fn dev_target_output_path(&self, target: DevTarget, source: &Path) -> PathBuf {
+        let mut output_path = self.target_folder_path();
+        output_path.push(target.folder_name());
+        output_path.push(format!(
+            "{}-{}",
+            self.package().name,
+            self.package().version
+        ));
+        output_path.push(source.file_stem().unwrap_or_default());
+        output_path.set_extension(self.target().executable_extension());
+        output_path
+    }
+
+    /// What the [`DevTarget`] programs link with to use this package: the library of
+    /// library packages, or the object files of executable packages without `<src_folder>/main.c`
+    ///
+    /// The package must be compiled first
+    fn package_link_inputs(&self) -> Result<Vec<PathBuf>, errors::BuildPackageError> {
+        if self.package().kind != PackageKind::Executable {
+            return Ok(vec![self.output_path()]);
+        }
+        let main_path = self.absolute_path_of_src_folder().join("main.c");
+        Ok(self
+            .src_files()
+            .chain(self.run_rules()?)
+            .filter(|src| *src != main_path)
+            .map(|src| self.object_file_for_source_file(src))
+            .collect())
+    }
+
+    /// Compiles a program of a [`DevTarget`] and returns the path of its executable
+    ///
+    /// The program is linked with the package, its dev-dependencies and its dependencies.
+    /// The package must be compiled first
+    pub fn compile_dev_target(
+        &self,
+        compiler: &dyn Compiler,
+        target: DevTarget,
+        source: &Path,
+    ) -> Result<PathBuf, errors::BuildPackageError> {
+        let output_path = self.dev_target_output_path(target, source);
+        let object_file_path = output_path.with_extension("o");
+        let package_inputs = self.package_link_inputs()?;
+        let mut harness_headers = vec![];
+        let mut dependency_artifacts: Vec<DependencyArtifact> = self
+            .dev_dependency_artifacts
+            .iter()
+            .chain(&self.dependency_artifacts)
+            .cloned()
+            .collect();
+        if target == DevTarget::Benches {
+            let include_folder = self
+                .target_folder_path()
+                .join(target.folder_name())
+                .join("include");
+            let header_path = bench::write_harness_header(&include_folder)?;
+            dependency_artifacts.push(DependencyArtifact {
+                include_folders: vec![include_folder],
+                library_paths: vec![],
+                system_libs: vec![],
+            });
+            harness_headers.push(header_path);
+        }
+        let library_paths: Vec<&PathBuf> = self
+            .dev_dependency_artifacts
+            .iter()
+            .chain(&self.dependency_artifacts)
+            .flat_map(|artifact| &artifact.library_paths)
+            .collect();
+        let mut manifest_path = self.package_path();
+        manifest_path.push("cpm.toml");
+        let build_stamp_path = self.build_stamp_path();
+        let needs_rebuild = [source, manifest_path.as_path(), build_stamp_path.as_path()]
+            .into_iter()
+            .chain(package_inputs.iter().map(PathBuf::as_path))
+            .chain(harness_headers.iter().map(PathBuf::as_path))
+            .chain(library_paths.iter().map(|path| path.as_path()))
+            .any(|input| file_needs_rebuild(input, &output_path));
+        if !needs_rebuild {
+            return Ok(output_path);
+        }
+        let mut package = self.package().clone();
+        package.kind = PackageKind::Executable;
+        let system_libraries = self.system_libraries()?;
+        create_parent_folder(&object_file_path)?;
+        let command = self.compile_command(
+            compiler,
+            &package,
+            source,
+            &object_file_path,
+            system_libraries,
+            &dependency_artifacts,
+        );
+        let compilation_errors =
+            run_compile_command(command, source.to_path_buf(), object_file_path.clone())?;
+        if let Some(error) = compilation_errors {
+            return Err(errors::BuildPackageError::CompilationError(vec![error]));
+        }
+        let mut objects = vec![object_file_path];
+        objects.extend(package_inputs);
+        let mut link_command = compiler.link_command(
+            self.package_path(),
+            objects,
+            output_path.clone(),
+            &package,
+            &self.profile,
+            &self.toolchain,
+        );
+        link_command.args(library_paths);
+        if target == DevTarget::Fuzz {
+            // links libFuzzer, which provides the `main` function
+            link_command.arg("-fsanitize=fuzzer");
+        }
+        for library in system_libraries {
+            link_command.args(&library.libs);
+        }
+        for artifact in self
+            .dev_dependency_artifacts
+            .iter()
+            .chain(&self.dependency_artifacts)
+        {
+            link_command.args(&artifact.system_libs);
+        }
+        let link_command_output = link_command
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .output()?;
+        if !link_command_output.status.success() {
+            return Err(errors::BuildPackageError::LinkingError {
+                output_file_path: output_path,
+                exit_code: link_command_output.status,
+            });
+        }
+        Ok(output_path)
+    }
+}
+
+/// Runs a compile command and returns the error of the compilation, if it failed
+fn run_compile_command(
+    mut command: Command,
+    src_file_path: PathBuf,
+    object_file_path: PathBuf,
+) -> std::io::Result<Option<CompileFileError>> {
+    let output = command
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()?;
+    Ok((!output.status.success()).then_some(CompileFileError {
+        exit_code: output.status,
+        src_file_path,
+        object_file_path,
+    }))
+}
+
 /// Checks if a file needs to be rebuilt based on the modified at timestamps.
 ///
 /// If the input file was modified after the output path was modified, `true` is returned, `false` otherwise
@@ -272,6 +1127,23 @@ pub fn file_needs_rebuild(input_path: impl Into<PathBuf>, output_path: impl Into
     object_modified < source_modified
 }
 
+/// Replaces the `{input}`, `{stem}`, `{name}` and `{out_dir}` placeholders of a rule template
+pub fn expand_rule_template(template: &str, input_path: &Path, out_dir: &Path) -> String {
+    let stem = input_path
+        .file_stem()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    let name = input_path
+        .file_name()
+        .map(|s| s.to_string_lossy())
+        .unwrap_or_default();
+    template
+        .replace("{input}", &input_path.to_string_lossy())
+        .replace("{stem}", &stem)
+        .replace("{name}", &name)
+        .replace("{out_dir}", &out_dir.to_string_lossy())
+}
+
 pub fn create_parent_folder(path: impl Into<PathBuf>) -> std::io::Result<()> {
     let path = path.into();
     if let Some(parent) = path.parent() {
@@ -279,3 +1151,42 @@ pub fn create_parent_folder(path: impl Into<PathBuf>) -> std::io::Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an empty executable package in a new temporary folder
+    fn temporary_package(name: &str) -> PathBuf {
+        let package_path = std::env::temp_dir().join(format!("cpm-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&package_path);
+        std::fs::create_dir_all(package_path.join("src")).unwrap();
+        std::fs::write(
+            package_path.join("cpm.toml"),
+            format!("[package]\nname = \"{name}\"\nversion = \"0.1.0\"\n"),
+        )
+        .unwrap();
+        package_path
+    }
+
+    #[test]
+    fn build_stamp_changes_with_the_profile() {
+        let package_path = temporary_package("stamp");
+        let mut builder = PackageBuilder::new(&package_path, &package_path).unwrap();
+        builder.update_build_stamp().unwrap();
+        let stamp_path = builder.build_stamp_path();
+        let first_stamp = std::fs::read_to_string(&stamp_path).unwrap();
+
+        builder.update_build_stamp().unwrap();
+        assert_eq!(std::fs::read_to_string(&stamp_path).unwrap(), first_stamp);
+
+        let mut profile = builder.profile().clone();
+        profile.opt_level = "3".to_string();
+        builder.set_profile(profile);
+        builder.update_build_stamp().unwrap();
+        let second_stamp = std::fs::read_to_string(&stamp_path).unwrap();
+        assert_ne!(second_stamp, first_stamp);
+        assert!(second_stamp.contains("opt_level: \"3\""));
+        std::fs::remove_dir_all(package_path).unwrap();
+    }
+}
@@ -1,6 +1,9 @@
 use std::{path::PathBuf, process::ExitStatus};
 
-use crate::manifest;
+use crate::{
+    manifest, pkg_config::errors::SystemDependencyError, size::errors::SizeError,
+    target::errors::TargetError,
+};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -11,6 +14,8 @@ pub enum CreatePackageCompilerError {
     InvalidWorkspaceManifest(manifest::errors::ManifestLoadError),
     #[error("`package` section not found")]
     PackageNotFound,
+    #[error("{0}")]
+    InvalidTarget(#[from] TargetError),
 }
 
 #[derive(Error, Debug)]
@@ -21,6 +26,29 @@ pub struct CompileFileError {
     pub object_file_path: PathBuf,
 }
 
+#[derive(Error, Debug)]
+pub enum RuleError {
+    #[error("invalid input pattern `{pattern}`: {error}")]
+    InvalidInputPattern {
+        pattern: String,
+        error: glob::PatternError,
+    },
+    #[error("rule for `{pattern}` has an empty command")]
+    EmptyCommand { pattern: String },
+    #[error("rule command for {input_path:?} failed (exit code {exit_code:?})")]
+    CommandFailed {
+        input_path: PathBuf,
+        exit_code: ExitStatus,
+    },
+    #[error("rule command for {input_path:?} did not produce {output_path:?}")]
+    MissingOutput {
+        input_path: PathBuf,
+        output_path: PathBuf,
+    },
+    #[error("Io error: {0}")]
+    IOError(std::io::Error),
+}
+
 #[derive(Error, Debug)]
 pub enum BuildPackageError {
     #[error("no files to compile (no source files found)")]
@@ -32,6 +60,23 @@ pub enum BuildPackageError {
         output_file_path: PathBuf,
         exit_code: ExitStatus,
     },
+    #[error("failed to create image {image_path:?} (exit code {exit_code:?})")]
+    ImageError {
+        image_path: PathBuf,
+        exit_code: ExitStatus,
+    },
+    #[error("`{section}` is {size} bytes, which exceeds its size budget of {limit} bytes")]
+    SizeBudgetExceeded {
+        section: String,
+        size: u64,
+        limit: u64,
+    },
+    #[error("failed to measure the output: {0}")]
+    SizeError(#[from] SizeError),
+    #[error("{0}")]
+    SystemDependencyError(#[from] SystemDependencyError),
+    #[error("failed to run code generation rule: {0}")]
+    RuleError(#[from] RuleError),
     #[error("Io error: {0}")]
     IOError(#[from] std::io::Error),
 }
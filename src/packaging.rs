@@ -0,0 +1,226 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use flate2::{Compression, GzBuilder};
+use walkdir::WalkDir;
+
+use crate::{
+    filenames::MANIFEST_FILE_NAME,
+    manifest::{Dependency, Manifest},
+    package::builder::PackageBuilder,
+};
+
+pub mod errors;
+use errors::PackagingError;
+
+/// Prefixes of the file names that are included in the tarball as license files
+const LICENSE_FILE_PREFIXES: &[&str] = &["LICENSE", "LICENCE", "COPYING", "UNLICENSE"];
+
+/// Folder where the tarballs are created and verified: `$workspace_path/target/package`
+pub fn package_folder_path(workspace_path: &Path) -> PathBuf {
+    workspace_path.join("target").join("package")
+}
+
+/// Returns the manifest that is put in the tarball of a package
+///
+/// Workspace, registry, vendor and patch settings and the dev-dependencies are removed and
+/// every dependency is turned into a registry dependency,
+/// so path dependencies must specify a version
+pub fn normalized_manifest(manifest: &Manifest) -> Result<Manifest, PackagingError> {
+    let mut normalized = manifest.clone();
+    normalized.workspace = None;
+    normalized.registry = None;
+    normalized.vendor = None;
+    normalized.patch.clear();
+    normalized.toolchain.clear();
+    normalized.dev_dependencies.clear();
+    normalize_dependencies(&mut normalized.dependencies)?;
+    normalize_dependencies(&mut normalized.build_dependencies)?;
+    for settings in normalized.target.values_mut() {
+        settings.dev_dependencies.clear();
+        normalize_dependencies(&mut settings.dependencies)?;
+        normalize_dependencies(&mut settings.build_dependencies)?;
+    }
+    Ok(normalized)
+}
+
+/// Turns every dependency of a table into a registry dependency
+fn normalize_dependencies(
+    dependencies: &mut BTreeMap<String, Dependency>,
+) -> Result<(), PackagingError> {
+    for (name, dependency) in dependencies.iter_mut() {
+        if let Dependency::Detailed(detailed) = dependency {
+            let requirement = detailed
+                .version
+                .clone()
+                .ok_or_else(|| PackagingError::DependencyWithoutVersion(name.clone()))?;
+            *dependency = Dependency::Version(requirement);
+        }
+    }
+    Ok(())
+}
+
+/// Creates a deterministic `.tar.gz` of a package in `target/package` and returns its path
+///
+/// The tarball contains a `<name>-<version>` folder with the normalized manifest,
+/// the source folder, the include folder, the files of the `sources` patterns,
+/// the inputs of the code generation rules, the linker script and the license files. Entries are sorted and
+/// their timestamps and owners are cleared, so packaging the same sources twice
+/// produces the same tarball.
+pub fn create_package_tarball(package_builder: &PackageBuilder) -> Result<PathBuf, PackagingError> {
+    let package = package_builder.package();
+    let package_path = package_builder.package_path();
+    let root_folder = PathBuf::from(format!("{}-{}", package.name, package.version));
+    let manifest = normalized_manifest(package_builder.package_manifest())?;
+    let manifest_content = format!(
+        "# This manifest was normalized by `cpm package`\n{}",
+        toml::to_string(&manifest)?
+    );
+
+    let mut files: Vec<PathBuf> = vec![];
+    for folder in [&package.src_folder, &package.include_folder] {
+        let folder_path = package_path.join(folder);
+        if !folder_path.exists() {
+            continue;
+        }
+        for entry in WalkDir::new(&folder_path).sort_by_file_name() {
+            let entry = entry.map_err(std::io::Error::from)?;
+            if entry.file_type().is_file() {
+                files.push(
+                    entry
+                        .path()
+                        .strip_prefix(&package_path)
+                        .map_err(|_| PackagingError::OutsidePackage(entry.path().to_path_buf()))?
+                        .to_path_buf(),
+                );
+            }
+        }
+    }
+    // the files of the `sources` patterns of every target
+    let package_manifest = package_builder.package_manifest();
+    let source_patterns = package_manifest
+        .package
+        .iter()
+        .flat_map(|package| &package.sources)
+        .chain(
+            package_manifest
+                .target
+                .values()
+                .flat_map(|settings| &settings.sources),
+        );
+    for pattern in source_patterns {
+        let Ok(paths) = glob::glob(&package_path.join(pattern).to_string_lossy()) else {
+            continue;
+        };
+        for path in paths.filter_map(|p| p.ok()) {
+            files.push(
+                path.strip_prefix(&package_path)
+                    .map_err(|_| PackagingError::OutsidePackage(path.clone()))?
+                    .to_path_buf(),
+            );
+        }
+    }
+    // the inputs of the code generation rules, which can be anywhere in the package
+    for (_, input_path) in package_builder.rule_inputs()? {
+        files.push(
+            input_path
+                .strip_prefix(&package_path)
+                .map_err(|_| PackagingError::OutsidePackage(input_path.clone()))?
+                .to_path_buf(),
+        );
+    }
+    if let Some(linker_script) = &package.linker_script {
+        let linker_script_path = package_path.join(linker_script);
+        files.push(
+            linker_script_path
+                .strip_prefix(&package_path)
+                .map_err(|_| PackagingError::OutsidePackage(linker_script_path.clone()))?
+                .to_path_buf(),
+        );
+    }
+    for entry in std::fs::read_dir(&package_path)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_uppercase();
+        if entry.file_type()?.is_file()
+            && LICENSE_FILE_PREFIXES
+                .iter()
+                .any(|prefix| file_name.starts_with(prefix))
+        {
+            files.push(PathBuf::from(entry.file_name()));
+        }
+    }
+    files.sort();
+    files.dedup();
+
+    let output_folder = package_folder_path(&package_builder.workspace_path());
+    std::fs::create_dir_all(&output_folder)?;
+    let tarball_path = output_folder.join(format!("{}.tar.gz", root_folder.display()));
+    let encoder = GzBuilder::new().write(File::create(&tarball_path)?, Compression::best());
+    let mut archive = tar::Builder::new(encoder);
+    append_file(
+        &mut archive,
+        &root_folder.join(MANIFEST_FILE_NAME),
+        manifest_content.as_bytes(),
+        false,
+    )?;
+    for file in files {
+        let content = std::fs::read(package_path.join(&file))?;
+        append_file(
+            &mut archive,
+            &root_folder.join(&file),
+            &content,
+            is_executable(&package_path.join(&file)),
+        )?;
+    }
+    archive.into_inner()?.finish()?.flush()?;
+    Ok(tarball_path)
+}
+
+fn append_file(
+    archive: &mut tar::Builder<impl Write>,
+    path: &Path,
+    content: &[u8],
+    executable: bool,
+) -> Result<(), PackagingError> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(content.len() as u64);
+    header.set_mode(if executable { 0o755 } else { 0o644 });
+    header.set_mtime(0);
+    header.set_uid(0);
+    header.set_gid(0);
+    header.set_entry_type(tar::EntryType::Regular);
+    archive.append_data(&mut header, path, content)?;
+    Ok(())
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::metadata(path).is_ok_and(|m| m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(_path: &Path) -> bool {
+    false
+}
+
+/// Extracts a package tarball into `target/package` to verify it,
+/// and returns the folder of the extracted package
+pub fn extract_for_verification(
+    tarball_path: &Path,
+    workspace_path: &Path,
+    package_folder_name: &str,
+) -> Result<PathBuf, PackagingError> {
+    let output_folder = package_folder_path(workspace_path);
+    let extracted_path = output_folder.join(package_folder_name);
+    if extracted_path.exists() {
+        std::fs::remove_dir_all(&extracted_path)?;
+    }
+    let tarball = File::open(tarball_path)?;
+    tar::Archive::new(flate2::read::GzDecoder::new(tarball)).unpack(&output_folder)?;
+    Ok(extracted_path)
+}
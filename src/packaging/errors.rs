@@ -0,0 +1,19 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::package::builder::errors::RuleError;
+
+#[derive(Error, Debug)]
+pub enum PackagingError {
+    #[error("dependency `{0}` must specify a `version` to be packaged")]
+    DependencyWithoutVersion(String),
+    #[error("file {0:?} is outside of the package folder")]
+    OutsidePackage(PathBuf),
+    #[error("{0}")]
+    RuleError(#[from] RuleError),
+    #[error("failed to serialize the manifest: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Io error: {0}")]
+    IOError(#[from] std::io::Error),
+}
@@ -0,0 +1,262 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{
+    profile::{BuildProfile, Pgo},
+    toolchain::Toolchain,
+};
+
+pub mod errors;
+use errors::PgoError;
+
+/// The file, in the profiles folder, that records how the profiles were collected
+const TRAINING_FILE_NAME: &str = "training.toml";
+
+/// The folder where the execution profiles of a workspace are collected for the target
+/// of `toolchain` (e.g. `target/pgo`)
+pub fn profiles_folder_path(workspace_path: &Path, toolchain: &Toolchain) -> PathBuf {
+    workspace_path
+        .join("target")
+        .join(toolchain.output_subfolder())
+        .join("pgo")
+}
+
+/// The build profile of a phase of profile-guided optimization, based on `profile`
+pub fn phase_profile(profile: &BuildProfile, phase: Pgo) -> BuildProfile {
+    BuildProfile {
+        pgo: Some(phase),
+        ..profile.clone()
+    }
+}
+
+/// The profiles (`.gcda` files) of a folder, relative to it
+fn profile_files(folder: &Path) -> Vec<PathBuf> {
+    WalkDir::new(folder)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "gcda"))
+        .filter_map(|e| e.path().strip_prefix(folder).ok().map(Path::to_path_buf))
+        .collect()
+}
+
+/// Removes the profiles of the instrumented outputs, the counts of a previous training
+/// would otherwise be added to the next one
+pub fn remove_profiles(instrumented_folder: &Path) -> std::io::Result<()> {
+    for profile in profile_files(instrumented_folder) {
+        std::fs::remove_file(instrumented_folder.join(profile))?;
+    }
+    Ok(())
+}
+
+/// Replaces the profiles of the profiles folder with the ones written next to the objects
+/// of the instrumented outputs by the training, and returns their number
+///
+/// They keep their paths relative to the output folder, so the optimized build finds them
+/// next to its own objects
+pub fn collect_profiles(
+    instrumented_folder: &Path,
+    profiles_folder: &Path,
+) -> std::io::Result<usize> {
+    match std::fs::remove_dir_all(profiles_folder) {
+        Err(error) if error.kind() != std::io::ErrorKind::NotFound => return Err(error),
+        _ => {}
+    }
+    copy_profiles(instrumented_folder, profiles_folder)
+}
+
+/// Copies the collected profiles next to the objects of the optimized outputs
+pub fn install_profiles(profiles_folder: &Path, optimized_folder: &Path) -> std::io::Result<usize> {
+    copy_profiles(profiles_folder, optimized_folder)
+}
+
+fn copy_profiles(from: &Path, to: &Path) -> std::io::Result<usize> {
+    let profiles = profile_files(from);
+    for profile in &profiles {
+        let destination = to.join(profile);
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(from.join(profile), destination)?;
+    }
+    Ok(profiles.len())
+}
+
+/// How the profiles of the profiles folder were collected, saved after the training
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug)]
+pub struct Training {
+    /// The build profile of the instrumented build
+    pub profile: String,
+    /// The target triple of the instrumented build
+    pub target: String,
+    /// The packages whose training workloads ran
+    pub packages: Vec<String>,
+}
+
+impl Training {
+    fn path(profiles_folder: &Path) -> PathBuf {
+        profiles_folder.join(TRAINING_FILE_NAME)
+    }
+
+    /// Loads the training record of the profiles folder, `None` when there was no training
+    pub fn load(profiles_folder: &Path) -> Result<Option<Self>, PgoError> {
+        let path = Self::path(profiles_folder);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let content = std::fs::read_to_string(&path)?;
+        toml::from_str(&content)
+            .map(Some)
+            .map_err(|error| PgoError::InvalidTraining { path, error })
+    }
+
+    /// Saves the training record in the profiles folder
+    pub fn save(&self, profiles_folder: &Path) -> Result<(), PgoError> {
+        std::fs::create_dir_all(profiles_folder)?;
+        std::fs::write(Self::path(profiles_folder), toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// The inputs that were modified after the training, their profiles are stale
+    pub fn modified_inputs<'a>(
+        profiles_folder: &Path,
+        inputs: impl IntoIterator<Item = &'a PathBuf>,
+    ) -> Vec<&'a PathBuf> {
+        let trained_at = std::fs::metadata(Self::path(profiles_folder))
+            .and_then(|metadata| metadata.modified())
+            .ok();
+        let mut modified: Vec<&PathBuf> = inputs
+            .into_iter()
+            .filter(|input| {
+                let input_modified = std::fs::metadata(input).and_then(|m| m.modified()).ok();
+                match (input_modified, trained_at) {
+                    (Some(input_modified), Some(trained_at)) => input_modified > trained_at,
+                    _ => false,
+                }
+            })
+            .collect();
+        modified.sort();
+        modified
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+
+    /// A new temporary folder, removed when dropped
+    struct TemporaryFolder(PathBuf);
+
+    impl TemporaryFolder {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("cpm-pgo-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            Self(path)
+        }
+
+        fn write(&self, path: &str) -> PathBuf {
+            let path = self.0.join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, path.display().to_string()).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TemporaryFolder {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn sorted_profiles(folder: &Path) -> Vec<PathBuf> {
+        let mut profiles = profile_files(folder);
+        profiles.sort();
+        profiles
+    }
+
+    #[test]
+    fn profiles_keep_their_paths_relative_to_the_output_folders() {
+        let folder = TemporaryFolder::new("collect");
+        let instrumented = folder.0.join("release/pgo-generate");
+        let profiles = folder.0.join("pgo");
+        let optimized = folder.0.join("release/pgo-use");
+        folder.write("release/pgo-generate/app/obj/src/main.gcda");
+        folder.write("release/pgo-generate/app/obj/src/main.o");
+        folder.write("release/pgo-generate/json/obj/src/parse.gcda");
+        // the profiles of a previous training are replaced
+        folder.write("pgo/app/obj/src/removed.gcda");
+
+        assert_eq!(collect_profiles(&instrumented, &profiles).unwrap(), 2);
+        let expected = [
+            PathBuf::from("app/obj/src/main.gcda"),
+            PathBuf::from("json/obj/src/parse.gcda"),
+        ];
+        assert_eq!(sorted_profiles(&profiles), expected);
+        assert!(!profiles.join("app/obj/src/main.o").exists());
+
+        assert_eq!(install_profiles(&profiles, &optimized).unwrap(), 2);
+        assert_eq!(sorted_profiles(&optimized), expected);
+        assert_eq!(
+            std::fs::read_to_string(optimized.join("json/obj/src/parse.gcda")).unwrap(),
+            instrumented
+                .join("json/obj/src/parse.gcda")
+                .display()
+                .to_string()
+        );
+
+        remove_profiles(&instrumented).unwrap();
+        assert!(sorted_profiles(&instrumented).is_empty());
+        assert!(instrumented.join("app/obj/src/main.o").exists());
+    }
+
+    #[test]
+    fn training_records_are_saved_and_loaded() {
+        let folder = TemporaryFolder::new("training");
+        assert_eq!(Training::load(&folder.0).unwrap(), None);
+        let training = Training {
+            profile: "release".to_string(),
+            target: "x86_64-unknown-linux-gnu".to_string(),
+            packages: vec!["app".to_string(), "json".to_string()],
+        };
+        training.save(&folder.0).unwrap();
+        assert_eq!(Training::load(&folder.0).unwrap(), Some(training));
+    }
+
+    #[test]
+    fn inputs_modified_after_the_training_are_stale() {
+        let folder = TemporaryFolder::new("stale");
+        let profiles = folder.0.join("pgo");
+        let unchanged = folder.write("app/src/unchanged.c");
+        let modified = folder.write("app/src/modified.c");
+        let added = folder.write("app/include/added.h");
+        let removed = folder.0.join("app/src/removed.c");
+        let inputs = [&unchanged, &modified, &added, &removed];
+        // nothing is stale without a training
+        assert!(Training::modified_inputs(&profiles, inputs).is_empty());
+
+        let training = Training {
+            profile: "release".to_string(),
+            target: "x86_64-unknown-linux-gnu".to_string(),
+            packages: vec!["app".to_string()],
+        };
+        training.save(&profiles).unwrap();
+        let trained_at = std::fs::metadata(profiles.join(TRAINING_FILE_NAME))
+            .and_then(|metadata| metadata.modified())
+            .unwrap();
+        let set_modified = |path: &Path, time: SystemTime| {
+            let file = std::fs::File::options().write(true).open(path).unwrap();
+            file.set_modified(time).unwrap();
+        };
+        set_modified(&unchanged, trained_at - Duration::from_secs(60));
+        set_modified(&modified, trained_at + Duration::from_secs(60));
+        set_modified(&added, trained_at + Duration::from_secs(1));
+        assert_eq!(
+            Training::modified_inputs(&profiles, inputs),
+            [&added, &modified]
+        );
+    }
+}
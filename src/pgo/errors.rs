@@ -0,0 +1,16 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum PgoError {
+    #[error("invalid training record {path:?}: {error}")]
+    InvalidTraining {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    #[error("failed to serialize the training record: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Io error: {0}")]
+    IOError(#[from] std::io::Error),
+}
@@ -0,0 +1,336 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use semver::VersionReq;
+
+pub mod errors;
+use errors::SystemDependencyError;
+
+/// Folders searched for `.pc` files when `pkg-config` isn't installed,
+/// in addition to the folders in `PKG_CONFIG_PATH`
+const DEFAULT_PC_FOLDERS: &[&str] = &[
+    "/usr/local/lib/pkgconfig",
+    "/usr/local/share/pkgconfig",
+    "/usr/lib/pkgconfig",
+    "/usr/lib64/pkgconfig",
+    "/usr/lib/x86_64-linux-gnu/pkgconfig",
+    "/usr/lib/aarch64-linux-gnu/pkgconfig",
+    "/usr/share/pkgconfig",
+];
+
+/// A library installed on the system, found with `pkg-config`
+pub struct SystemLibrary {
+    /// The version reported by the `.pc` file
+    pub version: String,
+    /// Flags that must be passed to the compiler to use this library
+    pub cflags: Vec<String>,
+    /// Flags that must be passed to the linker to use this library
+    pub libs: Vec<String>,
+}
+
+/// Finds a library with `pkg-config` and checks that its version satisfies `requirement`
+///
+/// Falls back to parsing the `.pc` files directly when the `pkg-config` program isn't available.
+pub fn find_system_library(
+    name: &str,
+    requirement: &VersionReq,
+) -> Result<SystemLibrary, SystemDependencyError> {
+    let library = match run_pkg_config(name, "--modversion") {
+        Ok(version) => SystemLibrary {
+            version: version.trim().to_string(),
+            cflags: split_flags(&run_pkg_config(name, "--cflags")?),
+            libs: split_flags(&run_pkg_config(name, "--libs")?),
+        },
+        Err(SystemDependencyError::PkgConfigNotInstalled) => {
+            find_system_library_in_pc_files(name, &mut HashSet::new())?
+        }
+        Err(e) => return Err(e),
+    };
+    let version = parse_lenient_version(&library.version).ok_or_else(|| {
+        SystemDependencyError::InvalidVersion {
+            name: name.to_string(),
+            version: library.version.clone(),
+        }
+    })?;
+    if !requirement.matches(&version) {
+        return Err(SystemDependencyError::VersionMismatch {
+            name: name.to_string(),
+            found: library.version,
+            required: requirement.clone(),
+        });
+    }
+    Ok(library)
+}
+
+fn run_pkg_config(name: &str, flag: &str) -> Result<String, SystemDependencyError> {
+    let output = match Command::new("pkg-config").arg(flag).arg(name).output() {
+        Ok(output) => output,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            return Err(SystemDependencyError::PkgConfigNotInstalled)
+        }
+        Err(e) => return Err(SystemDependencyError::IOError(e)),
+    };
+    if !output.status.success() {
+        return Err(SystemDependencyError::NotFound {
+            name: name.to_string(),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+fn split_flags(flags: &str) -> Vec<String> {
+    flags.split_whitespace().map(String::from).collect()
+}
+
+/// Folders that are searched for `.pc` files, in order
+pub fn pc_search_folders() -> Vec<PathBuf> {
+    let mut folders: Vec<PathBuf> = std::env::var_os("PKG_CONFIG_PATH")
+        .map(|paths| std::env::split_paths(&paths).collect())
+        .unwrap_or_default();
+    match std::env::var_os("PKG_CONFIG_LIBDIR") {
+        Some(paths) => folders.extend(std::env::split_paths(&paths)),
+        None => folders.extend(DEFAULT_PC_FOLDERS.iter().map(PathBuf::from)),
+    }
+    folders
+}
+
+/// Reads the `.pc` file of a library and of the modules it requires
+///
+/// `visited` holds the modules already read, which are skipped when they're required again
+/// (by several modules or by a cycle of `Requires`)
+fn find_system_library_in_pc_files(
+    name: &str,
+    visited: &mut HashSet<String>,
+) -> Result<SystemLibrary, SystemDependencyError> {
+    visited.insert(name.to_string());
+    let pc_file = load_pc_file(name)?;
+    let mut library = SystemLibrary {
+        version: pc_file.field("Version").unwrap_or_default(),
+        cflags: split_flags(&pc_file.field("Cflags").unwrap_or_default()),
+        libs: split_flags(&pc_file.field("Libs").unwrap_or_default()),
+    };
+    for required in pc_file.requires() {
+        if visited.contains(&required) {
+            continue;
+        }
+        let required_library = find_system_library_in_pc_files(&required, visited)?;
+        library.cflags.extend(required_library.cflags);
+        library.libs.extend(required_library.libs);
+    }
+    Ok(library)
+}
+
+fn load_pc_file(name: &str) -> Result<PcFile, SystemDependencyError> {
+    for folder in pc_search_folders() {
+        let mut pc_file_path = folder;
+        pc_file_path.push(format!("{name}.pc"));
+        if pc_file_path.exists() {
+            return PcFile::load(&pc_file_path);
+        }
+    }
+    Err(SystemDependencyError::NotFound {
+        name: name.to_string(),
+    })
+}
+
+/// Converts a version requirement into `Requires` entries of a `.pc` file (e.g. `zlib >= 1.2`)
+///
+/// pkg-config only understands plain comparisons,
+/// so caret, tilde and wildcard requirements are turned into their lower bound
+pub fn requires_entries(name: &str, requirement: &VersionReq) -> Vec<String> {
+    if requirement.comparators.is_empty() {
+        return vec![name.to_string()];
+    }
+    requirement
+        .comparators
+        .iter()
+        .map(|comparator| {
+            let operator = match comparator.op {
+                semver::Op::Exact => "=",
+                semver::Op::Greater => ">",
+                semver::Op::Less => "<",
+                semver::Op::LessEq => "<=",
+                _ => ">=",
+            };
+            let version = [Some(comparator.major), comparator.minor, comparator.patch]
+                .iter()
+                .flatten()
+                .map(u64::to_string)
+                .collect::<Vec<_>>()
+                .join(".");
+            format!("{name} {operator} {version}")
+        })
+        .collect()
+}
+
+/// Parses versions like `1.2.13`, `3.0` or `1.1.1w` into a semver version
+///
+/// Missing components are set to zero and any non numeric suffix is ignored
+pub fn parse_lenient_version(version: &str) -> Option<semver::Version> {
+    let mut components = version.trim().split('.').map(|component| {
+        component
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect::<String>()
+            .parse::<u64>()
+    });
+    let major = components.next()?.ok()?;
+    let minor = components.next().and_then(|c| c.ok()).unwrap_or(0);
+    let patch = components.next().and_then(|c| c.ok()).unwrap_or(0);
+    Some(semver::Version::new(major, minor, patch))
+}
+
+/// The contents of a pkg-config `.pc` file
+#[derive(Default)]
+pub struct PcFile {
+    variables: HashMap<String, String>,
+    fields: HashMap<String, String>,
+}
+
+impl PcFile {
+    pub fn load(path: &Path) -> Result<Self, SystemDependencyError> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(Self::parse(&content))
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let mut pc_file = Self::default();
+        for line in content.lines() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            let separator = line.find([':', '=']);
+            let Some(separator) = separator else {
+                continue;
+            };
+            let key = line[..separator].trim().to_string();
+            let value = pc_file.expand_variables(line[separator + 1..].trim());
+            if line[separator..].starts_with('=') {
+                pc_file.variables.insert(key, value);
+            } else {
+                pc_file.fields.insert(key, value);
+            }
+        }
+        pc_file
+    }
+
+    fn expand_variables(&self, value: &str) -> String {
+        let mut expanded = String::new();
+        let mut rest = value;
+        while let Some(start) = rest.find("${") {
+            let Some(end) = rest[start..].find('}') else {
+                break;
+            };
+            expanded.push_str(&rest[..start]);
+            let variable = &rest[start + 2..start + end];
+            expanded.push_str(self.variables.get(variable).map_or("", String::as_str));
+            rest = &rest[start + end + 1..];
+        }
+        expanded.push_str(rest);
+        expanded
+    }
+
+    /// Gets the value of a field (e.g. `Version`, `Cflags` or `Libs`) with its variables expanded
+    pub fn field(&self, key: &str) -> Option<String> {
+        self.fields.get(key).cloned()
+    }
+
+    /// Names of the modules listed in the `Requires` field, without their version constraints
+    pub fn requires(&self) -> Vec<String> {
+        let requires = self.field("Requires").unwrap_or_default();
+        let mut names = vec![];
+        let mut tokens = requires
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|t| !t.is_empty());
+        while let Some(token) = tokens.next() {
+            if matches!(token, "=" | "<" | ">" | "<=" | ">=" | "!=") {
+                tokens.next();
+            } else {
+                names.push(token.to_string());
+            }
+        }
+        names
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ZLIB_PC: &str = "\
+prefix=/usr
+libdir=${prefix}/lib # the folder of the library
+includedir=${prefix}/include
+
+Name: zlib
+Version: 1.2.13
+Requires: libpng >= 1.6, libm
+Cflags: -I${includedir}
+Libs: -L${libdir} -lz
+";
+
+    #[test]
+    fn variables_are_expanded_in_fields() {
+        let pc_file = PcFile::parse(ZLIB_PC);
+        assert_eq!(pc_file.field("Version").unwrap(), "1.2.13");
+        assert_eq!(pc_file.field("Cflags").unwrap(), "-I/usr/include");
+        assert_eq!(pc_file.field("Libs").unwrap(), "-L/usr/lib -lz");
+        assert_eq!(pc_file.field("Description"), None);
+        // an unterminated variable is kept as it's written
+        let pc_file = PcFile::parse("prefix=/usr\nCflags: -I${prefix}/include -I${prefix");
+        assert_eq!(
+            pc_file.field("Cflags").unwrap(),
+            "-I/usr/include -I${prefix"
+        );
+    }
+
+    #[test]
+    fn requires_are_listed_without_their_versions() {
+        assert_eq!(PcFile::parse(ZLIB_PC).requires(), ["libpng", "libm"]);
+        let pc_file = PcFile::parse("Requires: a = 1.0 b,c >= 2 d");
+        assert_eq!(pc_file.requires(), ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn lenient_versions_are_parsed() {
+        let version = |major, minor, patch| Some(semver::Version::new(major, minor, patch));
+        assert_eq!(parse_lenient_version("1.2.13"), version(1, 2, 13));
+        assert_eq!(parse_lenient_version("3.0"), version(3, 0, 0));
+        assert_eq!(parse_lenient_version(" 1.1.1w\n"), version(1, 1, 1));
+        assert_eq!(parse_lenient_version("2"), version(2, 0, 0));
+        assert_eq!(parse_lenient_version("v1.0"), None);
+    }
+
+    #[test]
+    fn requirements_become_pc_comparisons() {
+        let entries =
+            |requirement| requires_entries("zlib", &VersionReq::parse(requirement).unwrap());
+        assert_eq!(entries("*"), ["zlib"]);
+        assert_eq!(entries("^1.2"), ["zlib >= 1.2"]);
+        assert_eq!(entries("~1.2.3"), ["zlib >= 1.2.3"]);
+        assert_eq!(entries("=1.2.13"), ["zlib = 1.2.13"]);
+        assert_eq!(entries(">=1.2, <2"), ["zlib >= 1.2", "zlib < 2"]);
+    }
+
+    #[test]
+    fn required_modules_are_read_once() {
+        let folder = std::env::temp_dir().join(format!("cpm-pc-files-{}", std::process::id()));
+        std::fs::create_dir_all(&folder).unwrap();
+        // `a` requires `b` and `c`, which both require `a` again
+        let modules = [("a", "b c", "-la"), ("b", "a", "-lb"), ("c", "a b", "-lc")];
+        for (name, requires, libs) in modules {
+            std::fs::write(
+                folder.join(format!("{name}.pc")),
+                format!("Version: 1.0\nRequires: {requires}\nLibs: {libs}\n"),
+            )
+            .unwrap();
+        }
+        std::env::set_var("PKG_CONFIG_PATH", &folder);
+        let library = find_system_library_in_pc_files("a", &mut HashSet::new());
+        std::env::remove_var("PKG_CONFIG_PATH");
+        let _ = std::fs::remove_dir_all(&folder);
+        assert_eq!(library.unwrap().libs, ["-la", "-lb", "-lc"]);
+    }
+}
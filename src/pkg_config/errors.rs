@@ -0,0 +1,19 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SystemDependencyError {
+    #[error("`pkg-config` is not installed")]
+    PkgConfigNotInstalled,
+    #[error("system library `{name}` not found (is its development package installed?)")]
+    NotFound { name: String },
+    #[error("system library `{name}` has an invalid version `{version}`")]
+    InvalidVersion { name: String, version: String },
+    #[error("system library `{name}` version {found} does not satisfy `{required}`")]
+    VersionMismatch {
+        name: String,
+        found: String,
+        required: semver::VersionReq,
+    },
+    #[error("Io error: {0}")]
+    IOError(#[from] std::io::Error),
+}
@@ -0,0 +1,156 @@
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::{Command, ExitStatus},
+};
+
+use crate::manifest::RunSettings;
+
+/// Creates the command that runs a program built by cpm
+///
+/// The program is wrapped by the configured `runner`, gets the configured environment
+/// and working directory, and the `library_folders` (the folders of the dynamic libraries
+/// it links with) are added to `LD_LIBRARY_PATH` so they can be found at runtime.
+pub fn program_command(
+    program_path: &Path,
+    args: &[String],
+    run_settings: &RunSettings,
+    package_path: &Path,
+    library_folders: &[PathBuf],
+) -> Command {
+    let mut command = match run_settings.runner.split_first() {
+        Some((runner, runner_args)) => {
+            let mut command = Command::new(runner);
+            command.args(runner_args).arg(program_path);
+            command
+        }
+        None => Command::new(program_path),
+    };
+    command.args(args);
+    command.envs(&run_settings.env);
+    if let Some(cwd) = &run_settings.cwd {
+        command.current_dir(package_path.join(cwd));
+    }
+    if !library_folders.is_empty() {
+        command.env("LD_LIBRARY_PATH", dynamic_library_path(library_folders));
+    }
+    command
+}
+
+/// Builds a `LD_LIBRARY_PATH` value with the `library_folders`
+/// followed by the current value of `LD_LIBRARY_PATH`
+fn dynamic_library_path(library_folders: &[PathBuf]) -> OsString {
+    let mut folders = library_folders.to_vec();
+    if let Some(current) = std::env::var_os("LD_LIBRARY_PATH") {
+        folders.extend(std::env::split_paths(&current));
+    }
+    std::env::join_paths(folders).unwrap_or_default()
+}
+
+/// Converts the exit status of a program into an exit code and, when the program
+/// was killed by a signal, a message that describes it
+///
+/// Deaths by signal are reported with the conventional `128 + signal` exit code
+pub fn describe_exit_status(status: ExitStatus) -> (i32, Option<String>) {
+    if let Some(code) = status.code() {
+        return (code, None);
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            let message = match signal_name(signal) {
+                Some(name) => format!("terminated by {name}"),
+                None => format!("terminated by signal {signal}"),
+            };
+            return (128 + signal, Some(message));
+        }
+    }
+    (1, Some("terminated abnormally".to_string()))
+}
+
+/// Finds the first error reported by a sanitizer in the standard error of a program
+///
+/// Returns the `SUMMARY:` line of ASan, LSan and TSan (e.g. `AddressSanitizer: heap-buffer-overflow
+/// tests/parse.c:12 in main`), or the `runtime error:` line of UBSan
+pub fn sanitizer_report(stderr: &str) -> Option<String> {
+    let summary = stderr
+        .lines()
+        .filter_map(|line| line.trim().strip_prefix("SUMMARY: "))
+        .find(|summary| summary.contains("Sanitizer"));
+    let runtime_error = || {
+        stderr
+            .lines()
+            .find(|line| line.contains(": runtime error: "))
+            .map(|line| format!("UndefinedBehaviorSanitizer: {}", line.trim()))
+    };
+    summary.map(str::to_string).or_else(runtime_error)
+}
+
+/// Name of the common signals (using the Linux signal numbers)
+pub fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        5 => "SIGTRAP",
+        6 => "SIGABRT",
+        7 => "SIGBUS",
+        8 => "SIGFPE",
+        9 => "SIGKILL",
+        10 => "SIGUSR1",
+        11 => "SIGSEGV",
+        12 => "SIGUSR2",
+        13 => "SIGPIPE",
+        14 => "SIGALRM",
+        15 => "SIGTERM",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitizer_summary_is_reported() {
+        let stderr = "\
+=================================================================
+==4242==ERROR: AddressSanitizer: heap-buffer-overflow on address 0x602000000014
+READ of size 4 at 0x602000000014 thread T0
+    #0 0x401234 in main tests/parse.c:12
+SUMMARY: AddressSanitizer: heap-buffer-overflow tests/parse.c:12 in main
+==4242==ABORTING
+";
+        assert_eq!(
+            sanitizer_report(stderr).unwrap(),
+            "AddressSanitizer: heap-buffer-overflow tests/parse.c:12 in main"
+        );
+        // the summary of ASan is preferred over the runtime errors of UBSan printed before it
+        let stderr = format!("src/math.c:3:12: runtime error: division by zero\n{stderr}");
+        assert!(sanitizer_report(&stderr)
+            .unwrap()
+            .starts_with("AddressSanitizer:"));
+    }
+
+    #[test]
+    fn undefined_behavior_is_reported() {
+        let stderr = "src/math.c:7:5: runtime error: signed integer overflow: \
+                      2147483647 + 1 cannot be represented in type 'int'\n";
+        assert_eq!(
+            sanitizer_report(stderr).unwrap(),
+            "UndefinedBehaviorSanitizer: src/math.c:7:5: runtime error: signed integer overflow: \
+             2147483647 + 1 cannot be represented in type 'int'"
+        );
+    }
+
+    #[test]
+    fn regular_failures_have_no_sanitizer_report() {
+        assert_eq!(sanitizer_report(""), None);
+        assert_eq!(
+            sanitizer_report("assertion failed\nSUMMARY: 2 tests failed\n"),
+            None
+        );
+    }
+}
@@ -0,0 +1,352 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    manifest::{self, Linker, Lto, Strip},
+    toolchain::Toolchain,
+};
+
+pub mod errors;
+use errors::ProfileError;
+
+/// The profile used when no profile is specified
+pub const DEFAULT_PROFILE: &str = "dev";
+
+/// The profile used with `--release`
+pub const RELEASE_PROFILE: &str = "release";
+
+/// The folders of `target` used by the `dev` profile, which can't be the names of other
+/// profiles since their outputs go in `target/<profile>`
+const RESERVED_PROFILE_NAMES: &[&str] = &[
+    "executables",
+    "staticlibs",
+    "dynlibs",
+    "objects",
+    "generated",
+    "deps",
+    "package",
+    "probe",
+    "tests",
+    "examples",
+    "benches",
+    "bench",
+    "fuzz",
+    "pgo",
+    "coverage",
+    "pgo-generate",
+    "pgo-use",
+    "size",
+];
+
+/// A runtime error detector enabled with `--sanitize`
+#[derive(clap::ValueEnum, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Sanitizer {
+    /// Out-of-bounds accesses, use-after-free and leaks (ASan)
+    Address,
+    /// Undefined behavior like signed overflows or misaligned pointers (UBSan)
+    Undefined,
+    /// Data races (TSan)
+    Thread,
+    /// Memory leaks only (LSan)
+    Leak,
+    /// The coverage instrumentation of libFuzzer, enabled by `cpm fuzz` with the Clang backend
+    #[value(skip)]
+    Fuzzer,
+}
+
+impl Sanitizer {
+    /// The name of the sanitizer for `-fsanitize=`
+    pub fn name(self) -> &'static str {
+        match self {
+            Sanitizer::Address => "address",
+            Sanitizer::Undefined => "undefined",
+            Sanitizer::Thread => "thread",
+            Sanitizer::Leak => "leak",
+            Sanitizer::Fuzzer => "fuzzer",
+        }
+    }
+}
+
+/// A phase of profile-guided optimization, run by `cpm pgo`
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Pgo {
+    /// Instrument the outputs to record their execution profile next to their objects
+    Generate,
+    /// Optimize the outputs with the execution profiles found next to their objects
+    Use,
+}
+
+/// Pairs of sanitizers that can't be used in the same build
+const INCOMPATIBLE_SANITIZERS: &[(Sanitizer, Sanitizer)] = &[
+    (Sanitizer::Address, Sanitizer::Thread),
+    (Sanitizer::Leak, Sanitizer::Thread),
+];
+
+/// The settings used to compile and link the packages, resolved from
+/// the built-in profiles and the `[profile.<name>]` sections of the workspace manifest
+#[derive(Clone, Debug)]
+pub struct BuildProfile {
+    pub name: String,
+    /// Optimization level, passed as `-O<opt_level>`
+    pub opt_level: String,
+    /// Generate debug information
+    pub debug: bool,
+    /// Additional flags passed to the compiler
+    pub compiler_flags: Vec<String>,
+    /// Additional flags passed to the linker
+    pub linker_flags: Vec<String>,
+    /// Link-time optimization
+    pub lto: Lto,
+    /// The linker used by the compiler driver, its default linker when unset
+    pub linker: Option<Linker>,
+    /// What is stripped from executables and dynamic libraries
+    pub strip: Strip,
+    /// Write the debug information to `.dwo` files next to the objects
+    pub split_debuginfo: bool,
+    /// Sanitizers enabled with `--sanitize`, sorted
+    pub sanitizers: Vec<Sanitizer>,
+    /// Instrument the outputs to record the executed lines and branches, enabled with `--coverage`
+    pub coverage: bool,
+    /// The phase of profile-guided optimization, set by `cpm pgo`
+    pub pgo: Option<Pgo>,
+}
+
+impl BuildProfile {
+    /// Resolves the profile `name` from the profiles of the workspace manifest
+    ///
+    /// `dev` and `release` are built-in, their settings can be overridden in the manifest.
+    /// Custom profiles take their unset settings from the profile they `inherits` from,
+    /// their names can't be the names of the folders of `target` (e.g. `deps`)
+    pub fn resolve(
+        name: &str,
+        profiles: &BTreeMap<String, manifest::Profile>,
+    ) -> Result<Self, ProfileError> {
+        Self::resolve_inner(name, profiles, &mut vec![])
+    }
+
+    fn resolve_inner(
+        name: &str,
+        profiles: &BTreeMap<String, manifest::Profile>,
+        visited: &mut Vec<String>,
+    ) -> Result<Self, ProfileError> {
+        if visited.iter().any(|v| v == name) {
+            visited.push(name.to_string());
+            return Err(ProfileError::InheritanceCycle(visited.join(" -> ")));
+        }
+        visited.push(name.to_string());
+        if RESERVED_PROFILE_NAMES.contains(&name) || name.starts_with("sanitize-") {
+            return Err(ProfileError::ReservedName(name.to_string()));
+        }
+        let settings = profiles.get(name);
+        let mut profile = match (name, settings.and_then(|s| s.inherits.as_deref())) {
+            (DEFAULT_PROFILE, _) => Self::dev(),
+            (RELEASE_PROFILE, _) => Self::release(),
+            (_, Some(parent)) => Self::resolve_inner(parent, profiles, visited)?,
+            (_, None) if settings.is_some() => {
+                return Err(ProfileError::MissingInherits(name.to_string()))
+            }
+            (_, None) => return Err(ProfileError::NotFound(name.to_string())),
+        };
+        profile.name = name.to_string();
+        if let Some(settings) = settings {
+            profile.apply(settings);
+        }
+        Ok(profile)
+    }
+
+    fn dev() -> Self {
+        Self {
+            name: DEFAULT_PROFILE.to_string(),
+            opt_level: "0".to_string(),
+            debug: true,
+            compiler_flags: vec![],
+            linker_flags: vec![],
+            lto: Lto::Off,
+            linker: None,
+            strip: Strip::None,
+            split_debuginfo: false,
+            sanitizers: vec![],
+            coverage: false,
+            pgo: None,
+        }
+    }
+
+    fn release() -> Self {
+        Self {
+            name: RELEASE_PROFILE.to_string(),
+            opt_level: "3".to_string(),
+            debug: false,
+            compiler_flags: vec![],
+            linker_flags: vec![],
+            lto: Lto::Off,
+            linker: None,
+            strip: Strip::None,
+            split_debuginfo: false,
+            sanitizers: vec![],
+            coverage: false,
+            pgo: None,
+        }
+    }
+
+    fn apply(&mut self, settings: &manifest::Profile) {
+        if let Some(opt_level) = &settings.opt_level {
+            self.opt_level = opt_level.clone();
+        }
+        if let Some(debug) = settings.debug {
+            self.debug = debug;
+        }
+        if let Some(lto) = settings.lto {
+            self.lto = lto;
+        }
+        if let Some(linker) = settings.linker {
+            self.linker = Some(linker);
+        }
+        if let Some(strip) = settings.strip {
+            self.strip = strip;
+        }
+        if let Some(split_debuginfo) = settings.split_debuginfo {
+            self.split_debuginfo = split_debuginfo;
+        }
+        self.compiler_flags
+            .extend(settings.compiler_flags.iter().cloned());
+        self.linker_flags
+            .extend(settings.linker_flags.iter().cloned());
+    }
+
+    /// Enables sanitizers for this profile
+    ///
+    /// Fails when two of them can't be used together (e.g. `address` and `thread`)
+    pub fn set_sanitizers(&mut self, mut sanitizers: Vec<Sanitizer>) -> Result<(), ProfileError> {
+        sanitizers.sort();
+        sanitizers.dedup();
+        if let Some((first, second)) = INCOMPATIBLE_SANITIZERS
+            .iter()
+            .find(|(first, second)| sanitizers.contains(first) && sanitizers.contains(second))
+        {
+            return Err(ProfileError::IncompatibleSanitizers(
+                first.name(),
+                second.name(),
+            ));
+        }
+        self.sanitizers = sanitizers;
+        Ok(())
+    }
+
+    /// The folder, relative to the `target` folder, where the outputs of this profile go
+    ///
+    /// The `dev` profile uses the `target` folder itself. Sanitized outputs are placed
+    /// in a `sanitize-<sanitizers>` subfolder (e.g. `release/sanitize-address-undefined`)
+    /// and instrumented outputs in a `coverage` subfolder. The instrumented and optimized
+    /// outputs of profile-guided optimization are in `pgo-generate` and `pgo-use`
+    pub fn output_subfolder(&self) -> PathBuf {
+        let mut subfolder = PathBuf::new();
+        if self.name != DEFAULT_PROFILE {
+            subfolder.push(&self.name);
+        }
+        if !self.sanitizers.is_empty() {
+            let names: Vec<&str> = self.sanitizers.iter().map(|s| s.name()).collect();
+            subfolder.push(format!("sanitize-{}", names.join("-")));
+        }
+        if self.coverage {
+            subfolder.push("coverage");
+        }
+        match self.pgo {
+            Some(Pgo::Generate) => subfolder.push("pgo-generate"),
+            Some(Pgo::Use) => subfolder.push("pgo-use"),
+            None => {}
+        }
+        subfolder
+    }
+
+    /// The folder where the outputs of this profile are placed for a workspace
+    /// and the target of `toolchain` (e.g. `target/aarch64-linux-gnu/release`)
+    pub fn target_folder_path(&self, workspace_path: &Path, toolchain: &Toolchain) -> PathBuf {
+        workspace_path
+            .join("target")
+            .join(toolchain.output_subfolder())
+            .join(self.output_subfolder())
+    }
+
+    /// Whether the toolchain must be probed for the link-time settings of this profile
+    /// (`lto`, `linker`, `strip` and `split-debuginfo`) before building
+    pub fn has_link_settings(&self) -> bool {
+        self.lto != Lto::Off
+            || self.linker.is_some()
+            || self.strip != Strip::None
+            || (self.debug && self.split_debuginfo)
+    }
+
+    /// The link-time settings of this profile that differ from the defaults, as they're written
+    /// in the manifest (e.g. `lto = "thin", linker = "mold"`)
+    pub fn link_settings_description(&self) -> String {
+        let mut settings = vec![];
+        match self.lto {
+            Lto::Off => {}
+            Lto::Thin => settings.push(r#"lto = "thin""#.to_string()),
+            Lto::Fat => settings.push(r#"lto = "fat""#.to_string()),
+        }
+        if let Some(linker) = self.linker {
+            settings.push(format!(r#"linker = "{}""#, linker.name()));
+        }
+        match self.strip {
+            Strip::None => {}
+            Strip::Debuginfo => settings.push(r#"strip = "debuginfo""#.to_string()),
+            Strip::Symbols => settings.push(r#"strip = "symbols""#.to_string()),
+        }
+        if self.debug && self.split_debuginfo {
+            settings.push("split-debuginfo = true".to_string());
+        }
+        settings.join(", ")
+    }
+
+    /// The `-fsanitize=` flag of the sanitizers, passed to both the compiler and the linker
+    ///
+    /// The fuzzer instrumentation doesn't link libFuzzer (`fuzzer-no-link`), only the fuzz targets
+    /// are linked with `-fsanitize=fuzzer`
+    pub fn sanitizer_flags(&self) -> Vec<String> {
+        if self.sanitizers.is_empty() {
+            return vec![];
+        }
+        let names: Vec<&str> = self
+            .sanitizers
+            .iter()
+            .map(|s| match s {
+                Sanitizer::Fuzzer => "fuzzer-no-link",
+                _ => s.name(),
+            })
+            .collect();
+        vec![format!("-fsanitize={}", names.join(","))]
+    }
+
+    /// The flags of the sanitizers passed to the compiler
+    pub fn sanitizer_compiler_flags(&self) -> Vec<String> {
+        let mut flags = self.sanitizer_flags();
+        if !flags.is_empty() {
+            // stops at the first error, so the failures of tests are reported
+            flags
+                .extend(["-fno-omit-frame-pointer", "-fno-sanitize-recover=all"].map(String::from));
+        }
+        flags
+    }
+
+    /// The flags passed to the compiler for the settings of this profile
+    pub fn flags(&self) -> Vec<String> {
+        let mut flags = vec![format!("-O{}", self.opt_level)];
+        if self.debug {
+            flags.push("-g".to_string());
+            if self.split_debuginfo {
+                flags.push("-gsplit-dwarf".to_string());
+            }
+        }
+        flags.extend(self.compiler_flags.iter().cloned());
+        flags
+    }
+}
+
+impl Default for BuildProfile {
+    fn default() -> Self {
+        Self::dev()
+    }
+}
@@ -0,0 +1,15 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ProfileError {
+    #[error("profile `{0}` is not defined")]
+    NotFound(String),
+    #[error("profile `{0}` must specify the profile it `inherits` from")]
+    MissingInherits(String),
+    #[error("profile name `{0}` is reserved, it's the name of a folder of `target`")]
+    ReservedName(String),
+    #[error("profile inheritance cycle: {0}")]
+    InheritanceCycle(String),
+    #[error("the `{0}` and `{1}` sanitizers can't be used together")]
+    IncompatibleSanitizers(&'static str, &'static str),
+}
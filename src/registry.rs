@@ -0,0 +1,375 @@
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    filenames::cpm_home_path,
+    manifest::{Dependency, Manifest},
+    target::{cfg::target_section_matches, errors::TargetError, TargetTriple},
+};
+
+pub mod errors;
+use errors::RegistryError;
+
+/// A package registry stored in a folder
+///
+/// The registry has the following layout:
+/// - `index/<name>.toml`: the versions of the package `<name>`, see [`RegistryIndex`]
+/// - `packages/<name>-<version>.tar.gz`: the source tarball of each version
+pub struct Registry {
+    path: PathBuf,
+}
+
+/// The index of a package in the registry
+#[derive(Deserialize, Serialize, Default)]
+pub struct RegistryIndex {
+    #[serde(default)]
+    pub versions: Vec<IndexEntry>,
+}
+
+/// A published version of a package
+#[derive(Deserialize, Serialize, Clone)]
+pub struct IndexEntry {
+    pub version: semver::Version,
+    /// SHA-256 of the tarball of this version
+    pub checksum: String,
+    /// Registry dependencies of this version
+    #[serde(default)]
+    pub dependencies: BTreeMap<String, semver::VersionReq>,
+    /// Registry build-dependencies of this version
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub build_dependencies: BTreeMap<String, semver::VersionReq>,
+    /// Registry dependencies of the `[target.<key>]` sections of this version, by key
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub target: BTreeMap<String, IndexTargetEntry>,
+    /// Yanked versions are only used when they're pinned in the lockfile
+    #[serde(default)]
+    pub yanked: bool,
+}
+
+/// The registry dependencies of a `[target.<key>]` section of a published version
+#[derive(Deserialize, Serialize, Clone, Default)]
+pub struct IndexTargetEntry {
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub dependencies: BTreeMap<String, semver::VersionReq>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub build_dependencies: BTreeMap<String, semver::VersionReq>,
+}
+
+impl IndexEntry {
+    /// Creates the index entry of a version from its normalized manifest
+    pub fn new(version: semver::Version, checksum: String, manifest: &Manifest) -> Self {
+        Self {
+            version,
+            checksum,
+            dependencies: version_requirements(&manifest.dependencies),
+            build_dependencies: version_requirements(&manifest.build_dependencies),
+            target: manifest
+                .target
+                .iter()
+                .map(|(key, settings)| {
+                    let entry = IndexTargetEntry {
+                        dependencies: version_requirements(&settings.dependencies),
+                        build_dependencies: version_requirements(&settings.build_dependencies),
+                    };
+                    (key.clone(), entry)
+                })
+                .filter(|(_, entry)| {
+                    !entry.dependencies.is_empty() || !entry.build_dependencies.is_empty()
+                })
+                .collect(),
+            yanked: false,
+        }
+    }
+
+    /// The dependencies and build-dependencies of this version when it's built for `target`
+    ///
+    /// The dependencies of the matching `[target.<key>]` sections are included
+    pub fn for_target(&self, target: &TargetTriple) -> Result<IndexTargetEntry, TargetError> {
+        let mut dependencies = IndexTargetEntry {
+            dependencies: self.dependencies.clone(),
+            build_dependencies: self.build_dependencies.clone(),
+        };
+        for (key, entry) in &self.target {
+            if target_section_matches(key, target)? {
+                dependencies.dependencies.extend(entry.dependencies.clone());
+                dependencies
+                    .build_dependencies
+                    .extend(entry.build_dependencies.clone());
+            }
+        }
+        Ok(dependencies)
+    }
+}
+
+/// The version requirements of a dependency table of a normalized manifest
+fn version_requirements(
+    dependencies: &BTreeMap<String, Dependency>,
+) -> BTreeMap<String, semver::VersionReq> {
+    dependencies
+        .iter()
+        .map(|(name, dependency)| (name.clone(), dependency.version_requirement()))
+        .collect()
+}
+
+impl Registry {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    /// Opens the registry configured for a workspace
+    ///
+    /// The registry path is, in order of priority, `$CPM_REGISTRY`, the `[registry] path`
+    /// of the workspace manifest (relative to the workspace) or `~/.cpm/registry`
+    pub fn for_workspace(workspace_path: &Path, workspace_manifest: &Manifest) -> Self {
+        if let Some(path) = std::env::var_os("CPM_REGISTRY") {
+            return Self::new(path);
+        }
+        if let Some(path) = workspace_manifest
+            .registry
+            .as_ref()
+            .and_then(|r| r.path.as_ref())
+        {
+            return Self::new(workspace_path.join(path));
+        }
+        Self::new(cpm_home_path().join("registry"))
+    }
+
+    pub fn index_path(&self, name: &str) -> PathBuf {
+        self.path.join("index").join(format!("{name}.toml"))
+    }
+
+    pub fn tarball_path(&self, name: &str, version: &semver::Version) -> PathBuf {
+        self.path
+            .join("packages")
+            .join(format!("{name}-{version}.tar.gz"))
+    }
+
+    /// Reads the index of a package, returns an empty index if the package was never published
+    pub fn index(&self, name: &str) -> Result<RegistryIndex, RegistryError> {
+        let index_path = self.index_path(name);
+        let content = match std::fs::read_to_string(&index_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(RegistryIndex::default()),
+            Err(e) => return Err(e.into()),
+        };
+        toml::from_str(&content).map_err(|error| RegistryError::InvalidIndex { index_path, error })
+    }
+
+    /// Adds a new version of a package to the registry
+    ///
+    /// The tarball is copied into the registry and the version is added to the index.
+    /// Versions that were already published can't be replaced.
+    pub fn publish(
+        &self,
+        name: &str,
+        version: &semver::Version,
+        tarball_path: &Path,
+        manifest: &Manifest,
+    ) -> Result<IndexEntry, RegistryError> {
+        let mut index = self.index(name)?;
+        if index.versions.iter().any(|entry| &entry.version == version) {
+            return Err(RegistryError::VersionAlreadyExists {
+                name: name.to_string(),
+                version: version.clone(),
+            });
+        }
+        let entry = IndexEntry::new(version.clone(), file_checksum(tarball_path)?, manifest);
+        let registry_tarball_path = self.tarball_path(name, version);
+        std::fs::create_dir_all(self.path.join("packages"))?;
+        std::fs::copy(tarball_path, &registry_tarball_path)?;
+        index.versions.push(entry.clone());
+        let index_path = self.index_path(name);
+        std::fs::create_dir_all(self.path.join("index"))?;
+        // the index is replaced at once, so readers never see a partially written index
+        let temporary_index_path = index_path.with_extension("toml.tmp");
+        std::fs::write(&temporary_index_path, toml::to_string(&index)?)?;
+        std::fs::rename(temporary_index_path, index_path)?;
+        Ok(entry)
+    }
+
+    /// Extracts the tarball of a package version into the source cache and returns
+    /// the folder of the package
+    ///
+    /// The tarball checksum is verified against `checksum` before extracting it.
+    /// Already extracted packages are reused.
+    pub fn unpack(
+        &self,
+        name: &str,
+        version: &semver::Version,
+        checksum: &str,
+    ) -> Result<PathBuf, RegistryError> {
+        let package_folder = registry_source_cache_path().join(format!("{name}-{version}"));
+        if package_folder.join(".cpm-checksum").exists()
+            && std::fs::read_to_string(package_folder.join(".cpm-checksum"))? == checksum
+        {
+            return Ok(package_folder);
+        }
+        let tarball_path = self.tarball_path(name, version);
+        let found_checksum = file_checksum(&tarball_path)?;
+        if found_checksum != checksum {
+            return Err(RegistryError::ChecksumMismatch {
+                name: name.to_string(),
+                version: version.clone(),
+                expected: checksum.to_string(),
+                found: found_checksum,
+            });
+        }
+        if package_folder.exists() {
+            std::fs::remove_dir_all(&package_folder)?;
+        }
+        let cache_folder = registry_source_cache_path();
+        std::fs::create_dir_all(&cache_folder)?;
+        let tarball = std::fs::File::open(&tarball_path)?;
+        tar::Archive::new(flate2::read::GzDecoder::new(tarball)).unpack(&cache_folder)?;
+        if !package_folder.exists() {
+            return Err(RegistryError::InvalidTarball { tarball_path });
+        }
+        std::fs::write(package_folder.join(".cpm-checksum"), checksum)?;
+        Ok(package_folder)
+    }
+}
+
+/// The folder where the tarballs of the registry packages are extracted
+pub fn registry_source_cache_path() -> PathBuf {
+    cpm_home_path().join("cache").join("registry")
+}
+
+/// Computes the SHA-256 of a file as a hex string
+pub fn file_checksum(path: &Path) -> std::io::Result<String> {
+    let content = std::fs::read(path)?;
+    Ok(Sha256::digest(content)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A registry in a new temporary folder, removed when dropped
+    struct TemporaryRegistry {
+        folder: PathBuf,
+        registry: Registry,
+    }
+
+    impl TemporaryRegistry {
+        fn new(name: &str) -> Self {
+            let folder =
+                std::env::temp_dir().join(format!("cpm-registry-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&folder);
+            std::fs::create_dir_all(&folder).unwrap();
+            Self {
+                registry: Registry::new(folder.join("registry")),
+                folder,
+            }
+        }
+
+        /// Publishes a version whose tarball contains `content`
+        fn publish(
+            &self,
+            name: &str,
+            version: &str,
+            content: &str,
+        ) -> Result<IndexEntry, RegistryError> {
+            let tarball_path = self.folder.join(format!("{name}-{version}.tar.gz"));
+            std::fs::write(&tarball_path, content).unwrap();
+            let manifest: Manifest = toml::from_str(&format!(
+                "[package]\nname = \"{name}\"\nversion = \"{version}\"\n\n\
+                 [dependencies]\nzlib = \"1.2\"\n"
+            ))
+            .unwrap();
+            self.registry.publish(
+                name,
+                &semver::Version::parse(version).unwrap(),
+                &tarball_path,
+                &manifest,
+            )
+        }
+    }
+
+    impl Drop for TemporaryRegistry {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.folder);
+        }
+    }
+
+    #[test]
+    fn published_versions_are_added_to_the_index() {
+        let registry = TemporaryRegistry::new("publish");
+        let entry = registry.publish("json", "1.0.0", "first").unwrap();
+        let tarball_path = registry
+            .registry
+            .tarball_path("json", &semver::Version::new(1, 0, 0));
+        assert_eq!(entry.checksum, file_checksum(&tarball_path).unwrap());
+        assert_eq!(
+            entry.dependencies.get("zlib"),
+            Some(&semver::VersionReq::parse("1.2").unwrap())
+        );
+        registry.publish("json", "1.1.0", "second").unwrap();
+        let versions: Vec<String> = registry
+            .registry
+            .index("json")
+            .unwrap()
+            .versions
+            .iter()
+            .map(|entry| entry.version.to_string())
+            .collect();
+        assert_eq!(versions, ["1.0.0", "1.1.0"]);
+    }
+
+    #[test]
+    fn published_versions_cannot_be_replaced() {
+        let registry = TemporaryRegistry::new("duplicate");
+        let entry = registry.publish("json", "1.0.0", "first").unwrap();
+        assert!(matches!(
+            registry.publish("json", "1.0.0", "replaced"),
+            Err(RegistryError::VersionAlreadyExists { name, .. }) if name == "json"
+        ));
+        let index = registry.registry.index("json").unwrap();
+        assert_eq!(index.versions.len(), 1);
+        assert_eq!(index.versions[0].checksum, entry.checksum);
+        let tarball_path = registry
+            .registry
+            .tarball_path("json", &semver::Version::new(1, 0, 0));
+        assert_eq!(std::fs::read_to_string(tarball_path).unwrap(), "first");
+    }
+
+    #[test]
+    fn tarballs_that_do_not_match_their_checksum_are_rejected() {
+        let registry = TemporaryRegistry::new("checksum");
+        // the name is unique, so no extracted copy of the package is in the source cache
+        let name = format!("tampered-{}", std::process::id());
+        let entry = registry.publish(&name, "1.0.0", "published").unwrap();
+        let tarball_path = registry.registry.tarball_path(&name, &entry.version);
+        std::fs::write(&tarball_path, "tampered").unwrap();
+        let result = registry
+            .registry
+            .unpack(&name, &entry.version, &entry.checksum);
+        assert!(matches!(
+            result,
+            Err(RegistryError::ChecksumMismatch { expected, found, .. })
+                if expected == entry.checksum && found == file_checksum(&tarball_path).unwrap()
+        ));
+        assert!(!registry_source_cache_path()
+            .join(format!("{name}-1.0.0"))
+            .exists());
+    }
+
+    #[test]
+    fn file_checksum_is_the_hex_sha256() {
+        let registry = TemporaryRegistry::new("sha256");
+        let path = registry.folder.join("abc");
+        std::fs::write(&path, "abc").unwrap();
+        assert_eq!(
+            file_checksum(&path).unwrap(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum RegistryError {
+    #[error("invalid registry index {index_path:?}: {error}")]
+    InvalidIndex {
+        index_path: PathBuf,
+        error: toml::de::Error,
+    },
+    #[error("checksum mismatch for `{name}` {version}: expected {expected}, found {found}")]
+    ChecksumMismatch {
+        name: String,
+        version: semver::Version,
+        expected: String,
+        found: String,
+    },
+    #[error("tarball {tarball_path:?} does not contain a `<name>-<version>` folder")]
+    InvalidTarball { tarball_path: PathBuf },
+    #[error("version {version} of `{name}` is already published")]
+    VersionAlreadyExists {
+        name: String,
+        version: semver::Version,
+    },
+    #[error("failed to serialize the registry index: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Io error: {0}")]
+    IOError(#[from] std::io::Error),
+}
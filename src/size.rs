@@ -0,0 +1,225 @@
+use std::{io::ErrorKind, path::Path, process::Command};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{manifest::SizeBudget, toolchain::Toolchain};
+
+pub mod errors;
+use errors::SizeError;
+
+/// The sizes of the sections of a binary, in bytes, as reported by `size`
+#[derive(Deserialize, Serialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct SectionSizes {
+    /// Code and read-only data
+    pub text: u64,
+    /// Initialized data
+    pub data: u64,
+    /// Zero-initialized data
+    pub bss: u64,
+}
+
+impl SectionSizes {
+    /// The sections with their names, in the order they're reported
+    pub fn sections(&self) -> [(&'static str, u64); 3] {
+        [("text", self.text), ("data", self.data), ("bss", self.bss)]
+    }
+
+    /// The first section that exceeds its limit in `budget`, with its size and limit
+    pub fn exceeded_budget(&self, budget: &SizeBudget) -> Option<(&'static str, u64, u64)> {
+        let limits = [budget.text, budget.data, budget.bss];
+        self.sections()
+            .into_iter()
+            .zip(limits)
+            .find_map(|((section, size), limit)| {
+                limit
+                    .filter(|limit| size > *limit)
+                    .map(|limit| (section, size, limit))
+            })
+    }
+}
+
+/// A symbol of a binary and its size, as reported by `nm`
+pub struct SymbolSize {
+    pub name: String,
+    pub size: u64,
+    /// The `nm` symbol type (e.g. `T` for code or `B` for zero-initialized data)
+    pub kind: char,
+}
+
+/// The section sizes of the last two measured builds of an output, saved in `target/size`
+#[derive(Deserialize, Serialize)]
+pub struct SizeHistory {
+    pub current: SectionSizes,
+    pub previous: Option<SectionSizes>,
+}
+
+impl SizeHistory {
+    /// Loads the size history saved at `path`, if it was recorded
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = std::fs::read_to_string(path).ok()?;
+        toml::from_str(&content).ok()
+    }
+
+    /// Records the sizes of a new build in the history saved at `path`,
+    /// the current sizes become the previous ones
+    pub fn record(path: &Path, sizes: SectionSizes) -> Result<Self, SizeError> {
+        let history = Self {
+            current: sizes,
+            previous: Self::load(path).map(|history| history.current),
+        };
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(path, toml::to_string(&history)?)?;
+        Ok(history)
+    }
+}
+
+/// Measures the sections of a binary with the `size` of the toolchain
+///
+/// The sizes of the members of static libraries are added up
+pub fn section_sizes(toolchain: &Toolchain, path: &Path) -> Result<SectionSizes, SizeError> {
+    let output = run(Command::new(toolchain.binutils_program("size"))
+        .arg("-B")
+        .arg(path))?;
+    let mut sizes = SectionSizes::default();
+    // skips the header (`text data bss dec hex filename`)
+    for line in output.lines().skip(1) {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        let [text, data, bss, ..] = columns.as_slice() else {
+            continue;
+        };
+        let parse = |value: &str| {
+            value
+                .parse::<u64>()
+                .map_err(|_| SizeError::InvalidOutput(line.to_string()))
+        };
+        sizes.text += parse(text)?;
+        sizes.data += parse(data)?;
+        sizes.bss += parse(bss)?;
+    }
+    Ok(sizes)
+}
+
+/// The `count` largest symbols of a binary, measured with the `nm` of the toolchain
+pub fn largest_symbols(
+    toolchain: &Toolchain,
+    path: &Path,
+    count: usize,
+) -> Result<Vec<SymbolSize>, SizeError> {
+    let output = run(Command::new(toolchain.binutils_program("nm"))
+        .args(["--print-size", "--size-sort"])
+        .arg(path))?;
+    let mut symbols: Vec<SymbolSize> = output
+        .lines()
+        .filter_map(|line| {
+            let columns: Vec<&str> = line.split_whitespace().collect();
+            let [_, size, kind, name] = columns.as_slice() else {
+                return None;
+            };
+            Some(SymbolSize {
+                name: name.to_string(),
+                size: u64::from_str_radix(size, 16).ok()?,
+                kind: kind.chars().next()?,
+            })
+        })
+        .collect();
+    symbols.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+    symbols.truncate(count);
+    Ok(symbols)
+}
+
+/// Keeps the `count` largest symbols of several files and groups them by file
+///
+/// The files are sorted by their largest symbol, and the files without any of these symbols
+/// are left out
+pub fn group_largest_symbols(
+    file_symbols: Vec<(&str, Vec<SymbolSize>)>,
+    count: usize,
+) -> Vec<(&str, Vec<SymbolSize>)> {
+    let mut symbols: Vec<(&str, SymbolSize)> = file_symbols
+        .into_iter()
+        .flat_map(|(file, symbols)| symbols.into_iter().map(move |symbol| (file, symbol)))
+        .collect();
+    symbols.sort_by(|(a_file, a), (b_file, b)| {
+        b.size
+            .cmp(&a.size)
+            .then_with(|| a.name.cmp(&b.name))
+            .then_with(|| a_file.cmp(b_file))
+    });
+    symbols.truncate(count);
+    let mut groups: Vec<(&str, Vec<SymbolSize>)> = vec![];
+    for (file, symbol) in symbols {
+        match groups.iter_mut().find(|(name, _)| *name == file) {
+            Some((_, group)) => group.push(symbol),
+            None => groups.push((file, vec![symbol])),
+        }
+    }
+    groups
+}
+
+fn run(command: &mut Command) -> Result<String, SizeError> {
+    let program = command.get_program().to_string_lossy().to_string();
+    let output = command.output().map_err(|error| match error.kind() {
+        ErrorKind::NotFound => SizeError::ProgramNotInstalled(program.clone()),
+        _ => SizeError::IOError(error),
+    })?;
+    if !output.status.success() {
+        return Err(SizeError::CommandFailed {
+            program,
+            stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        });
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(name: &str, size: u64) -> SymbolSize {
+        SymbolSize {
+            name: name.to_string(),
+            size,
+            kind: 'T',
+        }
+    }
+
+    #[test]
+    fn largest_symbols_are_grouped_by_file() {
+        let file_symbols = vec![
+            ("main.o", vec![symbol("main", 40), symbol("usage", 10)]),
+            ("parse.o", vec![symbol("parse", 100), symbol("lex", 30)]),
+            ("util.o", vec![symbol("trim", 5)]),
+        ];
+        let groups = group_largest_symbols(file_symbols, 3);
+        let names: Vec<(&str, Vec<&str>)> = groups
+            .iter()
+            .map(|(file, symbols)| (*file, symbols.iter().map(|s| s.name.as_str()).collect()))
+            .collect();
+        assert_eq!(
+            names,
+            [("parse.o", vec!["parse", "lex"]), ("main.o", vec!["main"])]
+        );
+    }
+
+    #[test]
+    fn size_history_keeps_the_previous_sizes() {
+        let path = std::env::temp_dir()
+            .join(format!("cpm-size-{}", std::process::id()))
+            .join("size")
+            .join("app-0.1.0.toml");
+        let sizes = |text| SectionSizes {
+            text,
+            data: 8,
+            bss: 16,
+        };
+        let first = SizeHistory::record(&path, sizes(100)).unwrap();
+        assert_eq!(first.previous, None);
+        SizeHistory::record(&path, sizes(120)).unwrap();
+        let history = SizeHistory::load(&path).unwrap();
+        let _ = std::fs::remove_dir_all(path.parent().unwrap().parent().unwrap());
+        assert_eq!(history.current, sizes(120));
+        assert_eq!(history.previous, Some(sizes(100)));
+    }
+}
@@ -0,0 +1,15 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SizeError {
+    #[error("`{0}` is not installed")]
+    ProgramNotInstalled(String),
+    #[error("`{program}` failed: {stderr}")]
+    CommandFailed { program: String, stderr: String },
+    #[error("unexpected output line `{0}`")]
+    InvalidOutput(String),
+    #[error("failed to serialize the size history: {0}")]
+    Serialize(#[from] toml::ser::Error),
+    #[error("Io error: {0}")]
+    IOError(#[from] std::io::Error),
+}
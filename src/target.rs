@@ -0,0 +1,237 @@
+use std::fmt::Display;
+
+pub mod cfg;
+pub mod errors;
+use errors::TargetError;
+
+/// Operating systems that can be written without a vendor in a triple (e.g. `aarch64-linux-gnu`)
+const KNOWN_OPERATING_SYSTEMS: &[&str] = &[
+    "linux", "windows", "darwin", "macos", "freebsd", "netbsd", "openbsd", "android", "none",
+];
+
+/// Operating systems of the `unix` family
+const UNIX_OPERATING_SYSTEMS: &[&str] = &[
+    "linux", "macos", "ios", "freebsd", "netbsd", "openbsd", "android", "solaris", "illumos",
+];
+
+/// A target triple (e.g. `x86_64-unknown-linux-gnu`, `aarch64-linux-gnu` or `arm-none-eabi`)
+/// and the properties that `cfg(...)` expressions are evaluated against
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct TargetTriple {
+    /// The triple as written by the user
+    pub triple: String,
+    /// Normalized architecture (e.g. `x86_64`, `aarch64`, `arm` or `x86`)
+    pub arch: String,
+    pub vendor: String,
+    /// Normalized operating system (e.g. `linux`, `macos`, `windows` or `none` for bare-metal)
+    pub os: String,
+    /// C library environment (e.g. `gnu`, `musl` or `msvc`), empty when there's none
+    pub env: String,
+    /// ABI suffix of the environment (e.g. `eabihf`), empty when there's none
+    pub abi: String,
+}
+
+impl TargetTriple {
+    /// Parses a target triple, the vendor can be omitted
+    pub fn parse(triple: &str) -> Result<Self, TargetError> {
+        let parts: Vec<&str> = triple.split('-').collect();
+        if parts.len() < 2 || parts.iter().any(|part| part.is_empty()) {
+            return Err(TargetError::InvalidTriple(triple.to_string()));
+        }
+        let (vendor, os, environment) = match &parts[1..] {
+            [os] => ("unknown", *os, String::new()),
+            [os, environment] if KNOWN_OPERATING_SYSTEMS.contains(os) => {
+                ("unknown", *os, environment.to_string())
+            }
+            [vendor, os] => (*vendor, *os, String::new()),
+            [vendor, os, environment @ ..] => (*vendor, *os, environment.join("-")),
+            [] => unreachable!("triples have at least two parts"),
+        };
+        let (env, abi) = split_environment(&environment);
+        Ok(Self {
+            triple: triple.to_string(),
+            arch: normalize_arch(parts[0]).to_string(),
+            vendor: vendor.to_string(),
+            os: match os {
+                "darwin" => "macos".to_string(),
+                "mingw32" => "windows".to_string(),
+                "elf" => "none".to_string(),
+                os => os.to_string(),
+            },
+            env: match (os, env) {
+                ("mingw32", "") => "gnu".to_string(),
+                (_, env) => env.to_string(),
+            },
+            abi: abi.to_string(),
+        })
+    }
+
+    /// The target cpm itself was built for, which is the target of the host compiler
+    pub fn host() -> Self {
+        let arch = std::env::consts::ARCH;
+        let triple = match std::env::consts::OS {
+            "linux" if cfg!(target_env = "musl") => format!("{arch}-unknown-linux-musl"),
+            "linux" => format!("{arch}-unknown-linux-gnu"),
+            "macos" => format!("{arch}-apple-darwin"),
+            "windows" if cfg!(target_env = "gnu") => format!("{arch}-pc-windows-gnu"),
+            "windows" => format!("{arch}-pc-windows-msvc"),
+            os => format!("{arch}-unknown-{os}"),
+        };
+        Self::parse(&triple).expect("the host triple must be valid")
+    }
+
+    /// Whether two triples name the same target, even when one of them omits the vendor
+    /// (e.g. `aarch64-linux-gnu` and `aarch64-unknown-linux-gnu`)
+    pub fn is_same_target(&self, other: &TargetTriple) -> bool {
+        self.triple.split('-').next() == other.triple.split('-').next()
+            && self.vendor == other.vendor
+            && self.os == other.os
+            && self.env == other.env
+            && self.abi == other.abi
+    }
+
+    /// The family of the operating system (`unix` or `windows`), if any
+    pub fn family(&self) -> Option<&'static str> {
+        if self.os == "windows" {
+            Some("windows")
+        } else if UNIX_OPERATING_SYSTEMS.contains(&self.os.as_str()) {
+            Some("unix")
+        } else {
+            None
+        }
+    }
+
+    /// The extension of the executables of this target, without the dot
+    pub fn executable_extension(&self) -> &'static str {
+        match self.os.as_str() {
+            "windows" => "exe",
+            "none" => "elf",
+            _ => "",
+        }
+    }
+
+    /// The prefix of the file names of libraries on this target (e.g. `lib` in `libz.a`),
+    /// so they can be linked with `-l<name>`
+    pub fn library_prefix(&self) -> &'static str {
+        match self.env.as_str() {
+            "msvc" => "",
+            _ => "lib",
+        }
+    }
+
+    /// The extension of the static libraries of this target, without the dot
+    pub fn static_library_extension(&self) -> &'static str {
+        match self.env.as_str() {
+            "msvc" => "lib",
+            _ => "a",
+        }
+    }
+
+    /// The extension of the dynamic libraries of this target, without the dot
+    pub fn dynamic_library_extension(&self) -> &'static str {
+        match self.os.as_str() {
+            "windows" => "dll",
+            "macos" | "ios" => "dylib",
+            _ => "so",
+        }
+    }
+}
+
+impl Display for TargetTriple {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.triple)
+    }
+}
+
+/// Normalizes the architecture of a triple like `target_arch` (e.g. `armv7` is `arm`)
+fn normalize_arch(arch: &str) -> &str {
+    match arch {
+        "i386" | "i486" | "i586" | "i686" => "x86",
+        "amd64" => "x86_64",
+        "arm64" => "aarch64",
+        arch if arch.starts_with("arm") || arch.starts_with("thumb") => "arm",
+        arch => arch,
+    }
+}
+
+/// Splits the environment of a triple into the C library and the ABI (e.g. `gnueabihf`)
+fn split_environment(environment: &str) -> (&str, &str) {
+    for env in ["gnu", "musl", "msvc"] {
+        if let Some(abi) = environment.strip_prefix(env) {
+            return (env, abi);
+        }
+    }
+    ("", environment)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triples_are_normalized() {
+        let target = TargetTriple::parse("armv7-unknown-linux-gnueabihf").unwrap();
+        assert_eq!(target.arch, "arm");
+        assert_eq!(target.vendor, "unknown");
+        assert_eq!(target.os, "linux");
+        assert_eq!(target.env, "gnu");
+        assert_eq!(target.abi, "eabihf");
+        let target = TargetTriple::parse("x86_64-apple-darwin").unwrap();
+        assert_eq!(target.os, "macos");
+        assert_eq!(target.family(), Some("unix"));
+        let target = TargetTriple::parse("i686-w64-mingw32").unwrap();
+        assert_eq!(target.arch, "x86");
+        assert_eq!(target.os, "windows");
+        assert_eq!(target.env, "gnu");
+    }
+
+    #[test]
+    fn vendor_can_be_omitted() {
+        let target = TargetTriple::parse("aarch64-linux-gnu").unwrap();
+        assert_eq!(target.vendor, "unknown");
+        assert_eq!(target.os, "linux");
+        assert_eq!(target.env, "gnu");
+        assert!(target.is_same_target(&TargetTriple::parse("aarch64-unknown-linux-gnu").unwrap()));
+        assert!(!target.is_same_target(&TargetTriple::parse("aarch64-linux-musl").unwrap()));
+        // the architecture isn't normalized, `armv6` and `armv7` are different targets
+        let armv7 = TargetTriple::parse("armv7-none-eabi").unwrap();
+        assert!(!armv7.is_same_target(&TargetTriple::parse("armv6-none-eabi").unwrap()));
+    }
+
+    #[test]
+    fn bare_metal_triples_have_no_family() {
+        let target = TargetTriple::parse("arm-none-eabi").unwrap();
+        assert_eq!(target.os, "none");
+        assert_eq!(target.env, "");
+        assert_eq!(target.abi, "eabi");
+        assert_eq!(target.family(), None);
+        assert_eq!(target.executable_extension(), "elf");
+    }
+
+    #[test]
+    fn invalid_triples_are_rejected() {
+        for triple in ["x86_64", "x86_64--linux", "-linux", ""] {
+            assert!(
+                matches!(
+                    TargetTriple::parse(triple),
+                    Err(TargetError::InvalidTriple(_))
+                ),
+                "{triple}"
+            );
+        }
+    }
+
+    #[test]
+    fn artifact_names_come_from_the_target() {
+        let windows = TargetTriple::parse("x86_64-pc-windows-msvc").unwrap();
+        assert_eq!(windows.executable_extension(), "exe");
+        assert_eq!(windows.library_prefix(), "");
+        assert_eq!(windows.static_library_extension(), "lib");
+        assert_eq!(windows.dynamic_library_extension(), "dll");
+        let linux = TargetTriple::parse("aarch64-linux-gnu").unwrap();
+        assert_eq!(linux.executable_extension(), "");
+        assert_eq!(linux.library_prefix(), "lib");
+        assert_eq!(linux.static_library_extension(), "a");
+        assert_eq!(linux.dynamic_library_extension(), "so");
+    }
+}
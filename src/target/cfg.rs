@@ -0,0 +1,322 @@
+use super::{errors::TargetError, TargetTriple};
+
+/// A `cfg(...)` expression of a `[target.'cfg(...)']` manifest section
+///
+/// Supported predicates are `unix`, `windows`, `key = "value"` with the keys `target_arch`,
+/// `target_os`, `target_env`, `target_abi`, `target_vendor` and `target_family`,
+/// and the `all(...)`, `any(...)` and `not(...)` combinators
+#[derive(PartialEq, Eq, Debug)]
+pub enum CfgExpression {
+    /// A bare identifier (e.g. `unix`)
+    Name(String),
+    /// A key and its value (e.g. `target_os = "linux"`)
+    KeyValue(String, String),
+    All(Vec<CfgExpression>),
+    Any(Vec<CfgExpression>),
+    Not(Box<CfgExpression>),
+}
+
+impl CfgExpression {
+    /// Parses a `cfg(...)` expression
+    pub fn parse(expression: &str) -> Result<Self, TargetError> {
+        let invalid = |reason: &str| TargetError::InvalidCfg {
+            expression: expression.to_string(),
+            reason: reason.to_string(),
+        };
+        let mut parser = Parser {
+            tokens: tokenize(expression).map_err(|reason| invalid(&reason))?,
+            position: 0,
+        };
+        if parser.next() != Some(Token::Ident("cfg".to_string())) {
+            return Err(invalid("expected `cfg(`"));
+        }
+        parser.expect(Token::OpenParen).map_err(|r| invalid(&r))?;
+        let parsed = parser.expression().map_err(|r| invalid(&r))?;
+        parser.expect(Token::CloseParen).map_err(|r| invalid(&r))?;
+        if parser.position != parser.tokens.len() {
+            return Err(invalid("unexpected content after `cfg(...)`"));
+        }
+        parsed.check_keys(expression)?;
+        Ok(parsed)
+    }
+
+    /// Checks that the expression only uses the supported keys and identifiers
+    fn check_keys(&self, expression: &str) -> Result<(), TargetError> {
+        let unknown = |key: &str| TargetError::UnknownCfgKey {
+            expression: expression.to_string(),
+            key: key.to_string(),
+        };
+        match self {
+            CfgExpression::Name(name) if !matches!(name.as_str(), "unix" | "windows") => {
+                Err(unknown(name))
+            }
+            CfgExpression::KeyValue(key, _)
+                if target_value(&TargetTriple::host(), key).is_none() =>
+            {
+                Err(unknown(key))
+            }
+            CfgExpression::All(expressions) | CfgExpression::Any(expressions) => expressions
+                .iter()
+                .try_for_each(|e| e.check_keys(expression)),
+            CfgExpression::Not(inner) => inner.check_keys(expression),
+            _ => Ok(()),
+        }
+    }
+
+    /// Evaluates the expression against a target
+    pub fn matches(&self, target: &TargetTriple) -> bool {
+        match self {
+            CfgExpression::Name(name) => target.family() == Some(name.as_str()),
+            CfgExpression::KeyValue(key, value) => {
+                target_value(target, key).is_some_and(|v| v == value)
+            }
+            CfgExpression::All(expressions) => expressions.iter().all(|e| e.matches(target)),
+            CfgExpression::Any(expressions) => expressions.iter().any(|e| e.matches(target)),
+            CfgExpression::Not(inner) => !inner.matches(target),
+        }
+    }
+}
+
+/// Checks whether the key of a `[target.<key>]` section applies to a target
+///
+/// The key is either a `cfg(...)` expression or a target triple, which matches the same target
+/// written with or without its vendor (e.g. `aarch64-linux-gnu` and `aarch64-unknown-linux-gnu`)
+pub fn target_section_matches(key: &str, target: &TargetTriple) -> Result<bool, TargetError> {
+    if key.trim_start().starts_with("cfg") {
+        Ok(CfgExpression::parse(key)?.matches(target))
+    } else {
+        Ok(TargetTriple::parse(key)?.is_same_target(target))
+    }
+}
+
+/// The value of a cfg key for a target, `None` for unknown keys
+fn target_value<'a>(target: &'a TargetTriple, key: &str) -> Option<&'a str> {
+    Some(match key {
+        "target_arch" => &target.arch,
+        "target_os" => &target.os,
+        "target_env" => &target.env,
+        "target_abi" => &target.abi,
+        "target_vendor" => &target.vendor,
+        "target_family" => target.family().unwrap_or_default(),
+        _ => return None,
+    })
+}
+
+#[derive(PartialEq, Eq, Debug, Clone)]
+enum Token {
+    Ident(String),
+    String(String),
+    Equals,
+    Comma,
+    OpenParen,
+    CloseParen,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '=' => tokens.push(Token::Equals),
+            ',' => tokens.push(Token::Comma),
+            '(' => tokens.push(Token::OpenParen),
+            ')' => tokens.push(Token::CloseParen),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => value.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                tokens.push(Token::String(value));
+            }
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut ident = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    ident.push(c);
+                    chars.next();
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            c => return Err(format!("unexpected character `{c}`")),
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("expected {expected:?}, found {token:?}")),
+            None => Err(format!(
+                "expected {expected:?}, found the end of the expression"
+            )),
+        }
+    }
+
+    fn expression(&mut self) -> Result<CfgExpression, String> {
+        let Some(Token::Ident(name)) = self.next() else {
+            return Err("expected an identifier".to_string());
+        };
+        match self.peek() {
+            Some(Token::Equals) => {
+                self.next();
+                match self.next() {
+                    Some(Token::String(value)) => Ok(CfgExpression::KeyValue(name, value)),
+                    _ => Err(format!("expected a string after `{name} =`")),
+                }
+            }
+            Some(Token::OpenParen) => {
+                self.next();
+                let mut expressions = vec![];
+                while self.peek() != Some(&Token::CloseParen) {
+                    expressions.push(self.expression()?);
+                    if self.peek() == Some(&Token::Comma) {
+                        self.next();
+                    } else {
+                        break;
+                    }
+                }
+                self.expect(Token::CloseParen)?;
+                match name.as_str() {
+                    "all" => Ok(CfgExpression::All(expressions)),
+                    "any" => Ok(CfgExpression::Any(expressions)),
+                    "not" => match <[CfgExpression; 1]>::try_from(expressions) {
+                        Ok([inner]) => Ok(CfgExpression::Not(Box::new(inner))),
+                        Err(_) => Err("`not` takes exactly one expression".to_string()),
+                    },
+                    _ => Err(format!("unknown operator `{name}`")),
+                }
+            }
+            _ => Ok(CfgExpression::Name(name)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(expression: &str, triple: &str) -> bool {
+        let target = TargetTriple::parse(triple).unwrap();
+        CfgExpression::parse(expression).unwrap().matches(&target)
+    }
+
+    #[test]
+    fn expressions_are_parsed() {
+        assert_eq!(
+            CfgExpression::parse(r#"cfg(all(unix, not(target_os = "macos")))"#).unwrap(),
+            CfgExpression::All(vec![
+                CfgExpression::Name("unix".to_string()),
+                CfgExpression::Not(Box::new(CfgExpression::KeyValue(
+                    "target_os".to_string(),
+                    "macos".to_string()
+                ))),
+            ])
+        );
+        // a trailing comma is accepted like in Rust
+        assert_eq!(
+            CfgExpression::parse("cfg(any(windows,))").unwrap(),
+            CfgExpression::Any(vec![CfgExpression::Name("windows".to_string())])
+        );
+    }
+
+    #[test]
+    fn expressions_are_evaluated_against_the_target() {
+        assert!(matches("cfg(unix)", "aarch64-linux-gnu"));
+        assert!(!matches("cfg(unix)", "x86_64-pc-windows-msvc"));
+        assert!(matches("cfg(windows)", "x86_64-w64-mingw32"));
+        assert!(matches(
+            r#"cfg(target_arch = "arm")"#,
+            "armv7-unknown-linux-gnueabihf"
+        ));
+        assert!(matches(
+            r#"cfg(target_abi = "eabihf")"#,
+            "armv7-unknown-linux-gnueabihf"
+        ));
+        assert!(matches(r#"cfg(target_env = "musl")"#, "x86_64-linux-musl"));
+        assert!(matches(
+            r#"cfg(target_vendor = "apple")"#,
+            "aarch64-apple-darwin"
+        ));
+        assert!(matches(
+            r#"cfg(target_family = "unix")"#,
+            "aarch64-apple-darwin"
+        ));
+        assert!(matches(r#"cfg(target_os = "none")"#, "arm-none-eabi"));
+        assert!(!matches("cfg(any(unix, windows))", "arm-none-eabi"));
+        assert!(matches("cfg(all())", "arm-none-eabi"));
+        assert!(!matches("cfg(any())", "arm-none-eabi"));
+        assert!(matches(
+            r#"cfg(all(unix, not(target_arch = "x86_64")))"#,
+            "aarch64-linux-gnu"
+        ));
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected() {
+        for expression in [
+            "unix",
+            "cfg(unix",
+            "cfg(unix) extra",
+            r#"cfg(target_os = linux)"#,
+            r#"cfg(target_os = "linux)"#,
+            "cfg(not(unix, windows))",
+            "cfg(xor(unix))",
+            "cfg(unix; windows)",
+        ] {
+            assert!(
+                matches!(
+                    CfgExpression::parse(expression),
+                    Err(TargetError::InvalidCfg { .. })
+                ),
+                "{expression}"
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        for expression in [r#"cfg(target_pointer_width = "64")"#, "cfg(linux)"] {
+            assert!(
+                matches!(
+                    CfgExpression::parse(expression),
+                    Err(TargetError::UnknownCfgKey { .. })
+                ),
+                "{expression}"
+            );
+        }
+    }
+
+    #[test]
+    fn section_keys_are_cfg_expressions_or_triples() {
+        let target = TargetTriple::parse("aarch64-unknown-linux-gnu").unwrap();
+        assert!(target_section_matches("cfg(unix)", &target).unwrap());
+        assert!(target_section_matches("aarch64-unknown-linux-gnu", &target).unwrap());
+        assert!(target_section_matches("aarch64-linux-gnu", &target).unwrap());
+        assert!(!target_section_matches("aarch64-linux-musl", &target).unwrap());
+        assert!(target_section_matches("aarch64", &target).is_err());
+    }
+}
@@ -0,0 +1,11 @@
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum TargetError {
+    #[error("invalid target triple `{0}`")]
+    InvalidTriple(String),
+    #[error("invalid cfg expression `{expression}`: {reason}")]
+    InvalidCfg { expression: String, reason: String },
+    #[error("unknown cfg key `{key}` in `{expression}`")]
+    UnknownCfgKey { expression: String, key: String },
+}
@@ -0,0 +1,197 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+use crate::{
+    filenames::TOOLCHAIN_FILE_NAME,
+    manifest::{Manifest, ToolchainSettings},
+    target::TargetTriple,
+};
+
+pub mod errors;
+use errors::ToolchainError;
+
+/// The content of `cpm-toolchain.toml`
+#[derive(Deserialize, Default)]
+struct ToolchainFile {
+    #[serde(default)]
+    toolchain: BTreeMap<String, ToolchainSettings>,
+}
+
+/// The programs and flags used to build for a target
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Toolchain {
+    /// The target the toolchain builds for
+    pub target: TargetTriple,
+    /// Whether the target was selected with `--target`, its outputs are then placed in `target/<triple>`
+    pub explicit_target: bool,
+    /// Prefix of the programs of the toolchain (e.g. `aarch64-linux-gnu-`)
+    pub prefix: String,
+    pub compiler: Option<String>,
+    pub archiver: Option<String>,
+    pub objcopy: Option<String>,
+    pub linker: Option<String>,
+    /// Absolute path of the sysroot, passed as `--sysroot` to the compiler and the linker
+    pub sysroot: Option<PathBuf>,
+    pub compiler_flags: Vec<String>,
+    pub linker_flags: Vec<String>,
+}
+
+impl Toolchain {
+    /// The toolchain of the host, the programs of the compiler backend without a prefix
+    pub fn host() -> Self {
+        Self::from_settings(
+            TargetTriple::host(),
+            false,
+            &ToolchainSettings::default(),
+            Path::new(""),
+        )
+    }
+
+    /// Finds the toolchain of the target `triple` selected with `--target`
+    ///
+    /// The `[toolchain.<triple>]` sections of `cpm-toolchain.toml` take precedence over the ones of the
+    /// workspace manifest. The host target doesn't need a toolchain section
+    pub fn for_target(
+        triple: &str,
+        workspace_path: &Path,
+        workspace_manifest: &Manifest,
+    ) -> Result<Self, ToolchainError> {
+        let target = TargetTriple::parse(triple)?;
+        let toolchain_file = load_toolchain_file(workspace_path)?;
+        let settings = [&toolchain_file.toolchain, &workspace_manifest.toolchain]
+            .into_iter()
+            .find_map(|toolchains| find_settings(toolchains, &target));
+        match settings {
+            Some(settings) => Ok(Self::from_settings(target, true, settings, workspace_path)),
+            None if target.is_same_target(&TargetTriple::host()) => Ok(Self {
+                explicit_target: true,
+                ..Self::host()
+            }),
+            None => Err(ToolchainError::NotDefined(triple.to_string())),
+        }
+    }
+
+    fn from_settings(
+        target: TargetTriple,
+        explicit_target: bool,
+        settings: &ToolchainSettings,
+        workspace_path: &Path,
+    ) -> Self {
+        Self {
+            target,
+            explicit_target,
+            prefix: settings.prefix.clone(),
+            compiler: settings.compiler.clone(),
+            archiver: settings.archiver.clone(),
+            objcopy: settings.objcopy.clone(),
+            linker: settings.linker.clone(),
+            sysroot: settings
+                .sysroot
+                .as_ref()
+                .map(|sysroot| workspace_path.join(sysroot)),
+            compiler_flags: settings.compiler_flags.clone(),
+            linker_flags: settings.linker_flags.clone(),
+        }
+    }
+
+    /// The folder, relative to the `target` folder, where the outputs of this toolchain go
+    ///
+    /// It's the triple for targets selected with `--target`, the host uses the `target` folder itself
+    pub fn output_subfolder(&self) -> &str {
+        if self.explicit_target {
+            &self.target.triple
+        } else {
+            ""
+        }
+    }
+
+    /// Whether the toolchain builds for another target than the host
+    pub fn is_cross_compiling(&self) -> bool {
+        !self.target.is_same_target(&TargetTriple::host())
+    }
+
+    /// The compiler program, `default` is the program of the compiler backend (e.g. `gcc`)
+    pub fn compiler_program(&self, default: &str) -> String {
+        self.compiler
+            .clone()
+            .unwrap_or_else(|| format!("{}{default}", self.prefix))
+    }
+
+    /// The archiver program, `ar` with the prefix by default
+    pub fn archiver_program(&self) -> String {
+        self.archiver
+            .clone()
+            .unwrap_or_else(|| format!("{}ar", self.prefix))
+    }
+
+    /// The program that creates images from executables, `objcopy` with the prefix by default
+    pub fn objcopy_program(&self) -> String {
+        self.objcopy
+            .clone()
+            .unwrap_or_else(|| format!("{}objcopy", self.prefix))
+    }
+
+    /// A binary utility of the toolchain with its prefix (e.g. `aarch64-linux-gnu-size`)
+    pub fn binutils_program(&self, name: &str) -> String {
+        format!("{}{name}", self.prefix)
+    }
+
+    /// The linker program, the compiler program by default
+    pub fn linker_program(&self, default: &str) -> String {
+        self.linker
+            .clone()
+            .unwrap_or_else(|| self.compiler_program(default))
+    }
+
+    /// The flags of the toolchain passed to the compiler
+    pub fn compiler_flags(&self) -> Vec<String> {
+        let mut flags = self.sysroot_flag();
+        flags.extend(self.compiler_flags.iter().cloned());
+        flags
+    }
+
+    /// The flags of the toolchain passed to the linker
+    pub fn linker_flags(&self) -> Vec<String> {
+        let mut flags = self.sysroot_flag();
+        flags.extend(self.linker_flags.iter().cloned());
+        flags
+    }
+
+    fn sysroot_flag(&self) -> Vec<String> {
+        self.sysroot
+            .iter()
+            .map(|sysroot| format!("--sysroot={}", sysroot.display()))
+            .collect()
+    }
+}
+
+impl Default for Toolchain {
+    fn default() -> Self {
+        Self::host()
+    }
+}
+
+/// Loads `cpm-toolchain.toml` from the workspace folder, if there's one
+fn load_toolchain_file(workspace_path: &Path) -> Result<ToolchainFile, ToolchainError> {
+    let path = workspace_path.join(TOOLCHAIN_FILE_NAME);
+    if !path.exists() {
+        return Ok(ToolchainFile::default());
+    }
+    let content = std::fs::read_to_string(&path)?;
+    toml::from_str(&content).map_err(|error| ToolchainError::InvalidToolchainFile { path, error })
+}
+
+/// The settings of the toolchain section whose triple is the same target as `target`
+fn find_settings<'a>(
+    toolchains: &'a BTreeMap<String, ToolchainSettings>,
+    target: &TargetTriple,
+) -> Option<&'a ToolchainSettings> {
+    toolchains.iter().find_map(|(triple, settings)| {
+        let candidate = TargetTriple::parse(triple).ok()?;
+        candidate.is_same_target(target).then_some(settings)
+    })
+}
@@ -0,0 +1,20 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+use crate::target::errors::TargetError;
+
+#[derive(Error, Debug)]
+pub enum ToolchainError {
+    #[error(transparent)]
+    InvalidTarget(#[from] TargetError),
+    #[error("no toolchain is defined for target `{0}`, add a `[toolchain.{0}]` section to `cpm.toml` or `cpm-toolchain.toml`")]
+    NotDefined(String),
+    #[error("invalid toolchain file {path:?}: {error}")]
+    InvalidToolchainFile {
+        path: PathBuf,
+        error: toml::de::Error,
+    },
+    #[error("Io error: {0}")]
+    IOError(#[from] std::io::Error),
+}
@@ -0,0 +1,370 @@
+use std::{
+    collections::BTreeMap,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::{
+    dependencies::{PackageSource, ResolvedPackage},
+    manifest::Manifest,
+    package::builder::create_parent_folder,
+    registry::{file_checksum, IndexEntry, RegistryIndex},
+};
+
+pub mod errors;
+use errors::VendorError;
+
+/// The default folder of the vendored packages, relative to the workspace
+pub const DEFAULT_VENDOR_FOLDER: &str = "vendor";
+
+/// The file written in each vendored package with the checksums of its files
+const CHECKSUM_FILE_NAME: &str = ".cpm-checksum.toml";
+
+/// Files of the source caches that aren't part of a package
+const IGNORED_FILE_NAMES: &[&str] = &[".git", ".cpm-checksum", CHECKSUM_FILE_NAME];
+
+/// The checksum file of a vendored package
+#[derive(Deserialize, Serialize)]
+pub struct VendoredPackage {
+    pub name: String,
+    pub version: semver::Version,
+    /// The source of the package, as written in the lockfile
+    pub source: String,
+    /// SHA-256 of the registry tarball, for registry packages
+    pub checksum: Option<String>,
+    /// SHA-256 of every file of the package, by path relative to the package folder
+    pub files: BTreeMap<String, String>,
+}
+
+/// A folder with a copy of the registry and git dependencies of a workspace
+///
+/// Each package is copied in `<vendor folder>/<name>` along with a checksum file,
+/// so the builds don't need the registry or the git repositories
+pub struct VendorDirectory {
+    path: PathBuf,
+    allow_modified: bool,
+}
+
+impl VendorDirectory {
+    /// Opens a vendor folder, vendored packages are verified against their
+    /// checksums unless `allow_modified` is set
+    pub fn new(path: impl Into<PathBuf>, allow_modified: bool) -> Self {
+        Self {
+            path: path.into(),
+            allow_modified,
+        }
+    }
+
+    /// Opens the vendor folder configured with `[vendor] path` in the workspace manifest, if any
+    pub fn for_workspace(
+        workspace_path: &Path,
+        workspace_manifest: &Manifest,
+        allow_modified: bool,
+    ) -> Option<Self> {
+        let settings = workspace_manifest.vendor.as_ref()?;
+        Some(Self::new(
+            workspace_path.join(&settings.path),
+            allow_modified,
+        ))
+    }
+
+    /// The vendor folder
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The folder of a vendored package
+    pub fn package_path(&self, name: &str) -> PathBuf {
+        self.path.join(name)
+    }
+
+    /// Reads the checksum file of a vendored package and checks that the package wasn't modified
+    pub fn package(&self, name: &str) -> Result<VendoredPackage, VendorError> {
+        let package_path = self.package_path(name);
+        let checksum_file_path = package_path.join(CHECKSUM_FILE_NAME);
+        let content = match std::fs::read_to_string(&checksum_file_path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                return Err(VendorError::NotVendored(name.to_string()))
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let vendored: VendoredPackage =
+            toml::from_str(&content).map_err(|error| VendorError::InvalidChecksumFile {
+                path: checksum_file_path,
+                error: Box::new(error),
+            })?;
+        if !self.allow_modified {
+            let files = file_checksums(&package_path)?;
+            let modified_file = vendored
+                .files
+                .keys()
+                .chain(files.keys())
+                .find(|file| vendored.files.get(*file) != files.get(*file));
+            if let Some(file) = modified_file {
+                return Err(VendorError::Modified {
+                    name: name.to_string(),
+                    file: file.clone(),
+                });
+            }
+        }
+        Ok(vendored)
+    }
+
+    /// The commit and folder of a vendored git package
+    ///
+    /// `source_prefix` is the beginning of the lockfile source of the git dependency, up to the commit
+    pub fn git_package(
+        &self,
+        name: &str,
+        source_prefix: &str,
+    ) -> Result<(String, PathBuf), VendorError> {
+        let vendored = self.package(name)?;
+        let commit = vendored.source.strip_prefix(source_prefix).ok_or_else(|| {
+            VendorError::SourceMismatch {
+                name: name.to_string(),
+                expected: source_prefix.to_string(),
+                found: vendored.source.clone(),
+            }
+        })?;
+        Ok((commit.to_string(), self.package_path(name)))
+    }
+
+    /// The index of a vendored registry package, with its only vendored version
+    pub fn index(&self, name: &str) -> Result<RegistryIndex, VendorError> {
+        let vendored = self.package(name)?;
+        let (Some(checksum), "registry") = (vendored.checksum, vendored.source.as_str()) else {
+            return Err(VendorError::SourceMismatch {
+                name: name.to_string(),
+                expected: "registry".to_string(),
+                found: vendored.source,
+            });
+        };
+        let manifest = Manifest::load_manifest_from_project_path(self.package_path(name)).map_err(
+            |error| VendorError::InvalidManifest {
+                name: name.to_string(),
+                error: Box::new(error),
+            },
+        )?;
+        Ok(RegistryIndex {
+            versions: vec![IndexEntry::new(vendored.version, checksum, &manifest)],
+        })
+    }
+
+    /// Removes the previously vendored packages
+    pub fn clear(&self) -> Result<(), VendorError> {
+        let entries = match std::fs::read_dir(&self.path) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        for entry in entries {
+            let path = entry?.path();
+            if path.join(CHECKSUM_FILE_NAME).exists() {
+                std::fs::remove_dir_all(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies a registry or git package into the vendor folder and writes its checksum file
+    ///
+    /// Returns `false` for the packages that aren't vendored (workspace and path packages)
+    pub fn vendor_package(&self, package: &ResolvedPackage) -> Result<bool, VendorError> {
+        let (Some(source), PackageSource::Registry { .. } | PackageSource::Git { .. }) =
+            (package.source.lockfile_source(), &package.source)
+        else {
+            return Ok(false);
+        };
+        let package_path = self.package_path(&package.name);
+        for file in package_files(&package.path) {
+            let destination_path = package_path.join(&file);
+            create_parent_folder(&destination_path)?;
+            std::fs::copy(package.path.join(&file), destination_path)?;
+        }
+        let vendored = VendoredPackage {
+            name: package.name.clone(),
+            version: package.version.clone(),
+            source,
+            checksum: match &package.source {
+                PackageSource::Registry { checksum } => Some(checksum.clone()),
+                _ => None,
+            },
+            files: file_checksums(&package_path)?,
+        };
+        std::fs::write(
+            package_path.join(CHECKSUM_FILE_NAME),
+            format!(
+                "# This file is generated by `cpm vendor`, do not edit it by hand.\n{}",
+                toml::to_string(&vendored)?
+            ),
+        )?;
+        Ok(true)
+    }
+}
+
+/// The files of a package, relative to its folder, without the files of the source caches
+fn package_files(package_path: &Path) -> Vec<PathBuf> {
+    WalkDir::new(package_path)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| {
+            !IGNORED_FILE_NAMES
+                .iter()
+                .any(|ignored| e.file_name() == *ignored)
+        })
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| {
+            e.path()
+                .strip_prefix(package_path)
+                .ok()
+                .map(Path::to_path_buf)
+        })
+        .collect()
+}
+
+/// Computes the checksum of every file of a package, by path relative to the package folder
+fn file_checksums(package_path: &Path) -> std::io::Result<BTreeMap<String, String>> {
+    let mut checksums = BTreeMap::new();
+    for file in package_files(package_path) {
+        let key = file
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        checksums.insert(key, file_checksum(&package_path.join(&file))?);
+    }
+    Ok(checksums)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A registry package with a manifest and a source file, and a vendor folder
+    /// in a new temporary folder, removed when dropped
+    struct TemporaryVendor {
+        folder: PathBuf,
+        package: ResolvedPackage,
+    }
+
+    impl TemporaryVendor {
+        fn new(name: &str) -> Self {
+            let folder =
+                std::env::temp_dir().join(format!("cpm-vendor-{name}-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&folder);
+            let package_path = folder.join("cache").join("json-1.2.0");
+            std::fs::create_dir_all(package_path.join("src")).unwrap();
+            std::fs::write(
+                package_path.join("cpm.toml"),
+                "[package]\nname = \"json\"\nversion = \"1.2.0\"\nkind = \"staticlib\"\n",
+            )
+            .unwrap();
+            std::fs::write(package_path.join("src/json.c"), "int parse(void);\n").unwrap();
+            // the checksum marker of the registry source cache isn't vendored
+            std::fs::write(package_path.join(".cpm-checksum"), "ab12").unwrap();
+            Self {
+                package: ResolvedPackage {
+                    name: "json".to_string(),
+                    version: semver::Version::new(1, 2, 0),
+                    source: PackageSource::Registry {
+                        checksum: "ab12".to_string(),
+                    },
+                    path: package_path,
+                    dependencies: vec![],
+                    dev_dependencies: vec![],
+                    build_dependencies: vec![],
+                    host_dependencies: vec![],
+                    host_build_dependencies: vec![],
+                    external: None,
+                    patched: false,
+                },
+                folder,
+            }
+        }
+
+        fn vendor(&self, allow_modified: bool) -> VendorDirectory {
+            VendorDirectory::new(self.folder.join("vendor"), allow_modified)
+        }
+    }
+
+    impl Drop for TemporaryVendor {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.folder);
+        }
+    }
+
+    fn modified_file(result: Result<VendoredPackage, VendorError>) -> Option<String> {
+        match result {
+            Err(VendorError::Modified { file, .. }) => Some(file),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn vendored_packages_are_verified_against_their_checksums() {
+        let temporary = TemporaryVendor::new("verified");
+        let vendor = temporary.vendor(false);
+        assert!(vendor.vendor_package(&temporary.package).unwrap());
+        let vendored = vendor.package("json").unwrap();
+        assert_eq!(vendored.source, "registry");
+        let files: Vec<&str> = vendored.files.keys().map(String::as_str).collect();
+        assert_eq!(files, ["cpm.toml", "src/json.c"]);
+        let index = vendor.index("json").unwrap();
+        assert_eq!(index.versions[0].version, semver::Version::new(1, 2, 0));
+        assert_eq!(index.versions[0].checksum, "ab12");
+    }
+
+    #[test]
+    fn modified_vendored_files_are_detected() {
+        let temporary = TemporaryVendor::new("modified");
+        let vendor = temporary.vendor(false);
+        vendor.vendor_package(&temporary.package).unwrap();
+        let source_path = vendor.package_path("json").join("src/json.c");
+        std::fs::write(&source_path, "int parse(void) { return 1; }\n").unwrap();
+        assert_eq!(
+            modified_file(vendor.package("json")).as_deref(),
+            Some("src/json.c")
+        );
+        assert!(temporary.vendor(true).package("json").is_ok());
+    }
+
+    #[test]
+    fn added_and_removed_vendored_files_are_detected() {
+        let temporary = TemporaryVendor::new("added");
+        let vendor = temporary.vendor(false);
+        vendor.vendor_package(&temporary.package).unwrap();
+        let added_path = vendor.package_path("json").join("src/extra.c");
+        std::fs::write(&added_path, "int extra(void);\n").unwrap();
+        assert_eq!(
+            modified_file(vendor.package("json")).as_deref(),
+            Some("src/extra.c")
+        );
+        std::fs::remove_file(added_path).unwrap();
+        std::fs::remove_file(vendor.package_path("json").join("cpm.toml")).unwrap();
+        assert_eq!(
+            modified_file(vendor.package("json")).as_deref(),
+            Some("cpm.toml")
+        );
+    }
+
+    #[test]
+    fn only_vendored_packages_are_cleared() {
+        let temporary = TemporaryVendor::new("clear");
+        let vendor = temporary.vendor(false);
+        assert!(matches!(
+            vendor.package("json"),
+            Err(VendorError::NotVendored(name)) if name == "json"
+        ));
+        vendor.vendor_package(&temporary.package).unwrap();
+        let other_path = vendor.path().join("README.md");
+        std::fs::write(&other_path, "vendored packages\n").unwrap();
+        vendor.clear().unwrap();
+        assert!(!vendor.package_path("json").exists());
+        assert!(other_path.exists());
+    }
+}
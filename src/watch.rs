@@ -0,0 +1,252 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    ffi::{OsStr, OsString},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use walkdir::WalkDir;
+
+use crate::filenames::MANIFEST_FILE_NAME;
+
+pub mod errors;
+use errors::WatchError;
+
+/// How long the files must stay unchanged before a burst of changes is reported,
+/// editors and `git checkout` usually write several files at once
+pub const DEBOUNCE: Duration = Duration::from_millis(300);
+
+/// How often the events are read while waiting for changes
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// The events that change the files of a watched folder
+///
+/// Modifications are reported once the file is closed, so a file isn't read while it's written
+fn watch_mask() -> WatchMask {
+    WatchMask::CLOSE_WRITE
+        | WatchMask::CREATE
+        | WatchMask::DELETE
+        | WatchMask::MOVED_FROM
+        | WatchMask::MOVED_TO
+}
+
+/// The paths watched for a package
+pub struct WatchedPackage {
+    pub name: String,
+    /// The folder of the package, where its manifest is
+    pub path: PathBuf,
+    /// The folders of its sources and headers, watched with their subfolders
+    pub folders: Vec<PathBuf>,
+}
+
+/// The changes found by a [`Watcher`]
+#[derive(Default, Debug)]
+pub struct Changes {
+    /// The packages whose sources, headers or manifest changed
+    pub packages: BTreeSet<String>,
+    /// A manifest changed, packages and folders may have been added or removed
+    pub manifest: bool,
+    /// The workspace manifest changed or events were lost, every package must be considered changed
+    pub everything: bool,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.packages.is_empty() && !self.everything
+    }
+}
+
+/// What a watched folder is
+enum WatchKind {
+    /// A folder of sources or headers of a package, or one of its subfolders
+    Sources(String),
+    /// The folder of a package, only its manifest is considered
+    Package(String),
+    /// The folder of the workspace, only its manifest is considered
+    Workspace,
+}
+
+/// Watches the folders and manifests of packages through inotify
+pub struct Watcher {
+    inotify: Inotify,
+    /// The folders of every watch, a folder can be watched for several packages
+    watches: HashMap<WatchDescriptor, Vec<(PathBuf, WatchKind)>>,
+    /// The folders of packages that don't exist yet, they're watched once they're created
+    missing_folders: Vec<(PathBuf, String)>,
+    buffer: Vec<u8>,
+}
+
+impl Watcher {
+    /// Starts watching the workspace manifest and the folders of the packages
+    pub fn new(workspace_path: &Path, packages: &[WatchedPackage]) -> Result<Self, WatchError> {
+        let mut watcher = Self {
+            inotify: Inotify::init().map_err(WatchError::Init)?,
+            watches: HashMap::new(),
+            missing_folders: vec![],
+            buffer: vec![0; 4096],
+        };
+        watcher.add(workspace_path, WatchKind::Workspace)?;
+        for package in packages {
+            watcher.add(&package.path, WatchKind::Package(package.name.clone()))?;
+            for folder in &package.folders {
+                if folder.is_dir() {
+                    watcher.add_recursive(folder, &package.name)?;
+                } else {
+                    watcher
+                        .missing_folders
+                        .push((folder.clone(), package.name.clone()));
+                }
+            }
+        }
+        Ok(watcher)
+    }
+
+    /// The number of watched folders
+    pub fn folder_count(&self) -> usize {
+        self.watches.len()
+    }
+
+    fn add(&mut self, folder: &Path, kind: WatchKind) -> Result<(), WatchError> {
+        let descriptor = self
+            .inotify
+            .watches()
+            .add(folder, watch_mask())
+            .map_err(|error| WatchError::Watch {
+                path: folder.to_path_buf(),
+                error,
+            })?;
+        self.watches
+            .entry(descriptor)
+            .or_default()
+            .push((folder.to_path_buf(), kind));
+        Ok(())
+    }
+
+    /// Watches a folder of a package and its subfolders, except hidden ones
+    fn add_recursive(&mut self, folder: &Path, package: &str) -> Result<(), WatchError> {
+        let subfolders: Vec<PathBuf> = WalkDir::new(folder)
+            .into_iter()
+            .filter_entry(|entry| entry.depth() == 0 || !is_ignored(entry.file_name()))
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_dir())
+            .map(|entry| entry.into_path())
+            .collect();
+        for subfolder in subfolders {
+            self.add(&subfolder, WatchKind::Sources(package.to_string()))?;
+        }
+        Ok(())
+    }
+
+    /// Waits for changes and returns them once no file changed for [`DEBOUNCE`]
+    ///
+    /// `on_idle` is called after every poll of the events (e.g. to check if a program exited)
+    pub fn wait_for_changes(&mut self, mut on_idle: impl FnMut()) -> Result<Changes, WatchError> {
+        let mut changes = Changes::default();
+        let mut last_change = Instant::now();
+        loop {
+            if self.read_events(&mut changes)? {
+                last_change = Instant::now();
+            } else if !changes.is_empty() && last_change.elapsed() >= DEBOUNCE {
+                return Ok(changes);
+            }
+            on_idle();
+            std::thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Adds the pending events to `changes`, returns whether a file of a package changed
+    fn read_events(&mut self, changes: &mut Changes) -> Result<bool, WatchError> {
+        let mut changed = false;
+        loop {
+            let events: Vec<(WatchDescriptor, EventMask, Option<OsString>)> =
+                match self.inotify.read_events(&mut self.buffer) {
+                    Ok(events) => events
+                        .map(|event| (event.wd, event.mask, event.name.map(OsStr::to_os_string)))
+                        .collect(),
+                    Err(error) if error.kind() == ErrorKind::WouldBlock => return Ok(changed),
+                    Err(error) => return Err(WatchError::Read(error)),
+                };
+            if events.is_empty() {
+                return Ok(changed);
+            }
+            for (descriptor, mask, name) in events {
+                changed |= self.handle_event(descriptor, mask, name, changes)?;
+            }
+        }
+    }
+
+    fn handle_event(
+        &mut self,
+        descriptor: WatchDescriptor,
+        mask: EventMask,
+        name: Option<OsString>,
+        changes: &mut Changes,
+    ) -> Result<bool, WatchError> {
+        if mask.contains(EventMask::Q_OVERFLOW) {
+            changes.everything = true;
+            return Ok(true);
+        }
+        if mask.contains(EventMask::IGNORED) {
+            // the folder was removed
+            self.watches.remove(&descriptor);
+            return Ok(false);
+        }
+        let (Some(name), Some(entries)) = (name, self.watches.get(&descriptor)) else {
+            return Ok(false);
+        };
+        if is_ignored(&name) {
+            return Ok(false);
+        }
+        let folder_created = mask.contains(EventMask::ISDIR)
+            && mask.intersects(EventMask::CREATE | EventMask::MOVED_TO);
+        let mut changed = false;
+        let mut new_folders = vec![];
+        for (folder, kind) in entries {
+            match kind {
+                WatchKind::Sources(package) => {
+                    changes.packages.insert(package.clone());
+                    if folder_created {
+                        new_folders.push((folder.join(&name), package.clone()));
+                    }
+                }
+                WatchKind::Package(package) if name == MANIFEST_FILE_NAME => {
+                    changes.packages.insert(package.clone());
+                    changes.manifest = true;
+                }
+                WatchKind::Workspace if name == MANIFEST_FILE_NAME => {
+                    changes.everything = true;
+                    changes.manifest = true;
+                }
+                WatchKind::Package(_) | WatchKind::Workspace => continue,
+            }
+            changed = true;
+        }
+        if folder_created {
+            if let Some((folder, _)) = entries.first() {
+                let created = folder.join(&name);
+                self.missing_folders.retain(|(missing, package)| {
+                    if *missing != created {
+                        return true;
+                    }
+                    new_folders.push((created.clone(), package.clone()));
+                    false
+                });
+            }
+        }
+        for (folder, package) in new_folders {
+            changes.packages.insert(package.clone());
+            changed = true;
+            // the files written before the folder is watched are covered by this change
+            self.add_recursive(&folder, &package)?;
+        }
+        Ok(changed)
+    }
+}
+
+/// Hidden files and the backups of editors (e.g. `.main.c.swp` or `main.c~`) aren't sources
+fn is_ignored(name: &OsStr) -> bool {
+    let name = name.to_string_lossy();
+    name.starts_with('.') || name.ends_with('~')
+}
@@ -0,0 +1,16 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum WatchError {
+    #[error("failed to initialize inotify: {0}")]
+    Init(std::io::Error),
+    #[error("failed to watch {path:?}: {error}")]
+    Watch {
+        path: PathBuf,
+        error: std::io::Error,
+    },
+    #[error("failed to read the file events: {0}")]
+    Read(std::io::Error),
+}
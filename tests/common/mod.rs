@@ -0,0 +1,43 @@
+//! Helpers shared by the integration tests
+
+use std::path::PathBuf;
+
+/// A temporary folder, removed when dropped
+pub struct TemporaryFolder(pub PathBuf);
+
+impl TemporaryFolder {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("cpm-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn write(&self, path: &str, content: &str) -> PathBuf {
+        let path = self.0.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+}
+
+impl Drop for TemporaryFolder {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Adds a package with a single source file to `folder`, with `sections` appended to its manifest
+pub fn add_package(folder: &TemporaryFolder, name: &str, kind: &str, sections: &str) {
+    folder.write(
+        &format!("{name}/cpm.toml"),
+        &format!(
+            "[package]\nname = \"{name}\"\nversion = \"0.1.0\"\nkind = \"{kind}\"\n\n{sections}"
+        ),
+    );
+    let source = match kind {
+        "exe" => "int main(void) { return 0; }\n",
+        _ => "int answer(void) { return 42; }\n",
+    };
+    folder.write(&format!("{name}/src/{name}.c"), source);
+}
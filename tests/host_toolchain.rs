@@ -0,0 +1,188 @@
+//! Builds workspaces with the compiler of the host
+
+mod common;
+
+use std::process::{Command, Output, Stdio};
+
+use common::{add_package, TemporaryFolder};
+
+/// Runs cpm in the `package` folder and checks that it succeeded
+fn run_cpm(folder: &TemporaryFolder, package: &str, args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_cpm"))
+        .args(args)
+        .current_dir(folder.0.join(package))
+        .env("CPM_HOME", folder.0.join("home"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+#[test]
+fn dependents_link_the_system_libraries_of_static_libraries() {
+    let folder = TemporaryFolder::new("static-math");
+    add_package(
+        &folder,
+        "app",
+        "exe",
+        "[dependencies]\nroots = { path = \"../roots\" }\n",
+    );
+    folder.write(
+        "app/src/app.c",
+        "#include <stdio.h>\n\
+         double root(double value);\n\
+         int main(void) { printf(\"%g\\n\", root(16.0)); return 0; }\n",
+    );
+    add_package(
+        &folder,
+        "roots",
+        "staticlib",
+        "enable_math_library = true\n",
+    );
+    folder.write(
+        "roots/src/roots.c",
+        "#include <math.h>\n\
+         double root(double value) { return sqrt(value); }\n",
+    );
+    let output = run_cpm(&folder, "app", &["run"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().last(), Some("4"), "{stdout}");
+}
+
+#[test]
+fn programs_load_the_dynamic_libraries_of_their_dependencies() {
+    let folder = TemporaryFolder::new("dynamic-versions");
+    folder.write(
+        "app/src/app.c",
+        "#include <stdio.h>\n\
+         int greeting(void);\n\
+         int main(void) { printf(\"%d\\n\", greeting()); return 0; }\n",
+    );
+    // two versions of the same library, in different folders
+    for (folder_name, version, greeting) in [("old", "1.0.0", 1), ("new", "1.1.0", 2)] {
+        folder.write(
+            &format!("{folder_name}/cpm.toml"),
+            &format!(
+                "[package]\nname = \"greeting\"\nversion = \"{version}\"\nkind = \"dynlib\"\n"
+            ),
+        );
+        folder.write(
+            &format!("{folder_name}/src/greeting.c"),
+            &format!("int greeting(void) {{ return {greeting}; }}\n"),
+        );
+    }
+    let run_with = |folder_name: &str| {
+        folder.write(
+            "app/cpm.toml",
+            &format!(
+                "[package]\nname = \"app\"\nversion = \"0.1.0\"\n\n\
+                 [dependencies]\ngreeting = {{ path = \"../{folder_name}\" }}\n"
+            ),
+        );
+        let output = run_cpm(&folder, "app", &["run"]);
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .last()
+            .map(str::to_string)
+    };
+    assert_eq!(run_with("old").as_deref(), Some("1"));
+    assert_eq!(run_with("new").as_deref(), Some("2"));
+    // the output of 1.0.0 is up to date, so switching back doesn't rebuild it
+    assert_eq!(run_with("old").as_deref(), Some("1"));
+}
+
+#[test]
+fn published_packages_are_unpacked_and_locked() {
+    let folder = TemporaryFolder::new("registry-roundtrip");
+    add_package(
+        &folder,
+        "answer",
+        "staticlib",
+        "[registry]\npath = \"../registry\"\n",
+    );
+    run_cpm(&folder, "answer", &["publish"]);
+    add_package(
+        &folder,
+        "app",
+        "exe",
+        "[dependencies]\nanswer = \"0.1\"\n\n[registry]\npath = \"../registry\"\n",
+    );
+    folder.write(
+        "app/src/app.c",
+        "#include <stdio.h>\n\
+         int answer(void);\n\
+         int main(void) { printf(\"%d\\n\", answer()); return 0; }\n",
+    );
+    let output = run_cpm(&folder, "app", &["run"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().last(), Some("42"), "{stdout}");
+    let lockfile = std::fs::read_to_string(folder.0.join("app/cpm.lock")).unwrap();
+    assert!(lockfile.contains("name = \"answer\""), "{lockfile}");
+    assert!(lockfile.contains("checksum = "), "{lockfile}");
+    assert!(folder
+        .0
+        .join("home/cache/registry/answer-0.1.0/src/answer.c")
+        .exists());
+}
+
+#[test]
+fn precise_versions_must_be_published() {
+    let folder = TemporaryFolder::new("registry-precise");
+    add_package(
+        &folder,
+        "answer",
+        "staticlib",
+        "[registry]\npath = \"../registry\"\n",
+    );
+    run_cpm(&folder, "answer", &["publish"]);
+    add_package(
+        &folder,
+        "app",
+        "exe",
+        "[dependencies]\nanswer = \"0.1\"\n\n[registry]\npath = \"../registry\"\n",
+    );
+    run_cpm(&folder, "app", &["build"]);
+    run_cpm(
+        &folder,
+        "app",
+        &["update", "-p", "answer", "--precise", "0.1.0"],
+    );
+    let output = Command::new(env!("CARGO_BIN_EXE_cpm"))
+        .args(["update", "-p", "answer", "--precise", "0.1.5"])
+        .current_dir(folder.0.join("app"))
+        .env("CPM_HOME", folder.0.join("home"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("version 0.1.5 of `answer` not found in the registry"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn metadata_stops_quietly_when_the_reader_closes_the_pipe() {
+    let folder = TemporaryFolder::new("metadata-pipe");
+    add_package(&folder, "app", "exe", "");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_cpm"))
+        .arg("metadata")
+        .current_dir(folder.0.join("app"))
+        .env("CPM_HOME", folder.0.join("home"))
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // like `cpm metadata | head -c 0`
+    drop(child.stdout.take());
+    let output = child.wait_with_output().unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
@@ -0,0 +1,172 @@
+//! Builds workspaces with stub toolchains that record their invocations
+
+mod common;
+
+use std::{os::unix::fs::PermissionsExt, path::Path, process::Command};
+
+use common::{add_package, TemporaryFolder};
+
+/// A compiler, archiver and linker that records its arguments in `$STUB_LOG`
+/// and writes the file it's asked to create as an empty shell script, so programs can be run
+const STUB_PROGRAM: &str = r#"#!/bin/sh
+echo "$(basename "$0") $*" >> "$STUB_LOG"
+previous=
+for argument in "$@"; do
+    if [ "$previous" = "-o" ]; then output="$argument"; fi
+    previous="$argument"
+done
+if [ "$1" = "rcs" ]; then output="$2"; fi
+if [ -n "$output" ]; then
+    printf '#!/bin/sh\n' > "$output"
+    chmod +x "$output"
+fi
+"#;
+
+const TOOLCHAIN_FILE: &str = r#"[toolchain.aarch64-linux-gnu]
+prefix = "stub-"
+sysroot = "sysroot"
+compiler-flags = ["-DSTUB_TOOLCHAIN"]
+linker-flags = ["-Wl,--stub"]
+"#;
+
+fn add_stub_program(folder: &Path, name: &str) {
+    let path = folder.join(name);
+    std::fs::write(&path, STUB_PROGRAM).unwrap();
+    std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+}
+
+/// Runs cpm in the `package` folder with the programs of `stub_folder` first in `PATH`,
+/// checks that it succeeded and returns the invocations of the stub programs
+fn run_cpm(folder: &TemporaryFolder, stub_folder: &Path, package: &str, args: &[&str]) -> String {
+    let log_path = folder.0.join("stub.log");
+    let path = std::env::join_paths(
+        std::iter::once(stub_folder.to_path_buf())
+            .chain(std::env::split_paths(&std::env::var_os("PATH").unwrap())),
+    )
+    .unwrap();
+    let output = Command::new(env!("CARGO_BIN_EXE_cpm"))
+        .args(args)
+        .current_dir(folder.0.join(package))
+        .env("PATH", path)
+        .env("STUB_LOG", &log_path)
+        .env("CPM_HOME", folder.0.join("home"))
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    std::fs::read_to_string(&log_path).unwrap()
+}
+
+#[test]
+fn cross_compilation_uses_the_toolchain_of_the_target() {
+    let folder = TemporaryFolder::new("cross-compile");
+    let stub_folder = folder.0.join("stubs");
+    std::fs::create_dir_all(&stub_folder).unwrap();
+    for program in ["stub-gcc", "stub-ar", "stub-size"] {
+        add_stub_program(&stub_folder, program);
+    }
+    add_package(
+        &folder,
+        "app",
+        "exe",
+        "[dependencies]\nlib = { path = \"../lib\" }\n\n\
+         [build-dependencies]\ntool = { path = \"../tool\" }\n",
+    );
+    add_package(&folder, "lib", "staticlib", "");
+    add_package(&folder, "tool", "exe", "");
+    folder.write("app/cpm-toolchain.toml", TOOLCHAIN_FILE);
+    let log = run_cpm(
+        &folder,
+        &stub_folder,
+        "app",
+        &["build", "--target", "aarch64-linux-gnu"],
+    );
+    let invocations: Vec<&str> = log.lines().collect();
+    let sysroot = format!(
+        "--sysroot={}",
+        folder.0.join("app").join("sysroot").display()
+    );
+    let compiles: Vec<&&str> = invocations
+        .iter()
+        .filter(|line| line.starts_with("stub-gcc") && line.contains(" -c "))
+        .collect();
+    assert_eq!(compiles.len(), 2, "{log}");
+    for source in ["lib/src/lib.c", "app/src/app.c"] {
+        let compile = compiles.iter().find(|line| line.contains(source)).unwrap();
+        assert!(compile.contains("-DSTUB_TOOLCHAIN"), "{compile}");
+        assert!(compile.contains(&sysroot), "{compile}");
+    }
+    assert!(
+        invocations
+            .iter()
+            .any(|line| line.starts_with("stub-ar rcs") && line.contains("liblib.a")),
+        "{log}"
+    );
+    let link = invocations
+        .iter()
+        .find(|line| line.starts_with("stub-gcc") && !line.contains(" -c "))
+        .unwrap();
+    assert!(link.contains("-Wl,--stub"), "{link}");
+    assert!(
+        link.contains("liblib.a") || link.contains("-llib"),
+        "{link}"
+    );
+    // packages without a size budget aren't measured
+    assert!(!log.contains("stub-size"), "{log}");
+    // the build-dependency runs on the host, so it's built by the host compiler
+    assert!(!log.contains("tool"), "{log}");
+    let target_folder = folder.0.join("app").join("target");
+    assert!(target_folder.join("aarch64-linux-gnu").is_dir());
+}
+
+#[test]
+fn only_fuzz_targets_link_libfuzzer() {
+    let folder = TemporaryFolder::new("fuzz-instrumentation");
+    let stub_folder = folder.0.join("stubs");
+    std::fs::create_dir_all(&stub_folder).unwrap();
+    for program in ["clang", "ar"] {
+        add_stub_program(&stub_folder, program);
+    }
+    add_package(
+        &folder,
+        "parser",
+        "staticlib",
+        "[dependencies]\nlexer = { path = \"../lexer\" }\n",
+    );
+    add_package(&folder, "lexer", "staticlib", "");
+    folder.write(
+        "parser/fuzz/parse.c",
+        "int LLVMFuzzerTestOneInput(const char *data, int size) { return 0; }\n",
+    );
+
+    let log = run_cpm(&folder, &stub_folder, "parser", &["fuzz", "run", "parse"]);
+    let compiles: Vec<&str> = log.lines().filter(|line| line.contains(" -c ")).collect();
+    for source in [
+        "lexer/src/lexer.c",
+        "parser/src/parser.c",
+        "parser/fuzz/parse.c",
+    ] {
+        let compile = compiles.iter().find(|line| line.contains(source)).unwrap();
+        assert!(
+            compile.contains(" -fsanitize=address,fuzzer-no-link "),
+            "{compile}"
+        );
+    }
+    // libFuzzer is only linked into the fuzz target, which is the only linked program
+    let links: Vec<&str> = log
+        .lines()
+        .filter(|line| {
+            line.starts_with("clang ") && !line.contains(" -c ") && line.contains(" -o ")
+        })
+        .collect();
+    assert_eq!(links.len(), 1, "{log}");
+    let libfuzzer_flags = log
+        .split_whitespace()
+        .filter(|argument| *argument == "-fsanitize=fuzzer")
+        .count();
+    assert_eq!(libfuzzer_flags, 1, "{log}");
+    assert!(links[0].contains(" -fsanitize=fuzzer"), "{}", links[0]);
+}